@@ -0,0 +1,221 @@
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+
+use crate::analyzer::encoding::ColumnMapper;
+use crate::analyzer::protocol::{CallHierarchyItem, Position, Range};
+use crate::analyzer::uri::uri_to_file_path;
+
+/// Stop walking once this many functions have been found.
+pub const MAX_CALL_GRAPH_NODES: usize = 500;
+
+/// Which way to walk the call hierarchy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CallDirection {
+    /// Who calls the function.
+    Incoming,
+    /// What the function calls.
+    Outgoing,
+}
+
+impl CallDirection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Incoming => "incoming",
+            Self::Outgoing => "outgoing",
+        }
+    }
+}
+
+impl std::str::FromStr for CallDirection {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "incoming" | "callers" => Ok(Self::Incoming),
+            "outgoing" | "callees" => Ok(Self::Outgoing),
+            other => Err(anyhow::anyhow!("Unknown call direction: {other}")),
+        }
+    }
+}
+
+struct CallNode {
+    item: CallHierarchyItem,
+    /// Neighbours in the walk direction, with the call sites linking them.
+    /// Call sites are always in the caller's file.
+    calls: Vec<(usize, Vec<Range>)>,
+}
+
+/// The functions reached while walking a call hierarchy. Each function is
+/// stored once however many paths lead to it, so cycles end the walk.
+pub struct CallGraph {
+    direction: CallDirection,
+    nodes: Vec<CallNode>,
+    roots: Vec<usize>,
+    ids: HashMap<(String, Position), usize>,
+    truncated: bool,
+}
+
+impl CallGraph {
+    pub fn new(direction: CallDirection) -> Self {
+        Self {
+            direction,
+            nodes: Vec::new(),
+            roots: Vec::new(),
+            ids: HashMap::new(),
+            truncated: false,
+        }
+    }
+
+    pub fn roots(&self) -> &[usize] {
+        &self.roots
+    }
+
+    pub fn item(&self, id: usize) -> &CallHierarchyItem {
+        &self.nodes[id].item
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.nodes.len() >= MAX_CALL_GRAPH_NODES
+    }
+
+    pub fn add_root(&mut self, item: CallHierarchyItem) {
+        let (id, _) = self.insert(item);
+        self.roots.push(id);
+    }
+
+    /// Record that `from` reaches `item` through `ranges`. Returns the id of
+    /// `item` if it is new and still needs walking.
+    pub fn add_call(
+        &mut self,
+        from: usize,
+        item: CallHierarchyItem,
+        ranges: Vec<Range>,
+    ) -> Option<usize> {
+        if self.is_full() && !self.ids.contains_key(&Self::key(&item)) {
+            self.truncated = true;
+            return None;
+        }
+        let (id, new) = self.insert(item);
+        self.nodes[from].calls.push((id, ranges));
+        new.then_some(id)
+    }
+
+    fn key(item: &CallHierarchyItem) -> (String, Position) {
+        (item.uri.clone(), item.selection_range.start)
+    }
+
+    fn insert(&mut self, item: CallHierarchyItem) -> (usize, bool) {
+        let key = Self::key(&item);
+        if let Some(&id) = self.ids.get(&key) {
+            return (id, false);
+        }
+        let id = self.nodes.len();
+        self.nodes.push(CallNode {
+            item,
+            calls: Vec::new(),
+        });
+        self.ids.insert(key, id);
+        (id, true)
+    }
+
+    /// Every file mentioned, for loading a column mapper.
+    pub fn uris(&self) -> Vec<&str> {
+        let mut uris: Vec<&str> = self.nodes.iter().map(|n| n.item.uri.as_str()).collect();
+        uris.sort_unstable();
+        uris.dedup();
+        uris
+    }
+
+    /// The caller's file for a call from `from` to `to`.
+    fn caller_uri(&self, from: usize, to: usize) -> &str {
+        match self.direction {
+            CallDirection::Incoming => &self.nodes[to].item.uri,
+            CallDirection::Outgoing => &self.nodes[from].item.uri,
+        }
+    }
+
+    fn node_json(&self, id: usize, mapper: &ColumnMapper) -> Value {
+        let item = &self.nodes[id].item;
+        json!({
+            "name": item.name,
+            "kind": item.kind.as_str(),
+            "detail": item.detail,
+            "file": uri_to_file_path(&item.uri),
+            "range": mapper.range_to_client(&item.uri, item.selection_range)
+        })
+    }
+
+    fn call_sites_json(&self, uri: &str, ranges: &[Range], mapper: &ColumnMapper) -> Value {
+        let file = uri_to_file_path(uri);
+        ranges
+            .iter()
+            .map(|range| json!({ "file": file, "range": mapper.range_to_client(uri, *range) }))
+            .collect()
+    }
+
+    /// Nested `calls` under each root. A function already shown elsewhere
+    /// appears again only as a leaf marked `repeated`.
+    pub fn tree_json(&self, mapper: &ColumnMapper) -> Value {
+        let mut shown = HashSet::new();
+        let roots: Vec<Value> = self
+            .roots
+            .iter()
+            .map(|&root| self.subtree_json(root, &mut shown, mapper))
+            .collect();
+        json!({
+            "direction": self.direction.as_str(),
+            "truncated": self.truncated,
+            "roots": roots
+        })
+    }
+
+    fn subtree_json(&self, id: usize, shown: &mut HashSet<usize>, mapper: &ColumnMapper) -> Value {
+        let mut node = self.node_json(id, mapper);
+        if !shown.insert(id) {
+            node["repeated"] = json!(true);
+            return node;
+        }
+        let calls: Vec<Value> = self.nodes[id]
+            .calls
+            .iter()
+            .map(|(to, ranges)| {
+                let mut child = self.subtree_json(*to, shown, mapper);
+                child["call_sites"] =
+                    self.call_sites_json(self.caller_uri(id, *to), ranges, mapper);
+                child
+            })
+            .collect();
+        node["calls"] = json!(calls);
+        node
+    }
+
+    /// Every call found, as `caller -> callee` edges between `name @
+    /// file:line` labels.
+    pub fn edges_json(&self, mapper: &ColumnMapper) -> Value {
+        let label = |id: usize| {
+            let item = &self.nodes[id].item;
+            let line = mapper.to_client(&item.uri, item.selection_range.start).line;
+            format!("{} @ {}:{line}", item.name, uri_to_file_path(&item.uri))
+        };
+        let mut edges = Vec::new();
+        for (from, node) in self.nodes.iter().enumerate() {
+            for (to, ranges) in &node.calls {
+                let (caller, callee) = match self.direction {
+                    CallDirection::Incoming => (*to, from),
+                    CallDirection::Outgoing => (from, *to),
+                };
+                edges.push(json!({
+                    "caller": label(caller),
+                    "callee": label(callee),
+                    "call_sites": self.call_sites_json(self.caller_uri(from, *to), ranges, mapper)
+                }));
+            }
+        }
+        json!({
+            "direction": self.direction.as_str(),
+            "truncated": self.truncated,
+            "roots": self.roots.iter().map(|&root| label(root)).collect::<Vec<_>>(),
+            "edges": edges
+        })
+    }
+}
@@ -1,10 +1,50 @@
 use anyhow::Result;
 use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
-use tokio::process::Child;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex, Weak};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::{Child, ChildStdin, ChildStdout};
+use tokio::sync::{Mutex, Notify, oneshot};
+use tokio::task::JoinHandle;
 
+use crate::analyzer::call_hierarchy::{CallDirection, CallGraph};
+use crate::analyzer::config::AnalyzerConfig;
+use crate::analyzer::diagnostics::DiagnosticStore;
+use crate::analyzer::documents::{DOCUMENT_IDLE_TIMEOUT, DocumentStore, TextSource};
+use crate::analyzer::documents::{offset_to_position, position_to_offset};
+use crate::analyzer::encoding::{ColumnMapper, PositionEncoding, line_text};
+use crate::analyzer::journal::EditJournal;
+use crate::analyzer::lsp::{MessageKind, read_message, write_message};
+use crate::analyzer::macros::{innermost_item, macro_call_offsets};
+use crate::analyzer::markdown::{parse_hover, strip_markdown};
+use crate::analyzer::progress::{IndexingStatus, ProgressTracker};
 use crate::analyzer::protocol::*;
+use crate::analyzer::supervisor::{
+    AnalyzerState, EXIT_POLL_INTERVAL, MAX_RESTART_ATTEMPTS, SHUTDOWN_GRACE_PERIOD, restart_backoff,
+};
+use crate::analyzer::timeouts::RequestTimeouts;
+use crate::analyzer::type_hierarchy::{
+    TypeGraph, bound_ident, find_word, header_at, impl_header, item_keyword, item_name,
+    parse_impl_header, supertrait_bounds,
+};
+use crate::analyzer::uri::{
+    check_in_roots, current_dir, normalize_uri, path_to_uri, resolve_path, uri_to_file_path,
+    uri_to_path,
+};
+
+/// JSON-RPC error code for requests we don't know how to answer.
+const METHOD_NOT_FOUND: i64 = -32601;
+const REQUEST_CANCELLED: i64 = -32800;
+const CONTENT_MODIFIED: i64 = -32801;
+
+/// How many times a request is re-sent after rust-analyzer answers
+/// `ContentModified` or `RequestCancelled`.
+const MAX_REQUEST_RETRIES: u32 = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
 
 fn get_rust_analyzer_path() -> String {
     std::env::var("RUST_ANALYZER_PATH").unwrap_or_else(|_| {
@@ -14,44 +54,232 @@ fn get_rust_analyzer_path() -> String {
     })
 }
 
-pub struct RustAnalyzerClient {
-    process: Option<Child>,
-    request_id: u64,
-    initialized: bool,
+/// State shared between the client and its background tasks.
+struct Shared {
+    process: Mutex<Option<Child>>,
+    stdin: Mutex<Option<ChildStdin>>,
+    pending: StdMutex<HashMap<u64, oneshot::Sender<Value>>>,
+    request_id: AtomicU64,
+    state: StdMutex<AnalyzerState>,
+    /// Bumped on every launch so a reader from an old process can tell it is
+    /// no longer current.
+    generation: AtomicU64,
+    /// Signalled when the current process is found dead.
+    lost: Arc<Notify>,
+    /// Serializes restarts, whether triggered by the supervisor or by hand.
+    restart_lock: Mutex<()>,
+    reader: StdMutex<Option<JoinHandle<()>>>,
+    diagnostics: DiagnosticStore,
+    documents: Mutex<DocumentStore>,
+    progress: ProgressTracker,
+    config: StdMutex<AnalyzerConfig>,
+    timeouts: StdMutex<RequestTimeouts>,
+    /// The workspace rust-analyzer is started for.
+    root: PathBuf,
+    /// Relative paths in tool calls are resolved against this.
+    base: PathBuf,
+    /// How rust-analyzer counts columns, as negotiated in `initialize`.
+    server_encoding: StdMutex<PositionEncoding>,
+    /// How agents count columns in tool calls and results.
+    column_encoding: StdMutex<PositionEncoding>,
+    /// The MCP client's roots, once shared. Edits may not leave them.
+    roots: StdMutex<Option<Vec<PathBuf>>>,
+    /// Where applied edits are recorded for undo.
+    journal: StdMutex<Arc<EditJournal>>,
 }
 
-impl Default for RustAnalyzerClient {
-    fn default() -> Self {
-        Self::new()
+impl Shared {
+    fn state(&self) -> AnalyzerState {
+        self.state.lock().unwrap().clone()
     }
-}
 
-impl RustAnalyzerClient {
-    pub fn new() -> Self {
-        Self {
-            process: None,
-            request_id: 0,
-            initialized: false,
+    fn set_state(&self, state: AnalyzerState) {
+        let mut current = self.state.lock().unwrap();
+        // Once shutdown has begun, nothing may bring the server back.
+        if matches!(
+            *current,
+            AnalyzerState::ShuttingDown | AnalyzerState::Stopped
+        ) && state != AnalyzerState::Stopped
+        {
+            return;
         }
+        *current = state;
     }
 
-    pub async fn start(&mut self) -> Result<()> {
+    /// Fail fast with a useful message unless requests can be served.
+    fn ensure_ready(&self) -> Result<()> {
+        match self.state() {
+            AnalyzerState::Running => Ok(()),
+            AnalyzerState::NotStarted => Err(anyhow::anyhow!("Client not initialized")),
+            AnalyzerState::Starting => Err(anyhow::anyhow!(
+                "rust-analyzer is still starting; try again shortly"
+            )),
+            AnalyzerState::Restarting { attempt } => Err(anyhow::anyhow!(
+                "rust-analyzer is restarting (attempt {attempt}); try again shortly"
+            )),
+            AnalyzerState::Failed(reason) => Err(anyhow::anyhow!(
+                "rust-analyzer could not be restarted ({reason}); use restart_analyzer to try again"
+            )),
+            AnalyzerState::ShuttingDown | AnalyzerState::Stopped => {
+                Err(anyhow::anyhow!("rust-analyzer has been shut down"))
+            }
+        }
+    }
+
+    /// Spawn rust-analyzer, start reading its output and run the initialize
+    /// handshake.
+    async fn launch(self: &Arc<Self>) -> Result<()> {
         let rust_analyzer_path = get_rust_analyzer_path();
-        let child = tokio::process::Command::new(&rust_analyzer_path)
+        let mut child = tokio::process::Command::new(&rust_analyzer_path)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
+            .kill_on_drop(true)
             .spawn()?;
 
-        self.process = Some(child);
-        self.initialize().await?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("rust-analyzer stdout is not piped"))?;
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(drain(stderr));
+        }
+        *self.stdin.lock().await = child.stdin.take();
+        *self.process.lock().await = Some(child);
+
+        let generation = self.generation.fetch_add(1, Ordering::SeqCst) + 1;
+        let reader = tokio::spawn(reader_loop(stdout, self.clone(), generation));
+        if let Some(previous) = self.reader.lock().unwrap().replace(reader) {
+            previous.abort();
+        }
+
+        self.initialize().await
+    }
+
+    /// Kill the current process, if any, and fail everything waiting on it.
+    async fn stop_process(&self) {
+        *self.stdin.lock().await = None;
+        if let Some(mut child) = self.process.lock().await.take() {
+            let _ = child.kill().await;
+        }
+        if let Some(reader) = self.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+        self.pending.lock().unwrap().clear();
+    }
+
+    /// Replace the current process with a fresh one, backing off between
+    /// failed attempts, and re-open every tracked document.
+    async fn restart(self: &Arc<Self>) -> Result<()> {
+        let _guard = self.restart_lock.lock().await;
+        if self.is_shut_down() {
+            return Err(anyhow::anyhow!("rust-analyzer has been shut down"));
+        }
+
+        self.set_state(AnalyzerState::Restarting { attempt: 1 });
+        self.stop_process().await;
+        self.diagnostics.clear();
+        self.progress.reset();
+
+        let mut last_error = anyhow::anyhow!("no restart attempted");
+        for attempt in 1..=MAX_RESTART_ATTEMPTS {
+            if self.is_shut_down() {
+                return Err(anyhow::anyhow!("rust-analyzer has been shut down"));
+            }
+            self.set_state(AnalyzerState::Restarting { attempt });
+            tokio::time::sleep(restart_backoff(attempt)).await;
+
+            let launched = self.launch().await;
+            if self.is_shut_down() {
+                self.stop_process().await;
+                return Err(anyhow::anyhow!("rust-analyzer has been shut down"));
+            }
+            match launched {
+                Ok(()) => match self.reopen_documents().await {
+                    Ok(()) => {
+                        self.set_state(AnalyzerState::Running);
+                        return Ok(());
+                    }
+                    Err(e) => last_error = e,
+                },
+                Err(e) => last_error = e,
+            }
+            eprintln!("rust-analyzer restart attempt {attempt} failed: {last_error}");
+            self.stop_process().await;
+        }
+
+        self.set_state(AnalyzerState::Failed(last_error.to_string()));
+        Err(last_error)
+    }
+
+    /// Called when a reader sees its stream end. Only the reader of the
+    /// current, healthy process gets to trigger a restart.
+    fn connection_lost(&self, generation: u64) {
+        if generation == self.generation.load(Ordering::SeqCst)
+            && self.state() == AnalyzerState::Running
+        {
+            self.lost.notify_one();
+        }
+    }
+
+    fn is_shut_down(&self) -> bool {
+        matches!(
+            self.state(),
+            AnalyzerState::ShuttingDown | AnalyzerState::Stopped
+        )
+    }
+
+    /// Ask rust-analyzer to shut down and exit, killing it if it has not
+    /// gone within the grace period. Requests still waiting are failed.
+    async fn shutdown(self: &Arc<Self>) {
+        let previous = std::mem::replace(
+            &mut *self.state.lock().unwrap(),
+            AnalyzerState::ShuttingDown,
+        );
+        if matches!(
+            previous,
+            AnalyzerState::ShuttingDown | AnalyzerState::Stopped
+        ) {
+            return;
+        }
+
+        if previous == AnalyzerState::Running {
+            let polite = async {
+                self.request("shutdown", Value::Null).await?;
+                self.notify("exit", Value::Null).await?;
+                if let Some(child) = self.process.lock().await.as_mut() {
+                    child.wait().await?;
+                }
+                anyhow::Ok(())
+            };
+            match tokio::time::timeout(SHUTDOWN_GRACE_PERIOD, polite).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("rust-analyzer did not shut down cleanly: {e}"),
+                Err(_) => eprintln!(
+                    "rust-analyzer did not exit within {}s; killing it",
+                    SHUTDOWN_GRACE_PERIOD.as_secs()
+                ),
+            }
+        }
+
+        self.stop_process().await;
+        self.set_state(AnalyzerState::Stopped);
+    }
+
+    async fn reopen_documents(&self) -> Result<()> {
+        let mut documents = self.documents.lock().await;
+        for sync in documents.reopen() {
+            if let Some((method, params)) = sync.notification() {
+                self.notify(method, params).await?;
+            }
+        }
         Ok(())
     }
 
-    async fn initialize(&mut self) -> Result<()> {
-        // Get current working directory
-        let current_dir = std::env::current_dir()?;
-        let root_uri = format!("file://{}", current_dir.display());
+    async fn initialize(self: &Arc<Self>) -> Result<()> {
+        let root_uri = path_to_uri(&self.root);
+
+        let settings = self.config.lock().unwrap().settings().clone();
 
         // Send initialize request
         let init_params = json!({
@@ -61,7 +289,13 @@ impl RustAnalyzerClient {
                 "version": "0.1.0"
             },
             "rootUri": root_uri,
+            "initializationOptions": settings,
             "capabilities": {
+                "general": {
+                    // Byte columns need no conversion for agents that count
+                    // bytes, and are what Rust strings index by.
+                    "positionEncodings": ["utf-8", "utf-16"]
+                },
                 "textDocument": {
                     "definition": {
                         "dynamicRegistration": false
@@ -69,6 +303,80 @@ impl RustAnalyzerClient {
                     "references": {
                         "dynamicRegistration": false
                     },
+                    "hover": {
+                        "dynamicRegistration": false,
+                        "contentFormat": ["markdown", "plaintext"]
+                    },
+                    "implementation": {
+                        "dynamicRegistration": false,
+                        "linkSupport": true
+                    },
+                    "typeDefinition": {
+                        "dynamicRegistration": false,
+                        "linkSupport": true
+                    },
+                    "declaration": {
+                        "dynamicRegistration": false,
+                        "linkSupport": true
+                    },
+                    "typeHierarchy": {
+                        "dynamicRegistration": false
+                    },
+                    "callHierarchy": {
+                        "dynamicRegistration": false
+                    },
+                    "codeAction": {
+                        "dynamicRegistration": false,
+                        "codeActionLiteralSupport": {
+                            "codeActionKind": {
+                                "valueSet": [
+                                    "",
+                                    "quickfix",
+                                    "refactor",
+                                    "refactor.extract",
+                                    "refactor.inline",
+                                    "refactor.rewrite",
+                                    "source",
+                                    "source.organizeImports"
+                                ]
+                            }
+                        },
+                        "isPreferredSupport": true,
+                        "dataSupport": true,
+                        // Listing stays cheap; edits are computed for the
+                        // action an agent picks.
+                        "resolveSupport": {
+                            "properties": ["edit"]
+                        }
+                    },
+                    "documentSymbol": {
+                        "dynamicRegistration": false,
+                        "hierarchicalDocumentSymbolSupport": true
+                    },
+                    "signatureHelp": {
+                        "dynamicRegistration": false,
+                        "signatureInformation": {
+                            "documentationFormat": ["markdown", "plaintext"],
+                            "parameterInformation": {
+                                "labelOffsetSupport": true
+                            },
+                            "activeParameterSupport": true
+                        }
+                    },
+                    "completion": {
+                        "dynamicRegistration": false,
+                        "completionItem": {
+                            // Agents insert text verbatim; `$0` placeholders
+                            // would end up in their code.
+                            "snippetSupport": false,
+                            "documentationFormat": ["markdown", "plaintext"],
+                            "labelDetailsSupport": true,
+                            "deprecatedSupport": true,
+                            "resolveSupport": {
+                                "properties": ["documentation", "detail", "additionalTextEdits"]
+                            }
+                        }
+                    },
                     "publishDiagnostics": {
                         "relatedInformation": true
                     }
@@ -76,225 +384,1517 @@ impl RustAnalyzerClient {
                 "workspace": {
                     "symbol": {
                         "dynamicRegistration": false
+                    },
+                    "configuration": true,
+                    "didChangeConfiguration": {
+                        "dynamicRegistration": false
                     }
+                },
+                "window": {
+                    "workDoneProgress": true
+                },
+                "experimental": {
+                    "serverStatusNotification": true
                 }
             }
         });
 
-        let _response = self
-            .send_request_internal("initialize", init_params)
-            .await?;
+        let response = self.request("initialize", init_params).await?;
+
+        // Servers that do not negotiate use UTF-16.
+        let encoding = response
+            .pointer("/capabilities/positionEncoding")
+            .and_then(|e| e.as_str())
+            .and_then(|e| e.parse().ok())
+            .unwrap_or_default();
+        *self.server_encoding.lock().unwrap() = encoding;
+        self.documents.lock().await.set_encoding(encoding);
 
         // Send initialized notification
-        self.send_notification("initialized", json!({})).await?;
+        self.notify("initialized", json!({})).await
+    }
 
-        self.initialized = true;
-        Ok(())
+    /// Send a request and wait for the reader task to hand back its result.
+    ///
+    /// Any number of these can be in flight at once; responses are matched to
+    /// callers by id. Requests rust-analyzer drops because the document moved
+    /// on underneath them are retried a few times before giving up.
+    async fn request(self: &Arc<Self>, method: &str, params: Value) -> Result<Value> {
+        let mut attempt = 0;
+        loop {
+            let response = self.request_once(method, params.clone()).await?;
+
+            let Some(error) = response.get("error") else {
+                return Ok(response.get("result").cloned().unwrap_or(Value::Null));
+            };
+            let code = error.get("code").and_then(|c| c.as_i64());
+            if matches!(code, Some(CONTENT_MODIFIED | REQUEST_CANCELLED))
+                && attempt < MAX_REQUEST_RETRIES
+            {
+                attempt += 1;
+                tokio::time::sleep(RETRY_DELAY * attempt).await;
+                continue;
+            }
+            return Err(anyhow::anyhow!("{method} failed: {error}"));
+        }
     }
 
-    async fn send_notification(&mut self, method: &str, params: Value) -> Result<()> {
-        let notification = json!({
+    /// Send a request once and return the raw response.
+    ///
+    /// Gives up after the method's timeout. Whether it times out or the
+    /// caller stops waiting, rust-analyzer is told to cancel the request.
+    async fn request_once(self: &Arc<Self>, method: &str, params: Value) -> Result<Value> {
+        let id = self.request_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let request = json!({
             "jsonrpc": "2.0",
+            "id": id,
             "method": method,
             "params": params
         });
 
-        self.send_message(&notification).await
+        let (tx, rx) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, tx);
+        let _in_flight = InFlight {
+            shared: self.clone(),
+            id,
+        };
+
+        self.send_message(&request).await?;
+
+        let timeout = self.timeouts.lock().unwrap().for_method(method);
+        let response = tokio::time::timeout(timeout, rx)
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "rust-analyzer did not answer {method} within {}s; the request was cancelled",
+                    timeout.as_secs_f64()
+                )
+            })?
+            .map_err(|_| match self.state() {
+                AnalyzerState::Restarting { .. } => {
+                    anyhow::anyhow!("rust-analyzer exited while handling {method} and is restarting; try again shortly")
+                }
+                AnalyzerState::ShuttingDown | AnalyzerState::Stopped => {
+                    anyhow::anyhow!("rust-analyzer was shut down before answering {method}")
+                }
+                _ => anyhow::anyhow!("rust-analyzer connection closed before answering {method}"),
+            })?;
+
+        Ok(response)
     }
 
-    async fn send_request_internal(&mut self, method: &str, params: Value) -> Result<Value> {
-        self.request_id += 1;
-        let request = json!({
+    async fn notify(&self, method: &str, params: Value) -> Result<()> {
+        let notification = json!({
             "jsonrpc": "2.0",
-            "id": self.request_id,
             "method": method,
             "params": params
         });
 
-        self.send_message(&request).await?;
-        self.read_response(self.request_id).await
+        self.send_message(&notification).await
+    }
+
+    async fn send_message(&self, message: &Value) -> Result<()> {
+        let mut stdin = self.stdin.lock().await;
+        let stdin = stdin
+            .as_mut()
+            .ok_or_else(|| anyhow::anyhow!("rust-analyzer is not running"))?;
+        write_message(stdin, message).await
+    }
+}
+
+/// Tells rust-analyzer about files our tools wrote or deleted. Cheap to
+/// clone, so edits can finish in a task of their own.
+#[derive(Clone)]
+pub struct FileNotifier {
+    shared: Arc<Shared>,
+}
+
+impl FileNotifier {
+    /// Tell rust-analyzer that `path` now holds `text` on disk.
+    ///
+    /// Every tool that edits files must call this after writing, so open
+    /// documents receive a `didChange` and closed ones a file-watcher event.
+    pub async fn document_written(&self, path: &Path, text: String, existed: bool) -> Result<()> {
+        let uri = path_to_uri(path);
+        {
+            // Hold the lock while sending so versions reach the server in order.
+            let mut documents = self.shared.documents.lock().await;
+            if documents.is_open(&uri) {
+                let sync = documents.sync(&uri, text, TextSource::Written);
+                if let Some((method, params)) = sync.notification() {
+                    self.shared.notify(method, params).await?;
+                }
+                return Ok(());
+            }
+        }
+
+        // FileChangeType: 1 = Created, 2 = Changed
+        let change_type = if existed { 2 } else { 1 };
+        self.shared
+            .notify(
+                "workspace/didChangeWatchedFiles",
+                json!({ "changes": [{ "uri": uri, "type": change_type }] }),
+            )
+            .await
+    }
+
+    /// Tell rust-analyzer that `path` was deleted, closing it if open.
+    pub async fn document_deleted(&self, path: &Path) -> Result<()> {
+        let uri = path_to_uri(path);
+        {
+            let mut documents = self.shared.documents.lock().await;
+            if documents.close(&uri) {
+                let params = json!({ "textDocument": { "uri": uri } });
+                self.shared.notify("textDocument/didClose", params).await?;
+            }
+        }
+        // FileChangeType: 3 = Deleted
+        self.shared
+            .notify(
+                "workspace/didChangeWatchedFiles",
+                json!({ "changes": [{ "uri": uri, "type": 3 }] }),
+            )
+            .await
+    }
+}
+
+/// How to pick one of the code actions at a range.
+#[derive(Debug, Clone, Copy)]
+pub enum ActionChoice<'a> {
+    /// The first of these titles that is offered, in order of preference.
+    Titles(&'a [&'a str]),
+    /// The action at this index, as [`RustAnalyzerClient::list_code_actions`]
+    /// numbers them.
+    Index(usize),
+}
+
+/// A request waiting for its response.
+///
+/// If it is dropped before the reader has delivered the response, the entry
+/// is removed and rust-analyzer is sent `$/cancelRequest` so it can stop
+/// working on it.
+struct InFlight {
+    shared: Arc<Shared>,
+    id: u64,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self
+            .shared
+            .pending
+            .lock()
+            .unwrap()
+            .remove(&self.id)
+            .is_none()
+        {
+            return;
+        }
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let shared = self.shared.clone();
+            let id = self.id;
+            runtime.spawn(async move {
+                let _ = shared.notify("$/cancelRequest", json!({ "id": id })).await;
+            });
+        }
+    }
+}
+
+pub struct RustAnalyzerClient {
+    shared: Arc<Shared>,
+    /// Supervisor and idle-document sweeper; they live as long as the client.
+    background: StdMutex<Vec<JoinHandle<()>>>,
+}
+
+impl Default for RustAnalyzerClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for RustAnalyzerClient {
+    fn drop(&mut self) {
+        for task in self.background.get_mut().unwrap().drain(..) {
+            task.abort();
+        }
+        // The reader holds the last other reference to the shared state;
+        // stopping it lets the child be killed on drop.
+        if let Some(reader) = self.shared.reader.lock().unwrap().take() {
+            reader.abort();
+        }
+    }
+}
+
+impl RustAnalyzerClient {
+    pub fn new() -> Self {
+        Self::with_config(AnalyzerConfig::default())
+    }
+
+    /// Create a client that hands `config` to rust-analyzer, for the
+    /// workspace in the current directory.
+    pub fn with_config(config: AnalyzerConfig) -> Self {
+        Self::with_root(config, current_dir())
     }
 
-    async fn send_message(&mut self, message: &Value) -> Result<()> {
-        let content = message.to_string();
-        let header = format!("Content-Length: {}\r\n\r\n", content.len());
+    /// Create a client for the workspace rooted at `root`.
+    pub fn with_root(config: AnalyzerConfig, root: PathBuf) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                process: Mutex::new(None),
+                stdin: Mutex::new(None),
+                pending: StdMutex::new(HashMap::new()),
+                request_id: AtomicU64::new(0),
+                state: StdMutex::new(AnalyzerState::NotStarted),
+                generation: AtomicU64::new(0),
+                lost: Arc::new(Notify::new()),
+                restart_lock: Mutex::new(()),
+                reader: StdMutex::new(None),
+                diagnostics: DiagnosticStore::default(),
+                documents: Mutex::new(DocumentStore::default()),
+                progress: ProgressTracker::default(),
+                config: StdMutex::new(config),
+                timeouts: StdMutex::new(RequestTimeouts::default()),
+                root,
+                base: current_dir(),
+                server_encoding: StdMutex::new(PositionEncoding::default()),
+                column_encoding: StdMutex::new(PositionEncoding::Utf8),
+                roots: StdMutex::new(None),
+                journal: StdMutex::new(Arc::new(EditJournal::default())),
+            }),
+            background: StdMutex::new(Vec::new()),
+        }
+    }
 
-        if let Some(child) = &mut self.process {
-            if let Some(stdin) = child.stdin.as_mut() {
-                stdin.write_all(header.as_bytes()).await?;
-                stdin.write_all(content.as_bytes()).await?;
-                stdin.flush().await?;
+    pub async fn start(&self) -> Result<()> {
+        if self.shared.is_shut_down() {
+            return Err(anyhow::anyhow!("rust-analyzer has been shut down"));
+        }
+        {
+            let mut background = self.background.lock().unwrap();
+            if background.is_empty() {
+                let shared = Arc::downgrade(&self.shared);
+                background.push(tokio::spawn(supervise(
+                    shared.clone(),
+                    self.shared.lost.clone(),
+                )));
+                background.push(tokio::spawn(close_idle_documents(shared)));
             }
         }
 
+        self.shared.set_state(AnalyzerState::Starting);
+        if let Err(e) = self.shared.launch().await {
+            self.shared.stop_process().await;
+            self.shared.set_state(AnalyzerState::Failed(e.to_string()));
+            return Err(e);
+        }
+        self.shared.set_state(AnalyzerState::Running);
         Ok(())
     }
 
-    async fn read_response(&mut self, expected_id: u64) -> Result<Value> {
-        if let Some(child) = &mut self.process {
-            if let Some(stdout) = child.stdout.as_mut() {
-                let mut reader = BufReader::new(stdout);
+    /// Throw away the current rust-analyzer process and start a new one.
+    pub async fn restart(&self) -> Result<String> {
+        self.shared.restart().await?;
+        let documents = self.shared.documents.lock().await.len();
+        Ok(format!(
+            "rust-analyzer restarted; {documents} open documents re-sent"
+        ))
+    }
+
+    /// Shut rust-analyzer down with the `shutdown`/`exit` handshake, killing
+    /// it if it does not exit in time. The client cannot be started again
+    /// afterwards.
+    pub async fn shutdown(&self) {
+        for task in self.background.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.shared.shutdown().await;
+    }
+
+    /// Where the rust-analyzer process is in its lifecycle.
+    pub fn state(&self) -> AnalyzerState {
+        self.shared.state()
+    }
 
-                loop {
-                    // Read headers
-                    let mut content_length: Option<usize> = None;
-                    loop {
-                        let mut line = String::new();
-                        reader.read_line(&mut line).await?;
+    /// Replace the per-method timeouts used for requests sent from now on.
+    pub fn set_request_timeouts(&self, timeouts: RequestTimeouts) {
+        *self.shared.timeouts.lock().unwrap() = timeouts;
+    }
 
-                        if line == "\r\n" {
-                            break;
-                        }
+    /// Choose how agents count columns: bytes, UTF-16 code units or chars.
+    pub fn set_column_encoding(&self, encoding: PositionEncoding) {
+        *self.shared.column_encoding.lock().unwrap() = encoding;
+    }
 
-                        if let Some(stripped) = line.strip_prefix("Content-Length:") {
-                            let length_str = stripped.trim();
-                            content_length = Some(length_str.parse()?);
-                        }
-                    }
+    /// How agents count columns.
+    pub fn column_encoding(&self) -> PositionEncoding {
+        *self.shared.column_encoding.lock().unwrap()
+    }
 
-                    if let Some(length) = content_length {
-                        let mut content = vec![0u8; length];
-                        reader.read_exact(&mut content).await?;
+    pub fn set_roots(&self, roots: Option<Vec<PathBuf>>) {
+        *self.shared.roots.lock().unwrap() = roots;
+    }
 
-                        let response: Value = serde_json::from_slice(&content)?;
+    /// Record edits in `journal`, shared with other analyzers, instead of a
+    /// journal of this client's own.
+    pub fn set_journal(&self, journal: Arc<EditJournal>) {
+        *self.shared.journal.lock().unwrap() = journal;
+    }
 
-                        if let Some(id) = response.get("id") {
-                            if id.as_u64() == Some(expected_id) {
-                                return Ok(response);
-                            }
-                        }
-                    }
+    pub fn journal(&self) -> Arc<EditJournal> {
+        self.shared.journal.lock().unwrap().clone()
+    }
+
+    /// Refuse to edit `path` if it is outside the MCP client's roots.
+    pub fn check_editable(&self, path: &Path) -> Result<()> {
+        match self.shared.roots.lock().unwrap().as_ref() {
+            Some(roots) => check_in_roots(path, roots),
+            None => Ok(()),
+        }
+    }
+
+    /// The workspace this client's rust-analyzer was started for.
+    pub fn workspace_root(&self) -> &Path {
+        &self.shared.root
+    }
+
+    /// The absolute, canonical form of a path from a tool call.
+    pub fn resolve_path(&self, file_path: &str) -> PathBuf {
+        resolve_path(file_path, &self.shared.base)
+    }
+
+    fn document_uri(&self, file_path: &str) -> String {
+        path_to_uri(&self.resolve_path(file_path))
+    }
+
+    /// A mapper between agent and server columns that knows the text of
+    /// every document in `uris`. Texts are only loaded when the encodings
+    /// differ.
+    async fn column_mapper(&self, uris: &[&str]) -> ColumnMapper {
+        let mut mapper = ColumnMapper::new(
+            *self.shared.column_encoding.lock().unwrap(),
+            self.server_encoding(),
+        );
+        if mapper.is_identity() {
+            return mapper;
+        }
+        for &uri in uris {
+            if mapper.has_text(uri) {
+                continue;
+            }
+            if let Some(text) = self.document_text(uri).await {
+                mapper.insert_text(uri, text);
+            }
+        }
+        mapper
+    }
+
+    /// The text rust-analyzer sees for `uri`: the open document if there is
+    /// one, else the file on disk. `uri` may be spelled the way
+    /// rust-analyzer spells it in locations.
+    pub async fn document_text(&self, uri: &str) -> Option<String> {
+        let open = self
+            .shared
+            .documents
+            .lock()
+            .await
+            .text(&normalize_uri(uri))
+            .map(str::to_string);
+        match open {
+            Some(text) => Some(text),
+            None => tokio::fs::read_to_string(uri_to_path(uri)?).await.ok(),
+        }
+    }
+
+    /// What rust-analyzer last reported about indexing.
+    pub fn indexing_status(&self) -> IndexingStatus {
+        self.shared.progress.snapshot()
+    }
+
+    /// Block until rust-analyzer has finished indexing or `timeout` elapses.
+    /// Returns whether indexing finished.
+    pub async fn wait_for_indexing(&self, timeout: Duration) -> bool {
+        self.shared.progress.wait_until_ready(timeout).await
+    }
+
+    fn ensure_ready(&self) -> Result<()> {
+        self.shared.ensure_ready()
+    }
+
+    /// Make sure rust-analyzer has `file_path` open with its current disk
+    /// contents and return its URI. Called before every position-based
+    /// request.
+    pub async fn open_document(&self, file_path: &str) -> Result<String> {
+        let path = self.resolve_path(file_path);
+        let text = tokio::fs::read_to_string(&path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {e}", path.display()))?;
+        let uri = path_to_uri(&path);
+        self.sync_document(&uri, text, TextSource::Disk).await?;
+        Ok(uri)
+    }
+
+    /// Like [`Self::open_document`], but analyze `content` instead of the
+    /// file on disk when given. Callers must [`Self::release_buffer`] once
+    /// their request is answered, so later tools see the file again.
+    async fn open_document_with(&self, file_path: &str, content: Option<String>) -> Result<String> {
+        match content {
+            Some(text) => {
+                let uri = self.document_uri(file_path);
+                self.sync_document(&uri, text, TextSource::Buffer).await?;
+                Ok(uri)
+            }
+            None => self.open_document(file_path).await,
+        }
+    }
+
+    /// Put the file on disk back in place of a buffer given to
+    /// [`Self::open_document_with`], or close the document if there is no
+    /// such file.
+    async fn release_buffer(&self, uri: &str) -> Result<()> {
+        let disk = match uri_to_path(uri) {
+            Some(path) => tokio::fs::read_to_string(path).await.ok(),
+            None => None,
+        };
+        match disk {
+            Some(text) => self.sync_document(uri, text, TextSource::Disk).await,
+            None => {
+                let mut documents = self.shared.documents.lock().await;
+                if !documents.close(uri) {
+                    return Ok(());
                 }
+                let params = json!({ "textDocument": { "uri": uri } });
+                self.shared.notify("textDocument/didClose", params).await
             }
         }
+    }
+
+    /// A handle for telling rust-analyzer about files our tools change on
+    /// disk, which can outlive the tool call.
+    pub fn file_notifier(&self) -> FileNotifier {
+        FileNotifier {
+            shared: self.shared.clone(),
+        }
+    }
+
+    /// The version rust-analyzer has of `uri`, if it is open.
+    pub async fn document_version(&self, uri: &str) -> Option<i32> {
+        self.shared.documents.lock().await.version(uri)
+    }
+
+    /// Whether rust-analyzer sees caller-supplied contents for `uri` rather
+    /// than the file on disk.
+    pub async fn has_unsaved_buffer(&self, uri: &str) -> bool {
+        self.shared.documents.lock().await.from_buffer(uri)
+    }
+
+    async fn sync_document(&self, uri: &str, text: String, source: TextSource) -> Result<()> {
+        // Hold the lock while sending so versions reach the server in order.
+        let mut documents = self.shared.documents.lock().await;
+        if let Some((method, params)) = documents.sync(uri, text, source).notification() {
+            self.shared.notify(method, params).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_request_internal(&self, method: &str, params: Value) -> Result<Value> {
+        self.shared.request(method, params).await
+    }
 
-        Err(anyhow::anyhow!("Failed to read response"))
+    /// Send a typed request and decode its result.
+    async fn send_request<R: Request>(&self, params: R::Params) -> Result<R::Result> {
+        let params = serde_json::to_value(params)?;
+        let result = self.send_request_internal(R::METHOD, params).await?;
+        decode_result::<R>(result)
+    }
+
+    /// An agent's position in an open document, as rust-analyzer counts it.
+    async fn server_position(&self, uri: &str, line: u32, character: u32) -> Position {
+        self.column_mapper(&[uri])
+            .await
+            .to_server(uri, Position { line, character })
+    }
+
+    /// A workspace edit as a flat list of per-file edits and file
+    /// operations, in the order they apply.
+    async fn workspace_edit_json(&self, edit: WorkspaceEdit) -> Vec<Value> {
+        let changes = edit.into_document_changes();
+        let uris: Vec<&str> = changes
+            .iter()
+            .filter_map(|change| match change {
+                DocumentChange::Edit(edit) => Some(edit.text_document.uri.as_str()),
+                DocumentChange::Operation(_) => None,
+            })
+            .collect();
+        let mapper = self.column_mapper(&uris).await;
+
+        changes
+            .into_iter()
+            .map(|change| match change {
+                DocumentChange::Edit(edit) => json!({
+                    "kind": "edit",
+                    "file": uri_to_file_path(&edit.text_document.uri),
+                    "version": edit.text_document.version,
+                    "edits": text_edits_json(&edit.text_document.uri, &edit.edits, &mapper)
+                }),
+                DocumentChange::Operation(ResourceOp::Create { uri, .. }) => json!({
+                    "kind": "create",
+                    "file": uri_to_file_path(&uri)
+                }),
+                DocumentChange::Operation(ResourceOp::Rename {
+                    old_uri, new_uri, ..
+                }) => json!({
+                    "kind": "rename",
+                    "from": uri_to_file_path(&old_uri),
+                    "to": uri_to_file_path(&new_uri)
+                }),
+                DocumentChange::Operation(ResourceOp::Delete { uri, .. }) => json!({
+                    "kind": "delete",
+                    "file": uri_to_file_path(&uri)
+                }),
+            })
+            .collect()
     }
 
     // Tool implementation methods
     pub async fn find_definition(
-        &mut self,
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let params = create_text_document_position_params(&uri, position);
+        let locations = self
+            .send_request::<GotoDefinition>(params)
+            .await?
+            .map(GotoDefinitionResponse::into_locations)
+            .unwrap_or_default();
+
+        let uris: Vec<&str> = locations.iter().map(|l| l.uri.as_str()).collect();
+        let mapper = self.column_mapper(&uris).await;
+        Ok(serde_json::to_string_pretty(&locations_json(
+            &locations, &mapper,
+        ))?)
+    }
+
+    /// The impls of the trait or type at a position, or the implementations
+    /// of a trait method, each with `context_lines` of source around it.
+    pub async fn goto_implementation(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        context_lines: u32,
+    ) -> Result<String> {
+        self.goto_with_snippets::<GotoImplementation>(file_path, line, character, context_lines)
+            .await
+    }
+
+    /// Where the type of the expression at a position is defined.
+    pub async fn goto_type_definition(
+        &self,
         file_path: &str,
         line: u32,
         character: u32,
+        context_lines: u32,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+        self.goto_with_snippets::<GotoTypeDefinition>(file_path, line, character, context_lines)
+            .await
+    }
+
+    /// Where the symbol at a position is declared, e.g. the `mod` item for a
+    /// module or the trait method for an impl method.
+    pub async fn goto_declaration(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        context_lines: u32,
+    ) -> Result<String> {
+        self.goto_with_snippets::<GotoDeclaration>(file_path, line, character, context_lines)
+            .await
+    }
+
+    /// The recursive expansion of the macro call, attribute macro or derive
+    /// at a position, or with `whole_item` of every one in the item around
+    /// it.
+    pub async fn expand_macro(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        whole_item: bool,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let mapper = self.column_mapper(&[&uri]).await;
+        let position = mapper.to_server(&uri, Position { line, character });
+        let file = uri_to_file_path(&uri);
+
+        if !whole_item {
+            let params = create_text_document_position_params(&uri, position);
+            let Some(expanded) = self.send_request::<ExpandMacro>(params).await? else {
+                return Ok(format!("No macro to expand at {file}:{line}:{character}"));
+            };
+            return Ok(serde_json::to_string_pretty(&json!({
+                "file": file,
+                "name": expanded.name,
+                "expansion": expanded.expansion
+            }))?);
         }
 
-        let params = create_text_document_position_params(file_path, line, character);
-        let response = self
-            .send_request_internal("textDocument/definition", params)
-            .await?;
+        let symbols = self
+            .send_request::<DocumentSymbolRequest>(create_document_symbol_params(&uri))
+            .await?
+            .map(DocumentSymbolResponse::into_nested)
+            .unwrap_or_default();
+        let item = innermost_item(&symbols, position)
+            .ok_or_else(|| anyhow::anyhow!("No item at {file}:{line}:{character}"))?;
+        let text = self
+            .document_text(&uri)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Cannot read {file}"))?;
+        let encoding = self.server_encoding();
+        let start = position_to_offset(&text, item.range.start, encoding);
+        let end = position_to_offset(&text, item.range.end, encoding);
+
+        let mut expansions = Vec::new();
+        for offset in macro_call_offsets(&text, start..end) {
+            let position = offset_to_position(&text, offset, encoding);
+            let params = create_text_document_position_params(&uri, position);
+            if let Some(expanded) = self.send_request::<ExpandMacro>(params).await? {
+                expansions.push(json!({
+                    "name": expanded.name,
+                    "position": mapper.to_client(&uri, position),
+                    "expansion": expanded.expansion
+                }));
+            }
+        }
+        Ok(serde_json::to_string_pretty(&json!({
+            "file": file,
+            "item": item.name,
+            "kind": item.kind.as_str(),
+            "expansions": expansions
+        }))?)
+    }
 
-        Ok(format!("Definition response: {response}"))
+    async fn goto_with_snippets<R>(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        context_lines: u32,
+    ) -> Result<String>
+    where
+        R: Request<Params = TextDocumentPositionParams, Result = Option<GotoDefinitionResponse>>,
+    {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let locations = self
+            .send_request::<R>(create_text_document_position_params(&uri, position))
+            .await?
+            .map(GotoDefinitionResponse::into_locations)
+            .unwrap_or_default();
+
+        let uris: Vec<&str> = locations.iter().map(|l| l.uri.as_str()).collect();
+        let mapper = self.column_mapper(&uris).await;
+        let mut results = Vec::new();
+        for location in &locations {
+            let mut result = location_json(location, &mapper);
+            if let Some(text) = self.document_text(&location.uri).await {
+                let (start_line, snippet) = snippet(&text, location.range, context_lines);
+                result["snippet_start_line"] = json!(start_line);
+                result["snippet"] = json!(snippet);
+            }
+            results.push(result);
+        }
+        Ok(serde_json::to_string_pretty(&results)?)
     }
 
     pub async fn find_references(
-        &mut self,
+        &self,
         file_path: &str,
         line: u32,
         character: u32,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
+        self.ensure_ready()?;
 
-        let params = create_references_params(file_path, line, character);
-        let response = self
-            .send_request_internal("textDocument/references", params)
-            .await?;
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let params = create_references_params(&uri, position);
+        let locations = self
+            .send_request::<References>(params)
+            .await?
+            .unwrap_or_default();
+
+        let uris: Vec<&str> = locations.iter().map(|l| l.uri.as_str()).collect();
+        let mapper = self.column_mapper(&uris).await;
+        Ok(serde_json::to_string_pretty(&locations_json(
+            &locations, &mapper,
+        ))?)
+    }
+
+    /// Describe the item at a position, or the expression covering a range
+    /// when `end` is given: its signature or type, docs and memory layout.
+    pub async fn hover(
+        &self,
+        file_path: &str,
+        start: (u32, u32),
+        end: Option<(u32, u32)>,
+        plain_text: bool,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let mapper = self.column_mapper(&[&uri]).await;
+        let position = |(line, character)| mapper.to_server(&uri, Position { line, character });
+        let target = match end {
+            Some(end) => PositionOrRange::Range(Range {
+                start: position(start),
+                end: position(end),
+            }),
+            None => PositionOrRange::Position(position(start)),
+        };
+        let Some(hover) = self
+            .send_request::<HoverRequest>(create_hover_params(&uri, target))
+            .await?
+        else {
+            return Ok(format!(
+                "No hover information at {}:{}:{}",
+                uri_to_file_path(&uri),
+                start.0,
+                start.1
+            ));
+        };
+
+        let parts = parse_hover(&hover.contents.to_markdown());
+        let documentation = match parts.documentation {
+            Some(docs) if plain_text => Some(strip_markdown(&docs)),
+            docs => docs,
+        };
+        Ok(serde_json::to_string_pretty(&json!({
+            "path": parts.path,
+            "signature": parts.signature,
+            "memory_layout": parts.memory_layout,
+            "documentation": documentation,
+            "range": hover.range.map(|range| mapper.range_to_client(&uri, range))
+        }))?)
+    }
+
+    /// Completion candidates at a position, best first, each resolved for
+    /// docs and auto-import edits. `content` analyzes unsaved text in place
+    /// of the file on disk. Without a `prefix`, candidates are filtered by
+    /// the identifier being typed at the cursor.
+    pub async fn complete(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        content: Option<String>,
+        prefix: Option<&str>,
+        max_results: usize,
+    ) -> Result<String> {
+        self.ensure_ready()?;
 
-        Ok(format!("References response: {response}"))
+        let buffered = content.is_some();
+        let uri = self.open_document_with(file_path, content).await?;
+        let result = self
+            .complete_at(&uri, line, character, prefix, max_results)
+            .await;
+        if buffered {
+            self.release_buffer(&uri).await?;
+        }
+        result
     }
 
-    pub async fn get_diagnostics(&mut self, file_path: &str) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+    async fn complete_at(
+        &self,
+        uri: &str,
+        line: u32,
+        character: u32,
+        prefix: Option<&str>,
+        max_results: usize,
+    ) -> Result<String> {
+        let mapper = self.column_mapper(&[uri]).await;
+        let cursor = Position { line, character };
+        let params = create_text_document_position_params(uri, mapper.to_server(uri, cursor));
+        let (mut items, is_incomplete) = self
+            .send_request::<Completion>(params)
+            .await?
+            .map(CompletionResponse::into_parts)
+            .unwrap_or_default();
+
+        // The identifier left of the cursor: the default filter, and what a
+        // candidate without its own text edit replaces.
+        let text = self
+            .shared
+            .documents
+            .lock()
+            .await
+            .text(uri)
+            .map(str::to_string)
+            .unwrap_or_default();
+        let client_encoding = *self.shared.column_encoding.lock().unwrap();
+        let line_text = line_text(&text, line);
+        let cursor_byte = client_encoding.byte_offset(line_text, character);
+        let typed = line_text[..cursor_byte]
+            .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+            .len();
+        let word = Range {
+            start: mapper.to_server(
+                uri,
+                Position {
+                    line,
+                    character: client_encoding.len(&line_text[..typed]) as u32,
+                },
+            ),
+            end: mapper.to_server(uri, cursor),
+        };
+        let prefix = prefix
+            .unwrap_or(&line_text[typed..cursor_byte])
+            .to_lowercase();
+
+        items.retain(|item| {
+            item.filter_text
+                .as_deref()
+                .unwrap_or(&item.label)
+                .to_lowercase()
+                .starts_with(&prefix)
+        });
+        items.sort_by(|a, b| {
+            let key = |item: &CompletionItem| {
+                (
+                    !item.preselect.unwrap_or(false),
+                    item.sort_text.clone().unwrap_or_else(|| item.label.clone()),
+                )
+            };
+            key(a).cmp(&key(b))
+        });
+        let total = items.len();
+        items.truncate(max_results);
+
+        let mut candidates = Vec::new();
+        for item in items {
+            let item = match self
+                .send_request::<ResolveCompletionItem>(item.clone())
+                .await
+            {
+                Ok(resolved) => resolved,
+                Err(_) => item,
+            };
+            let edit = match item.text_edit {
+                Some(edit) => edit.into_text_edit(),
+                None => TextEdit {
+                    range: word,
+                    new_text: item
+                        .insert_text
+                        .clone()
+                        .unwrap_or_else(|| item.label.clone()),
+                },
+            };
+            let additional_edits = item.additional_text_edits.unwrap_or_default();
+            candidates.push(json!({
+                "label": item.label,
+                "kind": item.kind.map(CompletionItemKind::as_str),
+                "detail": item.detail.or_else(|| {
+                    item.label_details.and_then(|details| details.description)
+                }),
+                "documentation": item.documentation.as_ref().map(Documentation::value),
+                "deprecated": item.deprecated.unwrap_or(false),
+                "text_edit": text_edits_json(uri, std::slice::from_ref(&edit), &mapper)[0],
+                "additional_edits": text_edits_json(uri, &additional_edits, &mapper)
+            }));
         }
 
-        // For diagnostics, we typically receive them via notifications
-        // This is a simplified implementation
-        Ok(format!("Diagnostics for file: {file_path}"))
+        Ok(serde_json::to_string_pretty(&json!({
+            "is_incomplete": is_incomplete || total > candidates.len(),
+            "total": total,
+            "items": candidates
+        }))?)
     }
 
-    pub async fn workspace_symbols(&mut self, query: &str) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+    /// The signatures of the call surrounding a position, with the
+    /// parameter the cursor is on marked active. `content` analyzes unsaved
+    /// text in place of the file on disk.
+    pub async fn signature_help(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        content: Option<String>,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let buffered = content.is_some();
+        let uri = self.open_document_with(file_path, content).await?;
+        let result = self.signature_help_at(&uri, line, character).await;
+        if buffered {
+            self.release_buffer(&uri).await?;
         }
+        result
+    }
 
-        let params = create_workspace_symbol_params(query);
-        let response = self
-            .send_request_internal("workspace/symbol", params)
-            .await?;
+    async fn signature_help_at(&self, uri: &str, line: u32, character: u32) -> Result<String> {
+        let position = self.server_position(uri, line, character).await;
+        let Some(help) = self
+            .send_request::<SignatureHelpRequest>(create_signature_help_params(uri, position))
+            .await?
+            .filter(|help| !help.signatures.is_empty())
+        else {
+            return Ok(format!(
+                "No call surrounds {}:{line}:{character}",
+                uri_to_file_path(uri)
+            ));
+        };
 
-        Ok(format!("Workspace symbols response: {response}"))
+        let active_signature = help.active_signature.unwrap_or(0) as usize;
+        let signatures: Vec<Value> = help
+            .signatures
+            .iter()
+            .enumerate()
+            .map(|(index, signature)| {
+                let active_parameter = signature.active_parameter.or(help.active_parameter);
+                let parameters: Vec<Value> = signature
+                    .parameters
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(i, parameter)| {
+                        json!({
+                            "label": parameter.label.text(&signature.label),
+                            "documentation": parameter
+                                .documentation
+                                .as_ref()
+                                .map(Documentation::value),
+                            "active": active_parameter == Some(i as u32)
+                        })
+                    })
+                    .collect();
+                json!({
+                    "label": signature.label,
+                    "active": index == active_signature,
+                    "active_parameter": active_parameter,
+                    "parameters": parameters,
+                    "documentation": signature.documentation.as_ref().map(Documentation::value)
+                })
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&signatures)?)
     }
 
-    pub async fn rename_symbol(
-        &mut self,
+    /// An outline of `file_path`: modules, impls, items and fields, nested.
+    /// With `tree`, a compact indented listing instead of JSON. Symbols
+    /// nested deeper than `max_depth` are left out; top-level symbols are at
+    /// depth 0.
+    pub async fn document_symbols(
+        &self,
+        file_path: &str,
+        tree: bool,
+        max_depth: Option<usize>,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let symbols = self
+            .send_request::<DocumentSymbolRequest>(create_document_symbol_params(&uri))
+            .await?
+            .map(DocumentSymbolResponse::into_nested)
+            .unwrap_or_default();
+        let mapper = self.column_mapper(&[&uri]).await;
+        let max_depth = max_depth.unwrap_or(usize::MAX);
+
+        if tree {
+            let mut out = String::new();
+            symbol_tree(&symbols, 0, max_depth, &mut out);
+            if out.is_empty() {
+                return Ok(format!("No symbols in {}", uri_to_file_path(&uri)));
+            }
+            return Ok(out.trim_end().to_string());
+        }
+        Ok(serde_json::to_string_pretty(&symbols_json(
+            &uri, &symbols, 0, max_depth, &mapper,
+        ))?)
+    }
+
+    /// Walk the callers or callees of the function at a position, `depth`
+    /// levels deep, visiting each function once. Returns a nested tree, or a
+    /// flat list of caller/callee edges with `edges`.
+    pub async fn call_hierarchy(
+        &self,
         file_path: &str,
         line: u32,
         character: u32,
-        new_name: &str,
+        direction: CallDirection,
+        depth: usize,
+        edges: bool,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let items = self
+            .send_request::<PrepareCallHierarchy>(create_text_document_position_params(
+                &uri, position,
+            ))
+            .await?
+            .unwrap_or_default();
+        if items.is_empty() {
+            return Ok(format!(
+                "No function at {}:{line}:{character}",
+                uri_to_file_path(&uri)
+            ));
         }
 
-        let params = create_rename_params(file_path, line, character, new_name);
-        let response = self
-            .send_request_internal("textDocument/rename", params)
-            .await?;
+        let mut graph = CallGraph::new(direction);
+        for item in items {
+            graph.add_root(item);
+        }
+        let mut frontier = graph.roots().to_vec();
+        for _ in 0..depth {
+            let mut next = Vec::new();
+            for id in frontier {
+                let params = CallHierarchyCallsParams {
+                    item: graph.item(id).clone(),
+                };
+                let calls: Vec<_> = match direction {
+                    CallDirection::Incoming => self
+                        .send_request::<IncomingCalls>(params)
+                        .await?
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|call| (call.from, call.from_ranges))
+                        .collect(),
+                    CallDirection::Outgoing => self
+                        .send_request::<OutgoingCalls>(params)
+                        .await?
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|call| (call.to, call.from_ranges))
+                        .collect(),
+                };
+                for (item, ranges) in calls {
+                    next.extend(graph.add_call(id, item, ranges));
+                }
+            }
+            if next.is_empty() {
+                break;
+            }
+            frontier = next;
+        }
 
-        Ok(format!("Rename response: {response}"))
+        let mapper = self.column_mapper(&graph.uris()).await;
+        let result = if edges {
+            graph.edges_json(&mapper)
+        } else {
+            graph.tree_json(&mapper)
+        };
+        Ok(serde_json::to_string_pretty(&result)?)
     }
 
-    pub async fn format_code(&mut self, file_path: &str) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+    /// Report the diagnostics rust-analyzer has published for `file_path`, or
+    /// for the whole workspace when no file is given.
+    ///
+    /// For a single file this waits, up to the `textDocument/publishDiagnostics`
+    /// timeout, for the first publish covering its current version, and
+    /// reports it as `not_yet_analyzed` if none arrives.
+    pub async fn get_diagnostics(
+        &self,
+        file_path: Option<&str>,
+        min_severity: Option<DiagnosticSeverity>,
+        include_fixes: bool,
+    ) -> Result<String> {
+        self.ensure_ready()?;
+
+        let files = match file_path {
+            Some(file_path) => {
+                let uri = self.open_document(file_path).await?;
+                // A file we just opened has nothing cached until rust-analyzer
+                // publishes for it, which would look like a clean file.
+                let version = self.document_version(&uri).await;
+                let timeout = self
+                    .shared
+                    .timeouts
+                    .lock()
+                    .unwrap()
+                    .for_method("textDocument/publishDiagnostics");
+                let published = self
+                    .shared
+                    .diagnostics
+                    .wait_for(&uri, version, timeout)
+                    .await;
+                let diagnostics =
+                    published.then(|| self.shared.diagnostics.for_uri(&uri, min_severity));
+                vec![(uri, diagnostics)]
+            }
+            None => self
+                .shared
+                .diagnostics
+                .all(min_severity)
+                .into_iter()
+                .map(|(uri, diagnostics)| (uri, Some(diagnostics)))
+                .collect(),
+        };
+
+        let mut uris: Vec<&str> = files.iter().map(|(uri, _)| uri.as_str()).collect();
+        for (_, diagnostics) in &files {
+            for diagnostic in diagnostics.iter().flatten() {
+                for info in diagnostic.related_information.iter().flatten() {
+                    uris.push(&info.location.uri);
+                }
+            }
         }
+        let mapper = self.column_mapper(&uris).await;
 
-        let params = create_formatting_params(file_path);
-        let response = self
-            .send_request_internal("textDocument/formatting", params)
-            .await?;
+        let mut report = Vec::new();
+        for (uri, diagnostics) in &files {
+            let Some(diagnostics) = diagnostics else {
+                report.push(json!({
+                    "file": uri_to_file_path(uri),
+                    "status": "not_yet_analyzed",
+                    "message": "rust-analyzer has not published diagnostics for this file yet; try again shortly",
+                    "diagnostics": null
+                }));
+                continue;
+            };
+            let mut entries = Vec::new();
+            for diagnostic in diagnostics {
+                let fixes = if include_fixes {
+                    self.diagnostic_fixes(uri, diagnostic).await?
+                } else {
+                    Vec::new()
+                };
+                entries.push(json!({
+                    "severity": diagnostic.severity.map(DiagnosticSeverity::as_str),
+                    "code": diagnostic.code,
+                    "source": diagnostic.source,
+                    "message": diagnostic.message,
+                    "range": mapper.range_to_client(uri, diagnostic.range),
+                    "related_information": diagnostic
+                        .related_information
+                        .iter()
+                        .flatten()
+                        .map(|info| json!({
+                            "message": info.message,
+                            "location": location_json(&info.location, &mapper)
+                        }))
+                        .collect::<Vec<_>>(),
+                    "fixes": fixes
+                }));
+            }
+            report.push(json!({
+                "file": uri_to_file_path(uri),
+                "status": "analyzed",
+                "diagnostics": entries
+            }));
+        }
 
-        Ok(format!("Formatting response: {response}"))
+        Ok(serde_json::to_string_pretty(&report)?)
     }
 
-    pub async fn analyze_manifest(&mut self, manifest_path: &str) -> Result<String> {
-        // This would analyze Cargo.toml file
-        Ok(format!("Manifest analysis for: {manifest_path}"))
+    /// Quick fixes rust-analyzer offers for a single diagnostic.
+    async fn diagnostic_fixes(&self, uri: &str, diagnostic: &Diagnostic) -> Result<Vec<Value>> {
+        let params = create_code_action_params(
+            uri,
+            &diagnostic.range,
+            std::slice::from_ref(diagnostic),
+            Some(&["quickfix"]),
+        );
+        let actions = self
+            .send_request::<CodeActionRequest>(params)
+            .await?
+            .unwrap_or_default();
+
+        let mut fixes = Vec::new();
+        for action in actions {
+            let CodeActionOrCommand::CodeAction(action) = action else {
+                continue;
+            };
+            let edit = match action.edit {
+                Some(edit) => Some(self.workspace_edit_json(edit).await),
+                None => None,
+            };
+            fixes.push(json!({
+                "title": action.title,
+                "kind": action.kind,
+                "edit": edit
+            }));
+        }
+        Ok(fixes)
     }
 
-    pub async fn run_cargo_check(&mut self, workspace_path: &str) -> Result<String> {
-        // This would run cargo check and parse results
-        Ok(format!("Cargo check results for: {workspace_path}"))
+    /// The code actions at a range, with the diagnostics overlapping it so
+    /// quick fixes are offered too.
+    async fn code_actions(
+        &self,
+        uri: &str,
+        start: (u32, u32),
+        end: Option<(u32, u32)>,
+        kinds: &[&str],
+    ) -> Result<Vec<CodeAction>> {
+        let mapper = self.column_mapper(&[uri]).await;
+        let position = |(line, character)| mapper.to_server(uri, Position { line, character });
+        let range = Range {
+            start: position(start),
+            end: position(end.unwrap_or(start)),
+        };
+        let diagnostics: Vec<Diagnostic> = self
+            .shared
+            .diagnostics
+            .for_uri(uri, None)
+            .into_iter()
+            .filter(|d| d.range.start <= range.end && range.start <= d.range.end)
+            .collect();
+        let only = (!kinds.is_empty()).then_some(kinds);
+        let params = create_code_action_params(uri, &range, &diagnostics, only);
+        let actions = self
+            .send_request::<CodeActionRequest>(params)
+            .await?
+            .unwrap_or_default();
+        // rust-analyzer only sends code action literals; a bare command has
+        // nothing we could apply.
+        Ok(actions
+            .into_iter()
+            .filter_map(|action| match action {
+                CodeActionOrCommand::CodeAction(action) => Some(action),
+                CodeActionOrCommand::Command(_) => None,
+            })
+            .collect())
+    }
+
+    /// Ask rust-analyzer for the edit of an action listed without one.
+    async fn resolve_code_action(&self, action: CodeAction) -> Result<CodeAction> {
+        if action.edit.is_some() || action.data.is_none() {
+            return Ok(action);
+        }
+        self.send_request::<ResolveCodeAction>(action).await
     }
 
-    pub async fn extract_function(
-        &mut self,
+    /// Code actions available at a position, or for a range when `end` is
+    /// given. `kinds` limits them to those kinds, e.g. `refactor`. With
+    /// `include_edits` each action is resolved and its edit shown.
+    pub async fn list_code_actions(
+        &self,
         file_path: &str,
-        start_line: u32,
-        start_character: u32,
-        end_line: u32,
-        end_character: u32,
-        function_name: &str,
+        start: (u32, u32),
+        end: Option<(u32, u32)>,
+        kinds: &[&str],
+        include_edits: bool,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let actions = self.code_actions(&uri, start, end, kinds).await?;
+
+        let mut listed = Vec::with_capacity(actions.len());
+        for (index, action) in actions.into_iter().enumerate() {
+            let mut entry = json!({
+                "index": index,
+                "title": action.title,
+                "kind": action.kind,
+                "is_preferred": action.is_preferred.unwrap_or(false)
+            });
+            if include_edits {
+                let action = self.resolve_code_action(action).await?;
+                entry["edit"] = match action.edit {
+                    Some(edit) => json!(self.workspace_edit_json(edit).await),
+                    None => Value::Null,
+                };
+                if let Some(command) = action.command {
+                    entry["command"] = json!(command.command);
+                }
+            }
+            listed.push(entry);
         }
+        Ok(serde_json::to_string_pretty(&listed)?)
+    }
 
-        // This would use rust-analyzer's extract function code action
-        // For now, return a placeholder implementation
-        Ok(format!(
-            "Extract function '{function_name}' from {file_path}:{start_line}:{start_character} to {end_line}:{end_character}"
-        ))
+    /// The code action picked by `choice` among those at a position or
+    /// range, resolved so its edit is filled in.
+    pub async fn code_action_edit(
+        &self,
+        file_path: &str,
+        start: (u32, u32),
+        end: Option<(u32, u32)>,
+        kinds: &[&str],
+        choice: ActionChoice<'_>,
+    ) -> Result<(CodeAction, WorkspaceEdit)> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let mut actions = self.code_actions(&uri, start, end, kinds).await?;
+        if actions.is_empty() {
+            let of_kind = match kinds {
+                [] => String::new(),
+                kinds => format!(" of kind {}", kinds.join(", ")),
+            };
+            return Err(anyhow::anyhow!(
+                "No code actions{of_kind} at {file_path}:{}:{}",
+                start.0,
+                start.1
+            ));
+        }
+        let available = || {
+            actions
+                .iter()
+                .map(|action| format!("\"{}\"", action.title))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let chosen = match choice {
+            ActionChoice::Titles(titles) => titles
+                .iter()
+                .find_map(|title| actions.iter().position(|action| action.title == *title))
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "No code action titled \"{}\"; available: {}",
+                        titles.join("\" or \""),
+                        available()
+                    )
+                })?,
+            ActionChoice::Index(index) if index < actions.len() => index,
+            ActionChoice::Index(index) => {
+                return Err(anyhow::anyhow!(
+                    "No code action with index {index}; available: {}",
+                    available()
+                ));
+            }
+        };
+
+        let mut action = self
+            .resolve_code_action(actions.swap_remove(chosen))
+            .await?;
+        match action.edit.take() {
+            Some(edit) => Ok((action, edit)),
+            None => Err(match action.command {
+                Some(command) => anyhow::anyhow!(
+                    "\"{}\" only runs the editor command {}, which cannot be applied here",
+                    action.title,
+                    command.command
+                ),
+                None => anyhow::anyhow!("\"{}\" has no edit to apply", action.title),
+            }),
+        }
+    }
+
+    pub async fn workspace_symbols(&self, query: &str) -> Result<String> {
+        self.ensure_ready()?;
+
+        let params = create_workspace_symbol_params(query);
+        let symbols = self
+            .send_request::<WorkspaceSymbolRequest>(params)
+            .await?
+            .unwrap_or_default();
+
+        let uris: Vec<&str> = symbols.iter().map(|s| s.location.uri.as_str()).collect();
+        let mapper = self.column_mapper(&uris).await;
+        let symbols: Vec<Value> = symbols
+            .iter()
+            .map(|symbol| {
+                json!({
+                    "name": symbol.name,
+                    "kind": symbol.kind.as_str(),
+                    "container": symbol.container_name,
+                    "file": uri_to_file_path(&symbol.location.uri),
+                    "range": mapper.range_to_client(&symbol.location.uri, symbol.location.range)
+                })
+            })
+            .collect();
+        Ok(serde_json::to_string_pretty(&symbols)?)
+    }
+
+    /// The edit renaming the symbol at a position to `new_name` everywhere
+    /// it is used.
+    pub async fn rename_edit(
+        &self,
+        file_path: &str,
+        line: u32,
+        character: u32,
+        new_name: &str,
+    ) -> Result<WorkspaceEdit> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let params = create_rename_params(&uri, position, new_name);
+        self.send_request::<Rename>(params)
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Nothing to rename at {file_path}:{line}:{character}"))
+    }
+
+    /// The edits rust-analyzer's formatter (rustfmt) would make to a file,
+    /// with the text they apply to.
+    pub async fn formatting_edits(
+        &self,
+        file_path: &str,
+        options: FormattingOptions,
+    ) -> Result<(String, Vec<TextEdit>)> {
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let text = self
+            .document_text(&uri)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Cannot read {file_path}"))?;
+        let params = create_formatting_params(&uri, options);
+        let edits = self
+            .send_request::<Formatting>(params)
+            .await?
+            .unwrap_or_default();
+        Ok((text, edits))
+    }
+
+    /// Works in every state, so agents can use it to find out why other tools
+    /// are failing.
+    pub async fn server_status(&self, wait: Option<Duration>) -> Result<String> {
+        Ok(serde_json::to_string_pretty(
+            &self.status_report(wait).await,
+        )?)
+    }
+
+    /// What `server_status` reports, as JSON.
+    pub async fn status_report(&self, wait: Option<Duration>) -> Value {
+        let state = self.state();
+        let timed_out = match wait {
+            Some(timeout) if state == AnalyzerState::Running => {
+                !self.wait_for_indexing(timeout).await
+            }
+            _ => false,
+        };
+
+        let status = self.indexing_status();
+        json!({
+            "workspace": self.shared.root.display().to_string(),
+            "state": state.to_string(),
+            "indexing": !status.is_ready(),
+            "percentage": status.percentage(),
+            "health": status.health,
+            "quiescent": status.quiescent,
+            "message": status.message,
+            "progress": status.progress,
+            "position_encoding": self.shared.server_encoding.lock().unwrap().as_str(),
+            "column_encoding": self.shared.column_encoding.lock().unwrap().as_str(),
+            "wait_timed_out": timed_out
+        })
+    }
+
+    pub async fn analyze_manifest(&self, manifest_path: &str) -> Result<String> {
+        // This would analyze Cargo.toml file
+        Ok(format!("Manifest analysis for: {manifest_path}"))
+    }
+
+    pub async fn run_cargo_check(&self, workspace_path: &str) -> Result<String> {
+        // This would run cargo check and parse results
+        Ok(format!("Cargo check results for: {workspace_path}"))
     }
 
     pub async fn generate_struct(
-        &mut self,
+        &self,
         struct_name: &str,
         fields: &[Value],
         derives: &[&str],
@@ -308,7 +1908,7 @@ impl RustAnalyzerClient {
     }
 
     pub async fn generate_enum(
-        &mut self,
+        &self,
         enum_name: &str,
         variants: &[Value],
         derives: &[&str],
@@ -322,7 +1922,7 @@ impl RustAnalyzerClient {
     }
 
     pub async fn generate_trait_impl(
-        &mut self,
+        &self,
         trait_name: &str,
         struct_name: &str,
         file_path: &str,
@@ -334,7 +1934,7 @@ impl RustAnalyzerClient {
     }
 
     pub async fn generate_tests(
-        &mut self,
+        &self,
         target_function: &str,
         file_path: &str,
         test_cases: &[Value],
@@ -346,77 +1946,207 @@ impl RustAnalyzerClient {
         ))
     }
 
-    pub async fn inline_function(
-        &mut self,
-        file_path: &str,
-        line: u32,
-        character: u32,
-    ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
-        Ok(format!(
-            "Inlined function at {file_path}:{line}:{character}"
-        ))
-    }
-
     pub async fn change_signature(
-        &mut self,
+        &self,
         file_path: &str,
         line: u32,
         character: u32,
         new_signature: &str,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
+        self.ensure_ready()?;
         Ok(format!(
             "Changed signature to '{new_signature}' at {file_path}:{line}:{character}"
         ))
     }
 
-    pub async fn organize_imports(&mut self, file_path: &str) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
-        Ok(format!("Organized imports in {file_path}"))
-    }
-
-    pub async fn apply_clippy_suggestions(&mut self, file_path: &str) -> Result<String> {
+    pub async fn apply_clippy_suggestions(&self, file_path: &str) -> Result<String> {
         // This would apply clippy suggestions to the file
         Ok(format!("Applied clippy suggestions to {file_path}"))
     }
 
-    pub async fn validate_lifetimes(&mut self, file_path: &str) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
+    pub async fn validate_lifetimes(&self, file_path: &str) -> Result<String> {
+        self.ensure_ready()?;
         Ok(format!("Validated lifetimes in {file_path}"))
     }
 
     pub async fn get_type_hierarchy(
-        &mut self,
+        &self,
         file_path: &str,
         line: u32,
         character: u32,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+        self.ensure_ready()?;
+
+        let uri = self.open_document(file_path).await?;
+        let position = self.server_position(&uri, line, character).await;
+        let at = create_text_document_position_params(&uri, position);
+
+        // Hover says whether this is a trait or a type.
+        let signature = self
+            .send_request::<HoverRequest>(create_hover_params(
+                &uri,
+                PositionOrRange::Position(position),
+            ))
+            .await?
+            .and_then(|hover| parse_hover(&hover.contents.to_markdown()).signature)
+            .unwrap_or_default();
+        let Some(kind) = item_keyword(&signature) else {
+            return Ok(format!(
+                "No type or trait at {}:{line}:{character}",
+                uri_to_file_path(&uri)
+            ));
+        };
+        let name = item_name(&signature, kind).unwrap_or_else(|| signature.clone());
+        let declaration = self
+            .send_request::<GotoDefinition>(at.clone())
+            .await?
+            .map(GotoDefinitionResponse::into_links)
+            .unwrap_or_default()
+            .into_iter()
+            .next();
+
+        let mut graph = TypeGraph::new(name, kind, declaration.clone().map(Location::from));
+
+        // For a type, its impl blocks; for a trait, the impls of it.
+        let impls = self
+            .send_request::<GotoImplementation>(at.clone())
+            .await?
+            .map(GotoDefinitionResponse::into_links)
+            .unwrap_or_default();
+        for link in impls {
+            let Some(text) = self.document_text(&link.target_uri).await else {
+                continue;
+            };
+            let site = Location {
+                uri: link.target_uri.clone(),
+                range: link.target_selection_range,
+            };
+            let header = impl_header(&text, link.target_range, link.target_selection_range)
+                .and_then(|header| parse_impl_header(&header));
+            match header {
+                Some(header) if kind == "trait" => {
+                    let implementor = graph.node(&header.self_type, "type", None);
+                    graph.implements(implementor, 0, site, &header);
+                }
+                Some(header) => match &header.trait_name {
+                    Some(trait_name) => {
+                        let implemented = graph.node(trait_name, "trait", None);
+                        graph.implements(0, implemented, site, &header);
+                    }
+                    None => graph.inherent_impl(site),
+                },
+                // No `impl` keyword nearby: a derive, pointing at the
+                // derive's name inside the attribute.
+                None => {
+                    let derived =
+                        range_text(&text, link.target_selection_range, self.server_encoding());
+                    if kind == "trait" {
+                        let implementor = graph.node(&derived, "type", None);
+                        graph.derives(implementor, 0, site);
+                    } else {
+                        let implemented = graph.node(&derived, "trait", None);
+                        graph.derives(0, implemented, site);
+                    }
+                }
+            }
         }
-        // This would use rust-analyzer's type hierarchy capability
-        Ok(format!(
-            "Type hierarchy for symbol at {file_path}:{line}:{character}"
-        ))
+
+        if kind == "trait" {
+            self.add_supertraits(&mut graph, at, declaration).await?;
+        }
+
+        let mapper = self.column_mapper(&graph.uris()).await;
+        Ok(serde_json::to_string_pretty(&graph.to_json(&mapper))?)
     }
 
-    pub async fn suggest_dependencies(
-        &mut self,
-        query: &str,
-        workspace_path: &str,
-    ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
+    /// Add the supertraits of the trait at `at` to `graph`, from
+    /// rust-analyzer's type hierarchy if it has one, else from the bounds in
+    /// the trait's declaration, each looked up with go to definition.
+    async fn add_supertraits(
+        &self,
+        graph: &mut TypeGraph,
+        at: TextDocumentPositionParams,
+        declaration: Option<LocationLink>,
+    ) -> Result<()> {
+        let prepared = self
+            .send_request::<PrepareTypeHierarchy>(at)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|items| items.into_iter().next());
+        if let Some(item) = prepared
+            && let Ok(Some(supertypes)) = self
+                .send_request::<Supertypes>(TypeHierarchyParams { item })
+                .await
+        {
+            for supertype in supertypes {
+                let location = Location {
+                    uri: supertype.uri,
+                    range: supertype.selection_range,
+                };
+                let id = graph.node(&supertype.name, "trait", Some(location));
+                graph.supertrait(0, id, None);
+            }
+            return Ok(());
         }
+
+        let Some(declaration) = declaration else {
+            return Ok(());
+        };
+        let uri = declaration.target_uri;
+        let Some(text) = self.document_text(&uri).await else {
+            return Ok(());
+        };
+        let encoding = self.server_encoding();
+        let name = declaration.target_selection_range.start;
+        let line_start: usize = text
+            .split_inclusive('\n')
+            .take(name.line as usize)
+            .map(str::len)
+            .sum();
+        let header_start =
+            line_start + encoding.byte_offset(line_text(&text, name.line), name.character);
+        let header = header_at(&text, header_start);
+
+        for bound in supertrait_bounds(&format!("trait {header}")) {
+            // Find where the bound is named in the source to look it up.
+            let ident = bound_ident(&bound);
+            let end = text[header_start..]
+                .find(['{', ';'])
+                .map_or(text.len(), |i| header_start + i);
+            let named_at = find_word(&text[header_start..end], ident).map(|i| header_start + i);
+            let mut location = None;
+            let mut site = None;
+            if let Some(offset) = named_at {
+                let position = offset_to_position(&text, offset, encoding);
+                site = Some(Location {
+                    uri: uri.clone(),
+                    range: Range {
+                        start: position,
+                        end: offset_to_position(&text, offset + ident.len(), encoding),
+                    },
+                });
+                location = self
+                    .send_request::<GotoDefinition>(create_text_document_position_params(
+                        &uri, position,
+                    ))
+                    .await?
+                    .map(GotoDefinitionResponse::into_locations)
+                    .and_then(|locations| locations.into_iter().next());
+            }
+            let id = graph.node(&bound, "trait", location);
+            graph.supertrait(0, id, site);
+        }
+        Ok(())
+    }
+
+    /// How rust-analyzer counts columns in the positions it sends and takes.
+    pub fn server_encoding(&self) -> PositionEncoding {
+        *self.shared.server_encoding.lock().unwrap()
+    }
+
+    pub async fn suggest_dependencies(&self, query: &str, workspace_path: &str) -> Result<String> {
+        self.ensure_ready()?;
         // This would analyze code and suggest crates based on usage patterns
         Ok(format!(
             "Dependency suggestions for '{query}' in workspace {workspace_path}"
@@ -424,14 +2154,12 @@ impl RustAnalyzerClient {
     }
 
     pub async fn create_module(
-        &mut self,
+        &self,
         module_name: &str,
         module_path: &str,
         is_public: bool,
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
+        self.ensure_ready()?;
         let visibility = if is_public { "pub " } else { "" };
         Ok(format!(
             "Created {visibility}module '{module_name}' at {module_path}"
@@ -439,17 +2167,350 @@ impl RustAnalyzerClient {
     }
 
     pub async fn move_items(
-        &mut self,
+        &self,
         source_file: &str,
         target_file: &str,
         item_names: &[&str],
     ) -> Result<String> {
-        if !self.initialized {
-            return Err(anyhow::anyhow!("Client not initialized"));
-        }
+        self.ensure_ready()?;
         Ok(format!(
             "Moved {} items from {source_file} to {target_file}: {item_names:?}",
             item_names.len()
         ))
     }
 }
+
+/// Owns rust-analyzer's stdout and routes every message it sends.
+///
+/// Responses are delivered to the waiting request, server requests are
+/// answered, and notifications update diagnostics and progress. When the stream
+/// ends all pending requests are dropped so their callers see an error instead
+/// of hanging.
+async fn reader_loop(stdout: ChildStdout, shared: Arc<Shared>, generation: u64) {
+    let mut reader = BufReader::new(stdout);
+
+    loop {
+        let message = match read_message(&mut reader).await {
+            Ok(Some(message)) => message,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Failed to read from rust-analyzer: {e}");
+                break;
+            }
+        };
+
+        match message.kind() {
+            MessageKind::Response { id } => {
+                let sender = shared.pending.lock().unwrap().remove(&id);
+                if let Some(sender) = sender {
+                    let _ = sender.send(message.content);
+                }
+            }
+            MessageKind::Request { id, method } => {
+                let response = match handle_server_request(&shared, method, message.params()) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+                };
+                // Answer from a task of its own. rust-analyzer may be blocked
+                // writing to us while one of our requests is blocked writing
+                // to it, and the reader waiting for stdin would deadlock.
+                let shared = shared.clone();
+                let method = method.to_string();
+                tokio::spawn(async move {
+                    if let Err(e) = shared.send_message(&response).await {
+                        eprintln!("Failed to answer rust-analyzer request {method}: {e}");
+                    }
+                });
+            }
+            MessageKind::Notification { method } => {
+                handle_notification(&shared, method, message.params());
+            }
+            MessageKind::Invalid => {}
+        }
+    }
+
+    shared.pending.lock().unwrap().clear();
+    shared.connection_lost(generation);
+}
+
+/// Restart rust-analyzer whenever its process dies.
+///
+/// Death is noticed either by the reader hitting EOF or by polling the child,
+/// which catches the case where something else still holds stdout open.
+async fn supervise(shared: Weak<Shared>, lost: Arc<Notify>) {
+    let mut poll = tokio::time::interval(EXIT_POLL_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = lost.notified() => {}
+            _ = poll.tick() => {
+                let Some(shared) = shared.upgrade() else { return };
+                let exited = match shared.process.lock().await.as_mut() {
+                    Some(child) => !matches!(child.try_wait(), Ok(None)),
+                    None => false,
+                };
+                if !(exited && shared.state() == AnalyzerState::Running) {
+                    continue;
+                }
+            }
+        }
+
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        eprintln!("rust-analyzer exited unexpectedly; restarting");
+        if let Err(e) = shared.restart().await {
+            eprintln!("Giving up on restarting rust-analyzer: {e}");
+        }
+    }
+}
+
+/// Update client-side state from a notification.
+fn handle_notification(shared: &Shared, method: &str, params: Value) {
+    match method {
+        "textDocument/publishDiagnostics" => match serde_json::from_value(params) {
+            Ok(params) => shared.diagnostics.update(params),
+            Err(e) => eprintln!("Malformed publishDiagnostics: {e}"),
+        },
+        "$/progress" => shared.progress.handle_progress(&params),
+        "experimental/serverStatus" => shared.progress.handle_server_status(&params),
+        _ => {}
+    }
+}
+
+/// Periodically close documents nobody has touched for a while so rust-analyzer
+/// doesn't keep every file we ever looked at in memory.
+async fn close_idle_documents(shared: Weak<Shared>) {
+    let mut interval = tokio::time::interval(DOCUMENT_IDLE_TIMEOUT / 5);
+    loop {
+        interval.tick().await;
+        let Some(shared) = shared.upgrade() else {
+            return;
+        };
+        let mut documents = shared.documents.lock().await;
+        for uri in documents.take_idle(DOCUMENT_IDLE_TIMEOUT) {
+            let params = json!({ "textDocument": { "uri": uri } });
+            if shared
+                .notify("textDocument/didClose", params)
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
+/// Answer a request rust-analyzer sent to us.
+///
+/// rust-analyzer blocks on some of these (notably `workspace/configuration`),
+/// so everything gets an answer, even if it is an error.
+fn handle_server_request(shared: &Shared, method: &str, params: Value) -> Result<Value, Value> {
+    match method {
+        "workspace/configuration" => {
+            let config = shared.config.lock().unwrap();
+            let items = params
+                .get("items")
+                .and_then(|items| items.as_array())
+                .cloned()
+                .unwrap_or_default();
+            Ok(Value::Array(
+                items
+                    .iter()
+                    .map(|item| config.section(item.get("section").and_then(|s| s.as_str())))
+                    .collect(),
+            ))
+        }
+        // We don't act on dynamic registrations, but refusing them makes
+        // rust-analyzer log errors and retry.
+        "client/registerCapability" | "client/unregisterCapability" => Ok(Value::Null),
+        // Progress itself arrives through `$/progress`; creating a token needs
+        // no bookkeeping on our side.
+        "window/workDoneProgress/create" => Ok(Value::Null),
+        // Nobody is there to pick an action.
+        "window/showMessageRequest" => Ok(Value::Null),
+        // Editor refresh hints; we have nothing cached to refresh.
+        "workspace/semanticTokens/refresh"
+        | "workspace/inlayHint/refresh"
+        | "workspace/codeLens/refresh"
+        | "workspace/diagnostic/refresh" => Ok(Value::Null),
+        "workspace/applyEdit" => Ok(json!({
+            "applied": false,
+            "failureReason": "rust-mcp-server does not apply server-initiated edits"
+        })),
+        _ => Err(json!({
+            "code": METHOD_NOT_FOUND,
+            "message": format!("Unsupported request: {method}")
+        })),
+    }
+}
+
+/// Keep a child pipe from filling up by discarding whatever it writes.
+async fn drain<R: AsyncRead + Unpin>(stream: R) {
+    let mut lines = BufReader::new(stream).lines();
+    while let Ok(Some(_)) = lines.next_line().await {}
+}
+
+fn location_json(location: &Location, mapper: &ColumnMapper) -> Value {
+    json!({
+        "file": uri_to_file_path(&location.uri),
+        "range": mapper.range_to_client(&location.uri, location.range)
+    })
+}
+
+fn locations_json(locations: &[Location], mapper: &ColumnMapper) -> Vec<Value> {
+    locations
+        .iter()
+        .map(|location| location_json(location, mapper))
+        .collect()
+}
+
+fn symbols_json(
+    uri: &str,
+    symbols: &[DocumentSymbol],
+    depth: usize,
+    max_depth: usize,
+    mapper: &ColumnMapper,
+) -> Vec<Value> {
+    symbols
+        .iter()
+        .map(|symbol| {
+            let children = match &symbol.children {
+                Some(children) if depth < max_depth => {
+                    symbols_json(uri, children, depth + 1, max_depth, mapper)
+                }
+                _ => Vec::new(),
+            };
+            json!({
+                "name": symbol.name,
+                "kind": symbol.kind.as_str(),
+                "detail": symbol.detail,
+                "range": mapper.range_to_client(uri, symbol.range),
+                "selection_range": mapper.range_to_client(uri, symbol.selection_range),
+                "children": children
+            })
+        })
+        .collect()
+}
+
+/// One line per symbol, `kind name detail  lines`, indented by nesting.
+/// Lines are zero-based like everywhere else.
+fn symbol_tree(symbols: &[DocumentSymbol], depth: usize, max_depth: usize, out: &mut String) {
+    if depth > max_depth {
+        return;
+    }
+    for symbol in symbols {
+        out.push_str(&"  ".repeat(depth));
+        out.push_str(symbol.kind.as_str());
+        out.push(' ');
+        out.push_str(&symbol.name);
+        if let Some(detail) = symbol.detail.as_deref().filter(|d| !d.is_empty()) {
+            out.push(' ');
+            out.push_str(detail);
+        }
+        let (start, end) = (symbol.range.start.line, symbol.range.end.line);
+        if start == end {
+            out.push_str(&format!("  {start}\n"));
+        } else {
+            out.push_str(&format!("  {start}-{end}\n"));
+        }
+        if let Some(children) = &symbol.children {
+            symbol_tree(children, depth + 1, max_depth, out);
+        }
+    }
+}
+
+/// Snippets longer than this many lines are cut short.
+const MAX_SNIPPET_LINES: u32 = 40;
+
+/// The lines of `range` in `text` with `context` lines either side, and the
+/// zero-based line the snippet starts at.
+fn snippet(text: &str, range: Range, context: u32) -> (u32, String) {
+    let start = range.start.line.saturating_sub(context);
+    let end = range
+        .end
+        .line
+        .saturating_add(context)
+        .min(start + MAX_SNIPPET_LINES - 1);
+    let lines: Vec<&str> = text
+        .lines()
+        .skip(start as usize)
+        .take((end - start + 1) as usize)
+        .collect();
+    (start, lines.join("\n"))
+}
+
+/// The text `range` covers in `text`, on its start line.
+fn range_text(text: &str, range: Range, encoding: PositionEncoding) -> String {
+    let line = line_text(text, range.start.line);
+    let start = encoding.byte_offset(line, range.start.character);
+    let end = if range.end.line == range.start.line {
+        encoding.byte_offset(line, range.end.character).max(start)
+    } else {
+        line.len()
+    };
+    line[start..end].to_string()
+}
+
+fn text_edits_json(uri: &str, edits: &[TextEdit], mapper: &ColumnMapper) -> Vec<Value> {
+    edits
+        .iter()
+        .map(|edit| {
+            json!({
+                "range": mapper.range_to_client(uri, edit.range),
+                "new_text": edit.new_text
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbols() -> Vec<DocumentSymbol> {
+        let range =
+            json!({"start": {"line": 0, "character": 0}, "end": {"line": 4, "character": 1}});
+        let symbol = |name: &str, kind: u8, children: Value| json!({"name": name, "kind": kind, "range": range, "selectionRange": range, "children": children});
+        let field = symbol("x", 8, json!([]));
+        let method = symbol("new", 6, json!([symbol("inner", 12, json!([]))]));
+        serde_json::from_value(json!([
+            symbol("Point", 23, json!([field])),
+            symbol("Point_impl", 19, json!([method]))
+        ]))
+        .unwrap()
+    }
+
+    /// Names per depth in the JSON outline.
+    fn json_depths(symbols: &[Value], depth: usize, out: &mut Vec<(usize, String)>) {
+        for symbol in symbols {
+            out.push((depth, symbol["name"].as_str().unwrap().to_string()));
+            json_depths(symbol["children"].as_array().unwrap(), depth + 1, out);
+        }
+    }
+
+    #[test]
+    fn max_depth_means_the_same_in_json_and_tree() {
+        let symbols = symbols();
+        let mapper = ColumnMapper::new(PositionEncoding::Utf8, PositionEncoding::Utf8);
+        for max_depth in [0, 1, 2, usize::MAX] {
+            let mut from_json = Vec::new();
+            let json = symbols_json("file:///a.rs", &symbols, 0, max_depth, &mapper);
+            json_depths(&json, 0, &mut from_json);
+
+            let mut tree = String::new();
+            symbol_tree(&symbols, 0, max_depth, &mut tree);
+            let from_tree: Vec<(usize, String)> = tree
+                .lines()
+                .map(|line| {
+                    let indent = line.len() - line.trim_start().len();
+                    let name = line.split_whitespace().nth(1).unwrap().to_string();
+                    (indent / 2, name)
+                })
+                .collect();
+
+            assert_eq!(from_json, from_tree, "max_depth {max_depth}");
+            let deepest = from_json.iter().map(|(depth, _)| *depth).max();
+            assert_eq!(deepest, Some(max_depth.min(2)), "max_depth {max_depth}");
+        }
+    }
+}
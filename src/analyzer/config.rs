@@ -0,0 +1,114 @@
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+
+/// Environment variable holding rust-analyzer settings as JSON, e.g.
+/// `{"check.command": "clippy", "cargo.features": "all"}`.
+pub const CONFIG_ENV_VAR: &str = "RUST_ANALYZER_CONFIG";
+
+/// Settings handed to rust-analyzer through `initializationOptions` and
+/// `workspace/configuration`.
+///
+/// Stored as the contents of the `rust-analyzer` section, the same shape as the
+/// `"rust-analyzer": { ... }` block in an editor's settings. Dotted keys are
+/// expanded, so `{"check.command": "clippy"}` and
+/// `{"check": {"command": "clippy"}}` mean the same thing.
+#[derive(Debug, Clone)]
+pub struct AnalyzerConfig {
+    settings: Value,
+}
+
+impl Default for AnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            settings: Value::Object(Map::new()),
+        }
+    }
+}
+
+impl AnalyzerConfig {
+    pub fn new(settings: Value) -> Result<Self> {
+        match expand_dotted_keys(settings) {
+            settings @ Value::Object(_) => Ok(Self { settings }),
+            Value::Null => Ok(Self::default()),
+            other => Err(anyhow::anyhow!(
+                "rust-analyzer settings must be a JSON object, got {other}"
+            )),
+        }
+    }
+
+    /// Read settings from `RUST_ANALYZER_CONFIG`, if set.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(CONFIG_ENV_VAR) {
+            Ok(raw) => {
+                let settings = serde_json::from_str(&raw)
+                    .with_context(|| format!("{CONFIG_ENV_VAR} is not valid JSON"))?;
+                Self::new(settings)
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    pub fn settings(&self) -> &Value {
+        &self.settings
+    }
+
+    /// Answer one `workspace/configuration` item.
+    ///
+    /// `section` is dotted, e.g. `rust-analyzer` or `rust-analyzer.check`.
+    /// Without a section the whole client configuration is requested, which
+    /// for us is just the `rust-analyzer` block. Unknown sections get `null`.
+    pub fn section(&self, section: Option<&str>) -> Value {
+        let Some(section) = section else {
+            let mut root = Map::new();
+            root.insert("rust-analyzer".to_string(), self.settings.clone());
+            return Value::Object(root);
+        };
+
+        let mut parts = section.split('.');
+        if parts.next() != Some("rust-analyzer") {
+            return Value::Null;
+        }
+
+        let mut current = &self.settings;
+        for part in parts {
+            match current.get(part) {
+                Some(next) => current = next,
+                None => return Value::Null,
+            }
+        }
+        current.clone()
+    }
+}
+
+/// Turn `{"a.b": 1}` into `{"a": {"b": 1}}`, recursively.
+fn expand_dotted_keys(value: Value) -> Value {
+    let Value::Object(map) = value else {
+        return value;
+    };
+
+    let mut expanded = Map::new();
+    for (key, value) in map {
+        let value = expand_dotted_keys(value);
+        let mut parts: Vec<&str> = key.split('.').collect();
+        let last = parts.pop().unwrap_or_default();
+
+        let mut target = &mut expanded;
+        for part in parts {
+            let entry = target
+                .entry(part.to_string())
+                .or_insert_with(|| Value::Object(Map::new()));
+            if !entry.is_object() {
+                *entry = Value::Object(Map::new());
+            }
+            target = entry.as_object_mut().unwrap();
+        }
+
+        match (target.get_mut(last), value) {
+            (Some(Value::Object(existing)), Value::Object(new)) => existing.extend(new),
+            (_, value) => {
+                target.insert(last.to_string(), value);
+            }
+        }
+    }
+    Value::Object(expanded)
+}
@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+use crate::analyzer::protocol::{Diagnostic, DiagnosticSeverity, PublishDiagnosticsParams};
+use crate::analyzer::uri::normalize_uri;
+
+/// Latest diagnostics published by rust-analyzer, keyed by document URI as
+/// [`crate::analyzer::uri::path_to_uri`] spells it.
+///
+/// rust-analyzer always publishes the full set for a file, so each
+/// notification replaces whatever was stored before. An empty set removes
+/// the entry.
+#[derive(Default)]
+pub struct DiagnosticStore {
+    by_uri: Mutex<HashMap<String, Vec<Diagnostic>>>,
+    /// The document version of the last publish for each URI, including
+    /// empty ones, so "no diagnostics" can be told apart from "not analyzed
+    /// yet". `None` when rust-analyzer did not say which version it was.
+    published: Mutex<HashMap<String, Option<i32>>>,
+    /// Woken on every publish.
+    changed: Notify,
+}
+
+impl DiagnosticStore {
+    pub fn update(&self, mut params: PublishDiagnosticsParams) {
+        params.uri = normalize_uri(&params.uri);
+        self.published
+            .lock()
+            .unwrap()
+            .insert(params.uri.clone(), params.version);
+        let mut by_uri = self.by_uri.lock().unwrap();
+        if params.diagnostics.is_empty() {
+            by_uri.remove(&params.uri);
+        } else {
+            by_uri.insert(params.uri, params.diagnostics);
+        }
+        drop(by_uri);
+        self.changed.notify_waiters();
+    }
+
+    /// Whether rust-analyzer has published diagnostics for `version` of
+    /// `uri`, or a later one. A publish without a version counts for any.
+    pub fn is_published(&self, uri: &str, version: Option<i32>) -> bool {
+        match self.published.lock().unwrap().get(uri) {
+            Some(Some(published)) => version.is_none_or(|version| *published >= version),
+            Some(None) => true,
+            None => false,
+        }
+    }
+
+    /// Wait up to `timeout` for diagnostics covering `version` of `uri` to be
+    /// published. Returns whether they were.
+    pub async fn wait_for(&self, uri: &str, version: Option<i32>, timeout: Duration) -> bool {
+        let deadline = tokio::time::Instant::now() + timeout;
+        loop {
+            let changed = self.changed.notified();
+            tokio::pin!(changed);
+            // Register before checking so a publish in between is not missed.
+            changed.as_mut().enable();
+            if self.is_published(uri, version) {
+                return true;
+            }
+            if tokio::time::timeout_at(deadline, changed).await.is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// Diagnostics for one file at or above `min_severity`.
+    pub fn for_uri(&self, uri: &str, min_severity: Option<DiagnosticSeverity>) -> Vec<Diagnostic> {
+        self.by_uri
+            .lock()
+            .unwrap()
+            .get(uri)
+            .map(|diagnostics| filter(diagnostics, min_severity))
+            .unwrap_or_default()
+    }
+
+    /// Diagnostics for every file in the workspace, sorted by URI.
+    pub fn all(&self, min_severity: Option<DiagnosticSeverity>) -> Vec<(String, Vec<Diagnostic>)> {
+        let mut files: Vec<_> = self
+            .by_uri
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(uri, diagnostics)| (uri.clone(), filter(diagnostics, min_severity)))
+            .filter(|(_, diagnostics)| !diagnostics.is_empty())
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        files
+    }
+
+    pub fn clear(&self) {
+        self.by_uri.lock().unwrap().clear();
+        self.published.lock().unwrap().clear();
+    }
+}
+
+fn filter(diagnostics: &[Diagnostic], min_severity: Option<DiagnosticSeverity>) -> Vec<Diagnostic> {
+    diagnostics
+        .iter()
+        .filter(|d| match (min_severity, d.severity) {
+            (Some(min), Some(severity)) => severity <= min,
+            // A missing severity is left to the client to interpret, so keep it.
+            _ => true,
+        })
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::analyzer::uri::path_to_uri;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn publishes_are_found_by_path() {
+        let store = DiagnosticStore::default();
+        // How rust-analyzer spells the URI, with `+` left unescaped.
+        let params = serde_json::json!({
+            "uri": "file:///tmp/a+b/x.rs",
+            "version": 2,
+            "diagnostics": [{
+                "range": {"start": {"line": 0, "character": 0}, "end": {"line": 0, "character": 1}},
+                "severity": 1,
+                "message": "expected item"
+            }]
+        });
+        store.update(serde_json::from_value(params).unwrap());
+
+        let uri = path_to_uri(Path::new("/tmp/a+b/x.rs"));
+        assert!(store.is_published(&uri, Some(2)));
+        assert!(!store.is_published(&uri, Some(3)));
+        assert!(store.wait_for(&uri, Some(1), Duration::ZERO).await);
+        assert_eq!(store.for_uri(&uri, None).len(), 1);
+    }
+}
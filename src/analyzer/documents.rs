@@ -0,0 +1,481 @@
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::analyzer::encoding::PositionEncoding;
+use crate::analyzer::protocol::{Position, Range, TextEdit};
+
+/// How long a document may go untouched before it is closed again.
+pub const DOCUMENT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A document we have sent `textDocument/didOpen` for.
+pub struct OpenDocument {
+    pub version: i32,
+    pub text: String,
+    /// The text came from an unsaved buffer rather than the file on disk.
+    /// It stays until the file is next synced from disk.
+    pub from_buffer: bool,
+    last_used: Instant,
+}
+
+/// Where new document contents came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextSource {
+    /// Read from disk.
+    Disk,
+    /// Unsaved contents supplied by the caller.
+    Buffer,
+    /// Contents one of our tools just wrote to disk.
+    Written,
+}
+
+/// What has to be sent to rust-analyzer to bring a document up to date.
+pub enum DocumentSync {
+    Open {
+        uri: String,
+        version: i32,
+        text: String,
+    },
+    Change {
+        uri: String,
+        version: i32,
+        range: Range,
+        text: String,
+    },
+    Unchanged,
+}
+
+impl DocumentSync {
+    /// The notification that performs this sync, if any.
+    pub fn notification(&self) -> Option<(&'static str, Value)> {
+        match self {
+            Self::Open { uri, version, text } => Some((
+                "textDocument/didOpen",
+                json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "languageId": "rust",
+                        "version": version,
+                        "text": text
+                    }
+                }),
+            )),
+            Self::Change {
+                uri,
+                version,
+                range,
+                text,
+            } => Some((
+                "textDocument/didChange",
+                json!({
+                    "textDocument": {
+                        "uri": uri,
+                        "version": version
+                    },
+                    "contentChanges": [{
+                        "range": range,
+                        "text": text
+                    }]
+                }),
+            )),
+            Self::Unchanged => None,
+        }
+    }
+}
+
+/// Documents currently open in rust-analyzer, keyed by URI.
+#[derive(Default)]
+pub struct DocumentStore {
+    open: HashMap<String, OpenDocument>,
+    /// How the server counts columns in `didChange` ranges.
+    encoding: PositionEncoding,
+}
+
+impl DocumentStore {
+    pub fn set_encoding(&mut self, encoding: PositionEncoding) {
+        self.encoding = encoding;
+    }
+
+    /// The text we last sent for `uri`, if it is open.
+    pub fn text(&self, uri: &str) -> Option<&str> {
+        self.open.get(uri).map(|document| document.text.as_str())
+    }
+
+    pub fn version(&self, uri: &str) -> Option<i32> {
+        self.open.get(uri).map(|document| document.version)
+    }
+
+    pub fn is_open(&self, uri: &str) -> bool {
+        self.open.contains_key(uri)
+    }
+
+    /// Whether `uri` holds an unsaved buffer rather than the file on disk.
+    pub fn from_buffer(&self, uri: &str) -> bool {
+        self.open
+            .get(uri)
+            .is_some_and(|document| document.from_buffer)
+    }
+
+    /// Record `text` as the current contents of `uri`.
+    pub fn sync(&mut self, uri: &str, text: String, source: TextSource) -> DocumentSync {
+        let now = Instant::now();
+        let from_buffer = source == TextSource::Buffer;
+        let Some(document) = self.open.get_mut(uri) else {
+            self.open.insert(
+                uri.to_string(),
+                OpenDocument {
+                    version: 1,
+                    text: text.clone(),
+                    from_buffer,
+                    last_used: now,
+                },
+            );
+            return DocumentSync::Open {
+                uri: uri.to_string(),
+                version: 1,
+                text,
+            };
+        };
+
+        document.last_used = now;
+        if document.text == text {
+            document.from_buffer = from_buffer;
+            return DocumentSync::Unchanged;
+        }
+
+        let (range, replacement) = incremental_change(&document.text, &text, self.encoding);
+        document.version += 1;
+        document.text = text;
+        document.from_buffer = from_buffer;
+        DocumentSync::Change {
+            uri: uri.to_string(),
+            version: document.version,
+            range,
+            text: replacement,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.open.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.open.is_empty()
+    }
+
+    /// A `didOpen` for every tracked document, restarting version numbers.
+    /// Used to bring a freshly restarted server up to date.
+    pub fn reopen(&mut self) -> Vec<DocumentSync> {
+        self.open
+            .iter_mut()
+            .map(|(uri, document)| {
+                document.version = 1;
+                DocumentSync::Open {
+                    uri: uri.clone(),
+                    version: 1,
+                    text: document.text.clone(),
+                }
+            })
+            .collect()
+    }
+
+    /// Stop tracking `uri`. Returns whether it was open.
+    pub fn close(&mut self, uri: &str) -> bool {
+        self.open.remove(uri).is_some()
+    }
+
+    /// Forget every document not used within `idle` and return their URIs.
+    pub fn take_idle(&mut self, idle: Duration) -> Vec<String> {
+        let now = Instant::now();
+        let idle_uris: Vec<String> = self
+            .open
+            .iter()
+            .filter(|(_, document)| now.duration_since(document.last_used) >= idle)
+            .map(|(uri, _)| uri.clone())
+            .collect();
+        for uri in &idle_uris {
+            self.open.remove(uri);
+        }
+        idle_uris
+    }
+}
+
+/// The single replacement that turns `old` into `new`: everything between
+/// their common prefix and common suffix.
+pub fn incremental_change(old: &str, new: &str, encoding: PositionEncoding) -> (Range, String) {
+    let prefix = old
+        .char_indices()
+        .zip(new.chars())
+        .find(|((_, a), b)| a != b)
+        .map(|((i, _), _)| i)
+        .unwrap_or(old.len().min(new.len()));
+
+    let max_suffix = old.len().min(new.len()) - prefix;
+    let suffix = old[prefix..]
+        .chars()
+        .rev()
+        .zip(new[prefix..].chars().rev())
+        .take_while(|(a, b)| a == b)
+        .map(|(a, _)| a.len_utf8())
+        .scan(0, |total, len| {
+            *total += len;
+            Some(*total)
+        })
+        .take_while(|total| *total <= max_suffix)
+        .last()
+        .unwrap_or(0);
+
+    let range = Range {
+        start: offset_to_position(old, prefix, encoding),
+        end: offset_to_position(old, old.len() - suffix, encoding),
+    };
+    (range, new[prefix..new.len() - suffix].to_string())
+}
+
+/// Convert an LSP position with columns in `encoding` into a byte offset.
+/// Positions past the end of a line or of the text clamp to it.
+pub fn position_to_offset(text: &str, position: Position, encoding: PositionEncoding) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(i) => line_start += i + 1,
+            None => return text.len(),
+        }
+    }
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let line = line.strip_suffix('\r').unwrap_or(line);
+    line_start + encoding.byte_offset(line, position.character)
+}
+
+/// Apply LSP text edits, all relative to the original `text`.
+pub fn apply_text_edits(
+    text: &str,
+    edits: &[TextEdit],
+    encoding: PositionEncoding,
+) -> anyhow::Result<String> {
+    let mut spans: Vec<(usize, usize, &str)> = edits
+        .iter()
+        .map(|edit| {
+            let start = position_to_offset(text, edit.range.start, encoding);
+            let end = position_to_offset(text, edit.range.end, encoding).max(start);
+            (start, end, edit.new_text.as_str())
+        })
+        .collect();
+    // Stable, so inserts at the same offset keep their order.
+    spans.sort_by_key(|&(start, end, _)| (start, end));
+
+    let mut result = String::with_capacity(text.len());
+    let mut copied = 0;
+    for (start, end, new_text) in spans {
+        if start < copied {
+            return Err(anyhow::anyhow!("Overlapping text edits"));
+        }
+        result.push_str(&text[copied..start]);
+        result.push_str(new_text);
+        copied = end;
+    }
+    result.push_str(&text[copied..]);
+    Ok(result)
+}
+
+/// Convert a byte offset into an LSP position with columns in `encoding`.
+pub fn offset_to_position(text: &str, offset: usize, encoding: PositionEncoding) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    Position {
+        line: before.matches('\n').count() as u32,
+        character: encoding.len(&before[line_start..]) as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    fn edit(start: Position, end: Position, new_text: &str) -> TextEdit {
+        TextEdit {
+            range: Range { start, end },
+            new_text: new_text.to_string(),
+        }
+    }
+
+    /// Check that the change computed from `old` to `new` turns one into the
+    /// other when applied, and return its range.
+    fn round_trip(old: &str, new: &str, encoding: PositionEncoding) -> (Range, String) {
+        let (range, text) = incremental_change(old, new, encoding);
+        let applied = apply_text_edits(old, &[edit(range.start, range.end, &text)], encoding);
+        assert_eq!(applied.unwrap(), new);
+        (range, text)
+    }
+
+    #[test]
+    fn incremental_change_covers_only_the_difference() {
+        let (range, text) = round_trip(
+            "fn main() {\n    foo();\n}\n",
+            "fn main() {\n    bar();\n}\n",
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(range.start, position(1, 4));
+        assert_eq!(range.end, position(1, 7));
+        assert_eq!(text, "bar");
+    }
+
+    #[test]
+    fn incremental_change_at_end_of_file() {
+        let (range, text) = round_trip("a\nb", "a\nbc", PositionEncoding::Utf16);
+        assert_eq!((range.start, range.end), (position(1, 1), position(1, 1)));
+        assert_eq!(text, "c");
+
+        // A line appended after the trailing newline starts on the empty last
+        // line.
+        let (range, text) = round_trip("a\n", "a\nb\n", PositionEncoding::Utf16);
+        assert_eq!((range.start, range.end), (position(1, 0), position(1, 0)));
+        assert_eq!(text, "b\n");
+
+        let (range, text) = round_trip("a\n", "a", PositionEncoding::Utf16);
+        assert_eq!((range.start, range.end), (position(0, 1), position(1, 0)));
+        assert_eq!(text, "");
+    }
+
+    #[test]
+    fn incremental_change_with_repeated_text() {
+        // The common prefix and suffix must not overlap.
+        round_trip("aaa", "aaaa", PositionEncoding::Utf16);
+        round_trip("aaaa", "aa", PositionEncoding::Utf16);
+        round_trip("\n\n", "\n\n\n", PositionEncoding::Utf16);
+        round_trip("", "fn f() {}\n", PositionEncoding::Utf16);
+        round_trip("fn f() {}\n", "", PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn incremental_change_counts_columns_in_the_server_encoding() {
+        let old = "let s = \"é😀x\";\n";
+        let new = "let s = \"é😀y\";\n";
+        let (range, _) = round_trip(old, new, PositionEncoding::Utf16);
+        assert_eq!(range.start, position(0, 12));
+        let (range, _) = round_trip(old, new, PositionEncoding::Utf8);
+        assert_eq!(range.start, position(0, 15));
+        let (range, _) = round_trip(old, new, PositionEncoding::Utf32);
+        assert_eq!(range.start, position(0, 11));
+
+        // Characters that share leading bytes must not be split.
+        round_trip("é", "è", PositionEncoding::Utf16);
+        round_trip("😀", "😁", PositionEncoding::Utf16);
+    }
+
+    #[test]
+    fn apply_text_edits_rejects_overlapping_edits() {
+        let edits = [
+            edit(position(0, 0), position(0, 3), "x"),
+            edit(position(0, 2), position(0, 4), "y"),
+        ];
+        assert!(apply_text_edits("abcdef", &edits, PositionEncoding::Utf16).is_err());
+    }
+
+    #[test]
+    fn apply_text_edits_allows_adjacent_edits_and_keeps_insert_order() {
+        let edits = [
+            edit(position(0, 3), position(0, 3), "2"),
+            edit(position(0, 0), position(0, 3), "x"),
+            edit(position(0, 3), position(0, 3), "3"),
+            edit(position(0, 3), position(0, 6), "y"),
+        ];
+        let result = apply_text_edits("abcdef", &edits, PositionEncoding::Utf16).unwrap();
+        assert_eq!(result, "x23y");
+    }
+
+    #[test]
+    fn apply_text_edits_at_end_of_file() {
+        let text = "a\nb\n";
+        let edits = [edit(position(2, 0), position(2, 0), "c\n")];
+        let result = apply_text_edits(text, &edits, PositionEncoding::Utf16).unwrap();
+        assert_eq!(result, "a\nb\nc\n");
+
+        // Positions past the end clamp to it.
+        let edits = [edit(position(1, 0), position(9, 9), "")];
+        let result = apply_text_edits(text, &edits, PositionEncoding::Utf16).unwrap();
+        assert_eq!(result, "a\n");
+        let edits = [edit(position(0, 40), position(0, 40), "!")];
+        let result = apply_text_edits(text, &edits, PositionEncoding::Utf16).unwrap();
+        assert_eq!(result, "a!\nb\n");
+    }
+
+    #[test]
+    fn apply_text_edits_ignores_carriage_returns_in_columns() {
+        let edits = [edit(position(0, 1), position(1, 0), "")];
+        let result = apply_text_edits("a\r\nb\r\n", &edits, PositionEncoding::Utf16).unwrap();
+        assert_eq!(result, "ab\r\n");
+    }
+
+    #[test]
+    fn offset_to_position_at_line_ends() {
+        let text = "ab\n😀c\n";
+        let encoding = PositionEncoding::Utf16;
+        assert_eq!(offset_to_position(text, 0, encoding), position(0, 0));
+        assert_eq!(offset_to_position(text, 2, encoding), position(0, 2));
+        assert_eq!(offset_to_position(text, 3, encoding), position(1, 0));
+        assert_eq!(offset_to_position(text, 7, encoding), position(1, 2));
+        assert_eq!(
+            offset_to_position(text, text.len(), encoding),
+            position(2, 0)
+        );
+        for offset in [0, 2, 3, 7, 8, text.len()] {
+            let position = offset_to_position(text, offset, encoding);
+            assert_eq!(position_to_offset(text, position, encoding), offset);
+        }
+    }
+
+    #[test]
+    fn sync_opens_then_sends_versioned_changes() {
+        let mut store = DocumentStore::default();
+        let uri = "file:///src/lib.rs";
+        assert!(matches!(
+            store.sync(uri, "a\n".to_string(), TextSource::Disk),
+            DocumentSync::Open { version: 1, .. }
+        ));
+        assert!(matches!(
+            store.sync(uri, "a\n".to_string(), TextSource::Disk),
+            DocumentSync::Unchanged
+        ));
+        match store.sync(uri, "a\nb\n".to_string(), TextSource::Written) {
+            DocumentSync::Change {
+                version,
+                range,
+                text,
+                ..
+            } => {
+                assert_eq!(version, 2);
+                assert_eq!((range.start, range.end), (position(1, 0), position(1, 0)));
+                assert_eq!(text, "b\n");
+            }
+            _ => panic!("expected a change"),
+        }
+        assert_eq!(store.text(uri), Some("a\nb\n"));
+    }
+
+    #[test]
+    fn disk_contents_replace_a_buffer() {
+        let mut store = DocumentStore::default();
+        let uri = "file:///src/lib.rs";
+        store.sync(uri, "a\n".to_string(), TextSource::Disk);
+        store.sync(uri, "ab\n".to_string(), TextSource::Buffer);
+        assert!(store.from_buffer(uri));
+
+        assert!(matches!(
+            store.sync(uri, "a\n".to_string(), TextSource::Disk),
+            DocumentSync::Change { version: 3, .. }
+        ));
+        assert!(!store.from_buffer(uri));
+        assert_eq!(store.text(uri), Some("a\n"));
+
+        // A buffer matching the file is still a buffer until disk is synced.
+        store.sync(uri, "a\n".to_string(), TextSource::Buffer);
+        assert!(store.from_buffer(uri));
+        store.sync(uri, "a\n".to_string(), TextSource::Disk);
+        assert!(!store.from_buffer(uri));
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::HashMap;
+
+use crate::analyzer::protocol::{Position, Range};
+
+/// Environment variable choosing how agents count columns: `utf-8` (bytes,
+/// the default), `utf-16` or `utf-32` (characters).
+pub const COLUMN_ENCODING_ENV_VAR: &str = "RUST_MCP_COLUMN_ENCODING";
+
+/// What the `character` of an LSP position counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PositionEncoding {
+    /// Bytes.
+    Utf8,
+    /// UTF-16 code units, the LSP default.
+    #[default]
+    Utf16,
+    /// Unicode scalar values, i.e. Rust `char`s.
+    Utf32,
+}
+
+impl PositionEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Utf8 => "utf-8",
+            Self::Utf16 => "utf-16",
+            Self::Utf32 => "utf-32",
+        }
+    }
+
+    /// Read the agent-facing column encoding from `RUST_MCP_COLUMN_ENCODING`,
+    /// defaulting to bytes.
+    pub fn from_env() -> anyhow::Result<Self> {
+        match std::env::var(COLUMN_ENCODING_ENV_VAR) {
+            Ok(raw) => raw.parse(),
+            Err(_) => Ok(Self::Utf8),
+        }
+    }
+
+    /// Width of `c` in this encoding.
+    fn width(self, c: char) -> usize {
+        match self {
+            Self::Utf8 => c.len_utf8(),
+            Self::Utf16 => c.len_utf16(),
+            Self::Utf32 => 1,
+        }
+    }
+
+    /// Length of `s` in this encoding.
+    pub fn len(self, s: &str) -> usize {
+        match self {
+            Self::Utf8 => s.len(),
+            Self::Utf16 => s.encode_utf16().count(),
+            Self::Utf32 => s.chars().count(),
+        }
+    }
+
+    /// Byte offset of `column` within `line`. Columns past the end clamp to
+    /// the end; columns inside a character round down to its start.
+    pub fn byte_offset(self, line: &str, column: u32) -> usize {
+        let column = column as usize;
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            units += self.width(c);
+            if units > column {
+                return offset;
+            }
+        }
+        line.len()
+    }
+}
+
+impl std::str::FromStr for PositionEncoding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" | "byte" | "bytes" => Ok(Self::Utf8),
+            "utf-16" | "utf16" => Ok(Self::Utf16),
+            "utf-32" | "utf32" | "char" | "chars" => Ok(Self::Utf32),
+            other => Err(anyhow::anyhow!("Unknown position encoding: {other}")),
+        }
+    }
+}
+
+/// The text of line `line`, without its line ending.
+pub fn line_text(text: &str, line: u32) -> &str {
+    let line = text.split('\n').nth(line as usize).unwrap_or("");
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+/// Re-express `position` in `text` from one encoding in another.
+pub fn convert_position(
+    text: &str,
+    position: Position,
+    from: PositionEncoding,
+    to: PositionEncoding,
+) -> Position {
+    if from == to {
+        return position;
+    }
+    let line = line_text(text, position.line);
+    let offset = from.byte_offset(line, position.character);
+    Position {
+        line: position.line,
+        character: to.len(&line[..offset]) as u32,
+    }
+}
+
+/// Converts positions between the columns agents use and the ones
+/// rust-analyzer negotiated, for a set of documents whose text is known.
+pub struct ColumnMapper {
+    client: PositionEncoding,
+    server: PositionEncoding,
+    texts: HashMap<String, String>,
+}
+
+impl ColumnMapper {
+    pub fn new(client: PositionEncoding, server: PositionEncoding) -> Self {
+        Self {
+            client,
+            server,
+            texts: HashMap::new(),
+        }
+    }
+
+    /// Whether conversion is needed at all; if not, no texts are required.
+    pub fn is_identity(&self) -> bool {
+        self.client == self.server
+    }
+
+    pub fn has_text(&self, uri: &str) -> bool {
+        self.texts.contains_key(uri)
+    }
+
+    pub fn insert_text(&mut self, uri: &str, text: String) {
+        self.texts.insert(uri.to_string(), text);
+    }
+
+    /// An agent's position in `uri`, as rust-analyzer counts it.
+    pub fn to_server(&self, uri: &str, position: Position) -> Position {
+        match self.texts.get(uri) {
+            Some(text) => convert_position(text, position, self.client, self.server),
+            None => position,
+        }
+    }
+
+    /// A position from rust-analyzer in `uri`, as the agent counts it.
+    pub fn to_client(&self, uri: &str, position: Position) -> Position {
+        match self.texts.get(uri) {
+            Some(text) => convert_position(text, position, self.server, self.client),
+            None => position,
+        }
+    }
+
+    pub fn range_to_client(&self, uri: &str, range: Range) -> Range {
+        Range {
+            start: self.to_client(uri, range.start),
+            end: self.to_client(uri, range.end),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> Position {
+        Position { line, character }
+    }
+
+    // "a", "é" (2 bytes, 1 UTF-16 unit), "😀" (4 bytes, 2 UTF-16 units), "b".
+    const LINE: &str = "aé😀b";
+
+    #[test]
+    fn lengths_in_each_encoding() {
+        assert_eq!(PositionEncoding::Utf8.len(LINE), 8);
+        assert_eq!(PositionEncoding::Utf16.len(LINE), 5);
+        assert_eq!(PositionEncoding::Utf32.len(LINE), 4);
+    }
+
+    #[test]
+    fn byte_offsets_in_each_encoding() {
+        let cases = [
+            (PositionEncoding::Utf8, [0, 1, 3, 7, 8]),
+            (PositionEncoding::Utf16, [0, 1, 2, 4, 5]),
+            (PositionEncoding::Utf32, [0, 1, 2, 3, 4]),
+        ];
+        for (encoding, columns) in cases {
+            for (column, offset) in columns.into_iter().zip([0, 1, 3, 7, 8]) {
+                assert_eq!(encoding.byte_offset(LINE, column), offset, "{encoding:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn columns_inside_a_character_round_down_and_past_the_end_clamp() {
+        // Between the two UTF-16 units of the emoji.
+        assert_eq!(PositionEncoding::Utf16.byte_offset(LINE, 3), 3);
+        // Inside the emoji's bytes.
+        assert_eq!(PositionEncoding::Utf8.byte_offset(LINE, 5), 3);
+        assert_eq!(PositionEncoding::Utf16.byte_offset(LINE, 99), LINE.len());
+        assert_eq!(PositionEncoding::Utf8.byte_offset("", 3), 0);
+    }
+
+    #[test]
+    fn positions_convert_between_encodings() {
+        let text = "fn f() {}\nlet s = \"é😀\"; x\r\n";
+        let utf16 = position(1, 14);
+        let utf8 = convert_position(text, utf16, PositionEncoding::Utf16, PositionEncoding::Utf8);
+        assert_eq!(utf8, position(1, 17));
+        let utf32 = convert_position(text, utf8, PositionEncoding::Utf8, PositionEncoding::Utf32);
+        assert_eq!(utf32, position(1, 13));
+        let back = convert_position(
+            text,
+            utf32,
+            PositionEncoding::Utf32,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(back, utf16);
+
+        // The line ending is not part of the line.
+        let end = convert_position(
+            text,
+            position(1, 99),
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(end, position(1, 16));
+        // Lines past the end keep their number and clamp to column 0.
+        let past = convert_position(
+            text,
+            position(7, 3),
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16,
+        );
+        assert_eq!(past, position(7, 0));
+    }
+
+    #[test]
+    fn column_mapper_converts_only_known_documents() {
+        let mut mapper = ColumnMapper::new(PositionEncoding::Utf8, PositionEncoding::Utf16);
+        assert!(!mapper.is_identity());
+        mapper.insert_text("file:///a.rs", LINE.to_string());
+        assert_eq!(
+            mapper.to_server("file:///a.rs", position(0, 7)),
+            position(0, 4)
+        );
+        assert_eq!(
+            mapper.to_client("file:///a.rs", position(0, 4)),
+            position(0, 7)
+        );
+        assert_eq!(
+            mapper.to_server("file:///b.rs", position(0, 7)),
+            position(0, 7)
+        );
+        assert!(ColumnMapper::new(PositionEncoding::Utf16, PositionEncoding::Utf16).is_identity());
+    }
+
+    #[test]
+    fn encoding_names_parse() {
+        assert_eq!(
+            "UTF-16".parse::<PositionEncoding>().unwrap(),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(
+            "chars".parse::<PositionEncoding>().unwrap(),
+            PositionEncoding::Utf32
+        );
+        assert_eq!(
+            "bytes".parse::<PositionEncoding>().unwrap(),
+            PositionEncoding::Utf8
+        );
+        assert!("latin1".parse::<PositionEncoding>().is_err());
+    }
+}
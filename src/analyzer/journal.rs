@@ -0,0 +1,253 @@
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Mutex as StdMutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many transactions are kept for undo. Older ones are forgotten, along
+/// with the file contents they hold.
+pub const MAX_JOURNAL_TRANSACTIONS: usize = 100;
+
+/// One file as a transaction found and left it. `None` means it did not
+/// exist.
+#[derive(Debug, Clone)]
+pub struct FileRecord {
+    pub path: PathBuf,
+    pub before: Option<Vec<u8>>,
+    pub after: Option<Vec<u8>>,
+}
+
+/// The SHA-256 of file contents, or `None` for a missing file.
+pub fn content_hash(contents: Option<&[u8]>) -> Option<String> {
+    contents.map(|bytes| {
+        Sha256::digest(bytes)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    })
+}
+
+/// Every file a tool call changed, recorded once the changes are on disk.
+#[derive(Debug, Clone)]
+pub struct EditTransaction {
+    pub id: u64,
+    /// The tool that made the edit and what it was asked to do.
+    pub description: String,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    pub files: Vec<FileRecord>,
+}
+
+impl EditTransaction {
+    pub fn to_json(&self) -> Value {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                json!({
+                    "file": file.path.display().to_string(),
+                    "before_sha256": content_hash(file.before.as_deref()),
+                    "after_sha256": content_hash(file.after.as_deref())
+                })
+            })
+            .collect();
+        json!({
+            "id": self.id,
+            "description": self.description,
+            "timestamp": self.timestamp,
+            "files": files
+        })
+    }
+}
+
+#[derive(Default)]
+struct JournalState {
+    next_id: u64,
+    /// Applied transactions, oldest first.
+    done: Vec<EditTransaction>,
+    /// Undone transactions, most recently undone last.
+    undone: Vec<EditTransaction>,
+}
+
+/// The edits made during this session, for `list_edits`, `undo_edit` and
+/// `redo_edit`. Shared by every analyzer in a pool.
+#[derive(Default)]
+pub struct EditJournal {
+    state: StdMutex<JournalState>,
+}
+
+impl EditJournal {
+    /// Record a transaction that was just applied. Anything undone is no
+    /// longer redoable once new edits are made.
+    pub fn record(&self, description: String, files: Vec<FileRecord>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+        let id = state.next_id;
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        state.done.push(EditTransaction {
+            id,
+            description,
+            timestamp,
+            files,
+        });
+        if state.done.len() > MAX_JOURNAL_TRANSACTIONS {
+            state.done.remove(0);
+        }
+        state.undone.clear();
+        id
+    }
+
+    /// Applied and undone transactions, newest first.
+    pub fn to_json(&self) -> Value {
+        let state = self.state.lock().unwrap();
+        json!({
+            "applied": state.done.iter().rev().map(EditTransaction::to_json).collect::<Vec<_>>(),
+            "undone": state.undone.iter().rev().map(EditTransaction::to_json).collect::<Vec<_>>()
+        })
+    }
+
+    /// The transaction `undo_edit` would revert: `id`, or the latest.
+    pub fn undo_candidate(&self, id: Option<u64>) -> anyhow::Result<EditTransaction> {
+        let state = self.state.lock().unwrap();
+        find(&state.done, id, "applied")
+    }
+
+    /// The transaction `redo_edit` would reapply: `id`, or the one undone
+    /// most recently.
+    pub fn redo_candidate(&self, id: Option<u64>) -> anyhow::Result<EditTransaction> {
+        let state = self.state.lock().unwrap();
+        find(&state.undone, id, "undone")
+    }
+
+    /// Move a transaction from the applied list to the undone one.
+    pub fn mark_undone(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = state.done.iter().position(|t| t.id == id) {
+            let transaction = state.done.remove(i);
+            state.undone.push(transaction);
+        }
+    }
+
+    /// Move a transaction from the undone list back to the applied one.
+    pub fn mark_redone(&self, id: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(i) = state.undone.iter().position(|t| t.id == id) {
+            let transaction = state.undone.remove(i);
+            state.done.push(transaction);
+        }
+    }
+}
+
+fn find(
+    transactions: &[EditTransaction],
+    id: Option<u64>,
+    which: &str,
+) -> anyhow::Result<EditTransaction> {
+    let found = match id {
+        Some(id) => transactions.iter().find(|t| t.id == id),
+        None => transactions.last(),
+    };
+    found.cloned().ok_or_else(|| match id {
+        Some(id) => anyhow::anyhow!("No {which} edit with id {id}"),
+        None => anyhow::anyhow!("No {which} edits"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(journal: &EditJournal, name: &str) -> u64 {
+        let file = FileRecord {
+            path: PathBuf::from(format!("/src/{name}.rs")),
+            before: Some(b"before".to_vec()),
+            after: Some(name.as_bytes().to_vec()),
+        };
+        journal.record(name.to_string(), vec![file])
+    }
+
+    fn ids(journal: &EditJournal, list: &str) -> Vec<u64> {
+        journal.to_json()[list]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|transaction| transaction["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn undo_and_redo_take_the_latest_by_default() {
+        let journal = EditJournal::default();
+        assert_eq!(record(&journal, "a"), 1);
+        assert_eq!(record(&journal, "b"), 2);
+        assert_eq!(record(&journal, "c"), 3);
+
+        let undo = journal.undo_candidate(None).unwrap();
+        assert_eq!(undo.id, 3);
+        journal.mark_undone(3);
+        assert_eq!(journal.undo_candidate(None).unwrap().id, 2);
+        journal.mark_undone(2);
+
+        assert_eq!(ids(&journal, "applied"), [1]);
+        assert_eq!(ids(&journal, "undone"), [2, 3]);
+        // The most recently undone comes back first.
+        assert_eq!(journal.redo_candidate(None).unwrap().id, 2);
+        journal.mark_redone(2);
+        assert_eq!(journal.redo_candidate(None).unwrap().id, 3);
+        assert_eq!(ids(&journal, "applied"), [2, 1]);
+    }
+
+    #[test]
+    fn undo_by_id_skips_later_edits() {
+        let journal = EditJournal::default();
+        for name in ["a", "b", "c"] {
+            record(&journal, name);
+        }
+        assert_eq!(journal.undo_candidate(Some(1)).unwrap().description, "a");
+        journal.mark_undone(1);
+        assert_eq!(ids(&journal, "applied"), [3, 2]);
+        assert!(journal.undo_candidate(Some(1)).is_err());
+        assert!(journal.redo_candidate(Some(2)).is_err());
+        assert_eq!(journal.redo_candidate(Some(1)).unwrap().id, 1);
+    }
+
+    #[test]
+    fn a_new_edit_clears_what_can_be_redone() {
+        let journal = EditJournal::default();
+        record(&journal, "a");
+        record(&journal, "b");
+        journal.mark_undone(2);
+        assert_eq!(record(&journal, "c"), 3);
+        assert!(ids(&journal, "undone").is_empty());
+        let error = journal.redo_candidate(None).unwrap_err();
+        assert_eq!(error.to_string(), "No undone edits");
+    }
+
+    #[test]
+    fn only_the_latest_transactions_are_kept() {
+        let journal = EditJournal::default();
+        for i in 0..MAX_JOURNAL_TRANSACTIONS + 5 {
+            record(&journal, &i.to_string());
+        }
+        let applied = ids(&journal, "applied");
+        assert_eq!(applied.len(), MAX_JOURNAL_TRANSACTIONS);
+        assert_eq!(
+            applied.first(),
+            Some(&(MAX_JOURNAL_TRANSACTIONS as u64 + 5))
+        );
+        assert_eq!(applied.last(), Some(&6));
+        assert!(journal.undo_candidate(Some(5)).is_err());
+    }
+
+    #[test]
+    fn hashes_tell_missing_files_apart_from_empty_ones() {
+        assert_eq!(content_hash(None), None);
+        assert_eq!(
+            content_hash(Some(b"")).as_deref(),
+            Some("e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855")
+        );
+    }
+}
@@ -1,13 +1,48 @@
 // LSP types and utilities for rust-analyzer integration
 // This module can contain type definitions, LSP message parsing, etc.
 
+use anyhow::{Result, anyhow};
 use serde_json::Value;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 pub struct LspMessage {
     pub content_length: usize,
     pub content: Value,
 }
 
+/// What an incoming message from the server is, as far as routing is concerned.
+pub enum MessageKind<'a> {
+    /// A reply to one of our requests.
+    Response { id: u64 },
+    /// A request the server expects us to answer.
+    Request { id: &'a Value, method: &'a str },
+    /// A fire-and-forget notification.
+    Notification { method: &'a str },
+    /// Anything that doesn't fit the JSON-RPC shapes above.
+    Invalid,
+}
+
+impl LspMessage {
+    pub fn kind(&self) -> MessageKind<'_> {
+        let id = self.content.get("id").filter(|id| !id.is_null());
+        let method = self.content.get("method").and_then(|m| m.as_str());
+
+        match (id, method) {
+            (Some(id), Some(method)) => MessageKind::Request { id, method },
+            (None, Some(method)) => MessageKind::Notification { method },
+            (Some(id), None) => match id.as_u64() {
+                Some(id) => MessageKind::Response { id },
+                None => MessageKind::Invalid,
+            },
+            (None, None) => MessageKind::Invalid,
+        }
+    }
+
+    pub fn params(&self) -> Value {
+        self.content.get("params").cloned().unwrap_or(Value::Null)
+    }
+}
+
 pub fn parse_lsp_message(raw_content: &[u8]) -> anyhow::Result<LspMessage> {
     let content: Value = serde_json::from_slice(raw_content)?;
     Ok(LspMessage {
@@ -15,3 +50,45 @@ pub fn parse_lsp_message(raw_content: &[u8]) -> anyhow::Result<LspMessage> {
         content,
     })
 }
+
+/// Read one `Content-Length` framed message. Returns `None` on a clean EOF.
+pub async fn read_message<R>(reader: &mut R) -> Result<Option<LspMessage>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 {
+            return Ok(None);
+        }
+
+        if line == "\r\n" {
+            break;
+        }
+
+        if let Some(stripped) = line.strip_prefix("Content-Length:") {
+            content_length = Some(stripped.trim().parse()?);
+        }
+    }
+
+    let length = content_length.ok_or_else(|| anyhow!("LSP message without Content-Length"))?;
+    let mut content = vec![0u8; length];
+    reader.read_exact(&mut content).await?;
+
+    parse_lsp_message(&content).map(Some)
+}
+
+/// Write one message with its `Content-Length` header and flush it.
+pub async fn write_message<W>(writer: &mut W, message: &Value) -> Result<()>
+where
+    W: AsyncWrite + Unpin,
+{
+    let content = message.to_string();
+    let header = format!("Content-Length: {}\r\n\r\n", content.len());
+
+    writer.write_all(header.as_bytes()).await?;
+    writer.write_all(content.as_bytes()).await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -0,0 +1,247 @@
+use crate::analyzer::protocol::{DocumentSymbol, Position};
+
+/// Symbol kinds that are part of an item rather than items themselves:
+/// fields, variables, enum variants and type parameters.
+const NON_ITEM_KINDS: [u8; 4] = [8, 13, 22, 26];
+
+/// The innermost item whose range contains `position`, e.g. a method
+/// rather than the impl around it.
+pub fn innermost_item(symbols: &[DocumentSymbol], position: Position) -> Option<&DocumentSymbol> {
+    symbols
+        .iter()
+        .filter(|symbol| {
+            symbol.range.start <= position
+                && position <= symbol.range.end
+                && !NON_ITEM_KINDS.contains(&symbol.kind.0)
+        })
+        .find_map(|symbol| {
+            let children = symbol.children.as_deref().unwrap_or_default();
+            innermost_item(children, position).or(Some(symbol))
+        })
+}
+
+/// Byte offsets in `text` of the macros to expand within `range`: the name
+/// of each outermost macro call (`name!(..)`, `name![..]`, `name! {..}`) and
+/// the path of each attribute, which covers derives and attribute macros.
+/// Calls nested in another call's arguments are left out, since expanding
+/// the outer one expands them too. Comments and literals are skipped.
+pub fn macro_call_offsets(text: &str, range: std::ops::Range<usize>) -> Vec<usize> {
+    let bytes = &text.as_bytes()[..range.end.min(text.len())];
+    let mut offsets = Vec::new();
+    let mut i = range.start;
+    while i < bytes.len() {
+        if let Some(next) = skip_trivia(bytes, i) {
+            i = next;
+            continue;
+        }
+        let byte = bytes[i];
+        if byte == b'#' {
+            let mut open = skip_whitespace(bytes, i + 1);
+            if bytes.get(open) == Some(&b'!') {
+                open = skip_whitespace(bytes, open + 1);
+            }
+            if bytes.get(open) == Some(&b'[') {
+                let path = skip_whitespace(bytes, open + 1);
+                if bytes.get(path).is_some_and(|&b| is_ident_start(b)) {
+                    offsets.push(path);
+                }
+                i = skip_group(bytes, open);
+                continue;
+            }
+        } else if is_ident_start(byte) && (i == 0 || !is_ident_byte(bytes[i - 1])) {
+            let start = i;
+            while i < bytes.len() && is_ident_byte(bytes[i]) {
+                i += 1;
+            }
+            let bang = skip_whitespace(bytes, i);
+            if bytes.get(bang) == Some(&b'!') && bytes.get(bang + 1) != Some(&b'=') {
+                let open = skip_whitespace(bytes, bang + 1);
+                if matches!(bytes.get(open), Some(b'(' | b'[' | b'{')) {
+                    offsets.push(start);
+                    i = skip_group(bytes, open);
+                }
+            }
+            continue;
+        }
+        i += 1;
+    }
+    offsets
+}
+
+fn is_ident_start(byte: u8) -> bool {
+    byte.is_ascii_alphabetic() || byte == b'_'
+}
+
+fn is_ident_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// Whether an identifier or literal can begin at `i`, rather than `i`
+/// being in the middle of one.
+fn starts_token(bytes: &[u8], i: usize) -> bool {
+    i == 0 || !is_ident_byte(bytes[i - 1])
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+        i += 1;
+    }
+    i
+}
+
+/// The index just past the bracketed group opening at `open`, or the end of
+/// the text if it is never closed.
+fn skip_group(bytes: &[u8], open: usize) -> usize {
+    let mut depth = 0usize;
+    let mut i = open;
+    while i < bytes.len() {
+        if let Some(next) = skip_trivia(bytes, i) {
+            i = next;
+            continue;
+        }
+        match bytes[i] {
+            b'(' | b'[' | b'{' => depth += 1,
+            b')' | b']' | b'}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+/// If a comment, string or character literal starts at `i`, the index just
+/// past it.
+fn skip_trivia(bytes: &[u8], i: usize) -> Option<usize> {
+    let find = |from: usize, needle: &[u8]| {
+        bytes[from.min(bytes.len())..]
+            .windows(needle.len())
+            .position(|window| window == needle)
+            .map_or(bytes.len(), |found| from + found + needle.len())
+    };
+    match (bytes[i], bytes.get(i + 1).copied()) {
+        (b'/', Some(b'/')) => Some(find(i, b"\n")),
+        // Block comments nest.
+        (b'/', Some(b'*')) => {
+            let (mut depth, mut j) = (0usize, i);
+            while j < bytes.len() {
+                match (bytes[j], bytes.get(j + 1)) {
+                    (b'/', Some(b'*')) => depth += 1,
+                    (b'*', Some(b'/')) => depth -= 1,
+                    _ => {
+                        j += 1;
+                        continue;
+                    }
+                }
+                j += 2;
+                if depth == 0 {
+                    return Some(j);
+                }
+            }
+            Some(bytes.len())
+        }
+        (b'"', _) => {
+            let mut j = i + 1;
+            while j < bytes.len() && bytes[j] != b'"' {
+                j += if bytes[j] == b'\\' { 2 } else { 1 };
+            }
+            Some((j + 1).min(bytes.len()))
+        }
+        // `r"..."`, `r#"..."#` and their byte string forms, `br"..."`.
+        (b'r', Some(b'"' | b'#')) | (b'b', Some(b'r')) if starts_token(bytes, i) => {
+            let r = i + usize::from(bytes[i] == b'b');
+            let hashes = bytes[r + 1..].iter().take_while(|&&b| b == b'#').count();
+            if bytes.get(r + 1 + hashes) != Some(&b'"') {
+                return None;
+            }
+            let mut closing = vec![b'"'];
+            closing.extend(std::iter::repeat_n(b'#', hashes));
+            Some(find(r + 2 + hashes, &closing))
+        }
+        // A character literal; a lifetime has no closing quote.
+        (b'\'', Some(b'\\')) => Some(find(i + 3, b"'")),
+        (b'\'', Some(first)) => {
+            let width = match first {
+                0x00..=0x7f => 1,
+                0xc0..=0xdf => 2,
+                0xe0..=0xef => 3,
+                _ => 4,
+            };
+            (bytes.get(i + 1 + width) == Some(&b'\'')).then_some(i + 2 + width)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The names at each offset `macro_call_offsets` finds in `text`.
+    fn calls(text: &str) -> Vec<&str> {
+        macro_call_offsets(text, 0..text.len())
+            .into_iter()
+            .map(|offset| {
+                let len = text[offset..]
+                    .bytes()
+                    .take_while(|&byte| is_ident_byte(byte))
+                    .count();
+                &text[offset..offset + len]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_outermost_calls_and_attributes() {
+        let text = "#[derive(Debug)]\nstruct A;\n#![allow(dead_code)]\nfn f() { vec![format!(\"{}\", 1)]; m! { x } }";
+        assert_eq!(calls(text), ["derive", "allow", "vec", "m"]);
+        // Not calls: `!=` and a bare `!`.
+        assert_eq!(calls("fn f() { a != b; !c; }"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn skips_macros_in_strings_and_comments() {
+        let text = r#"// todo!()
+/// println!("doc")
+let s = "dbg!(x) \" format!()";
+let c = b"m!()";
+assert!(ok);"#;
+        assert_eq!(calls(text), ["assert"]);
+    }
+
+    #[test]
+    fn skips_raw_strings() {
+        let text = r##"let a = r#"panic!("x") \"#; let b = br"unreachable!() \"; todo!()"##;
+        assert_eq!(calls(text), ["todo"]);
+        // `r` ending an identifier does not start a raw string.
+        assert_eq!(calls(r#"let bar = "x"; m!()"#), ["m"]);
+        assert_eq!(calls("loop { break; } m!()"), ["m"]);
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        let text = "/* outer /* inner */ println!(\"x\") */ dbg!(1)";
+        assert_eq!(calls(text), ["dbg"]);
+        assert_eq!(calls("/* never closed m!()"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn tells_char_literals_from_lifetimes() {
+        // A quote in a char literal does not open a string...
+        assert_eq!(calls("let q = '\"'; m!(); let e = '\\''; n!()"), ["m", "n"]);
+        // ...and a lifetime has no closing quote to look for.
+        let text = "fn f<'a>(x: &'a str) -> &'a str { m!(x) } fn g() { 'é'; n!() }";
+        assert_eq!(calls(text), ["m", "n"]);
+    }
+
+    #[test]
+    fn only_looks_within_the_range() {
+        let text = "a!(); b!(); c!();";
+        let offsets = macro_call_offsets(text, 6..11);
+        assert_eq!(offsets, [6]);
+    }
+}
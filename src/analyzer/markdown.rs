@@ -0,0 +1,344 @@
+use serde_json::{Map, Value, json};
+
+/// A rust-analyzer hover, split into the parts agents care about.
+#[derive(Debug, Default)]
+pub struct HoverParts {
+    /// The item's module path, e.g. `core::option`.
+    pub path: Option<String>,
+    /// The rendered declaration or the type of the hovered expression.
+    pub signature: Option<String>,
+    /// `size = 24 (0x18), align = 0x8, niches = 1` and similar, parsed.
+    pub memory_layout: Option<Value>,
+    /// The doc comment, still in markdown.
+    pub documentation: Option<String>,
+}
+
+/// Split hover markdown as rust-analyzer renders it: code blocks for the path
+/// and signature, then `---`-separated sections holding the memory layout
+/// and the docs.
+pub fn parse_hover(markdown: &str) -> HoverParts {
+    let mut parts = HoverParts::default();
+    let mut sections = split_sections(markdown).into_iter();
+
+    if let Some(header) = sections.next() {
+        let mut blocks = code_blocks(&header);
+        if blocks.is_empty() {
+            // Not the usual shape; treat everything as prose.
+            parts.documentation = non_empty(header.trim());
+        } else {
+            // Older versions put the layout in the signature block as a
+            // `// size = ...` comment.
+            let last = blocks.len() - 1;
+            let mut signature = Vec::new();
+            for line in blocks[last].lines() {
+                match line.trim().strip_prefix("// ").filter(|l| is_layout(l)) {
+                    Some(layout) => parts.memory_layout = Some(parse_layout(layout)),
+                    None => signature.push(line),
+                }
+            }
+            parts.signature = non_empty(signature.join("\n").trim());
+            if last > 0 {
+                parts.path = non_empty(blocks.swap_remove(0).trim());
+            }
+        }
+    }
+
+    let mut docs = Vec::new();
+    for section in sections {
+        let section = section.trim();
+        if parts.memory_layout.is_none() && !section.contains('\n') && is_layout(section) {
+            parts.memory_layout = Some(parse_layout(section));
+        } else if !section.is_empty() {
+            docs.push(section.to_string());
+        }
+    }
+    if !docs.is_empty() {
+        let docs = docs.join("\n\n");
+        parts.documentation = Some(match parts.documentation.take() {
+            Some(prose) => format!("{prose}\n\n{docs}"),
+            None => docs,
+        });
+    }
+    parts
+}
+
+/// Split on `---`/`___` rules that are not inside a code block.
+fn split_sections(markdown: &str) -> Vec<String> {
+    let mut sections = vec![String::new()];
+    let mut in_code = false;
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_code = !in_code;
+        }
+        if !in_code && (trimmed == "---" || trimmed == "___") {
+            sections.push(String::new());
+            continue;
+        }
+        let current = sections.last_mut().expect("sections is never empty");
+        current.push_str(line);
+        current.push('\n');
+    }
+    sections
+}
+
+/// The contents of each fenced code block.
+fn code_blocks(markdown: &str) -> Vec<String> {
+    let mut blocks = Vec::new();
+    let mut current: Option<Vec<&str>> = None;
+    for line in markdown.lines() {
+        if line.trim().starts_with("```") {
+            match current.take() {
+                Some(lines) => blocks.push(lines.join("\n")),
+                None => current = Some(Vec::new()),
+            }
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    blocks
+}
+
+fn is_layout(line: &str) -> bool {
+    line.starts_with("size = ") || line.starts_with("offset = ")
+}
+
+/// Parse `size = 24 (0x18), align = 0x8, niches = 1, no Drop` into
+/// `{"size": 24, "align": 8, "niches": 1, "notes": ["no Drop"]}`.
+fn parse_layout(line: &str) -> Value {
+    let mut layout = Map::new();
+    let mut notes = Vec::new();
+    for item in line.split(", ") {
+        match item.split_once(" = ") {
+            Some((key, value)) => {
+                let value = parse_number(value)
+                    .map(Value::from)
+                    .unwrap_or_else(|| Value::from(value));
+                layout.insert(key.trim().to_string(), value);
+            }
+            None => notes.push(item.trim().to_string()),
+        }
+    }
+    if !notes.is_empty() {
+        layout.insert("notes".to_string(), json!(notes));
+    }
+    Value::Object(layout)
+}
+
+/// `24`, `0x8` or `24 (0x18)`.
+fn parse_number(value: &str) -> Option<u64> {
+    let first = value.split_whitespace().next()?;
+    match first.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => first.parse().ok(),
+    }
+}
+
+fn non_empty(s: &str) -> Option<String> {
+    (!s.is_empty()).then(|| s.to_string())
+}
+
+/// Reduce markdown to plain text: fences, headings, emphasis and inline code
+/// markers go, links keep only their text, and blank runs collapse.
+pub fn strip_markdown(markdown: &str) -> String {
+    let mut lines = Vec::new();
+    let mut in_code = false;
+    for line in markdown.lines() {
+        if line.trim().starts_with("```") {
+            in_code = !in_code;
+            continue;
+        }
+        if in_code {
+            lines.push(line.to_string());
+            continue;
+        }
+        let line = line.trim_start_matches('#').trim_start();
+        let line = line.strip_prefix("> ").unwrap_or(line);
+        lines.push(strip_inline(line));
+    }
+
+    let mut text = String::new();
+    let mut blank = false;
+    for line in lines {
+        if line.trim().is_empty() {
+            blank = !text.is_empty();
+            continue;
+        }
+        if blank {
+            text.push('\n');
+            blank = false;
+        }
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text.trim_end().to_string()
+}
+
+/// Drop inline markup from one line of prose. Code spans keep their
+/// contents as written, and `*` or `[` that do not start emphasis or a link,
+/// as in `*const T` or `[u8; 4]`, are left alone.
+fn strip_inline(line: &str) -> String {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = String::with_capacity(line.len());
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() && chars[i + 1].is_ascii_punctuation() => {
+                out.push(chars[i + 1]);
+                i += 2;
+            }
+            c @ ('`' | '*') => {
+                let run = chars[i..].iter().take_while(|&&d| d == c).count();
+                let close = match c {
+                    '`' => closing_run(&chars, i + run, c, run),
+                    _ => emphasis_end(&chars, i, run),
+                };
+                match close {
+                    Some(close) => {
+                        let inner: String = chars[i + run..close].iter().collect();
+                        match c {
+                            '`' => out.push_str(&inner),
+                            _ => out.push_str(&strip_inline(&inner)),
+                        }
+                        i = close + run;
+                    }
+                    None => {
+                        out.extend(&chars[i..i + run]);
+                        i += run;
+                    }
+                }
+            }
+            '[' => match link_end(&chars, i) {
+                Some((text_end, end)) => {
+                    let text: String = chars[i + 1..text_end].iter().collect();
+                    out.push_str(&strip_inline(&text));
+                    i = end;
+                }
+                None => {
+                    out.push('[');
+                    i += 1;
+                }
+            },
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// The index of the next run of exactly `len` `c`s at or after `from`.
+fn closing_run(chars: &[char], from: usize, c: char, len: usize) -> Option<usize> {
+    let mut i = from;
+    while i < chars.len() {
+        let run = chars[i..].iter().take_while(|&&d| d == c).count();
+        if run == len {
+            return Some(i);
+        }
+        i += run.max(1);
+    }
+    None
+}
+
+/// For a run of `len` asterisks at `start`, the index of the run closing
+/// the emphasis it opens, if it does. An opener is followed by text and not
+/// inside a word; a closer follows text.
+fn emphasis_end(chars: &[char], start: usize, len: usize) -> Option<usize> {
+    let after = *chars.get(start + len)?;
+    let before = start.checked_sub(1).map(|i| chars[i]);
+    if after.is_whitespace() || before.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    let mut from = start + len;
+    loop {
+        let close = closing_run(chars, from, '*', len)?;
+        if close > start + len && !chars[close - 1].is_whitespace() {
+            return Some(close);
+        }
+        from = close + len;
+    }
+}
+
+/// For a `[` at `start`, the index of its `]` and of the first character
+/// after the link: `[text](url)`, `[text][ref]` or an intra-doc link such
+/// as `[Vec]` or ``[`Option::map`]``.
+fn link_end(chars: &[char], start: usize) -> Option<(usize, usize)> {
+    let close = start + chars[start..].iter().position(|&c| c == ']')?;
+    let closing = match chars.get(close + 1) {
+        Some('(') => ')',
+        Some('[') => ']',
+        _ => {
+            let text = &chars[start + 1..close];
+            let code = text.len() > 2 && text[0] == '`' && text[text.len() - 1] == '`';
+            let path = !text.is_empty()
+                && text
+                    .iter()
+                    .all(|&c| c.is_alphanumeric() || matches!(c, '_' | ':' | '!' | '(' | ')'));
+            return (code || path).then_some((close, close + 1));
+        }
+    };
+    let end = close + 2 + chars[close + 2..].iter().position(|&c| c == closing)?;
+    Some((close, end + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hover_with_path_signature_layout_and_docs() {
+        let markdown = "```rust\ncore::option\n```\n\n```rust\npub enum Option<T>\n```\n\n---\n\nsize = 24 (0x18), align = 0x8, niches = 1, no Drop\n\n---\n\nThe `Option` type.\n\n```rust\nlet x = 1;\n---\n```\n";
+        let parts = parse_hover(markdown);
+        assert_eq!(parts.path.as_deref(), Some("core::option"));
+        assert_eq!(parts.signature.as_deref(), Some("pub enum Option<T>"));
+        assert_eq!(
+            parts.memory_layout,
+            Some(json!({"size": 24, "align": 8, "niches": 1, "notes": ["no Drop"]}))
+        );
+        // A rule inside a code block does not start a section.
+        assert_eq!(
+            parts.documentation.as_deref(),
+            Some("The `Option` type.\n\n```rust\nlet x = 1;\n---\n```")
+        );
+    }
+
+    #[test]
+    fn hover_with_the_layout_in_the_signature() {
+        let parts = parse_hover("```rust\nstruct Point\n// size = 8, align = 0x4\n```");
+        assert_eq!(parts.path, None);
+        assert_eq!(parts.signature.as_deref(), Some("struct Point"));
+        assert_eq!(parts.memory_layout, Some(json!({"size": 8, "align": 4})));
+        assert_eq!(parts.documentation, None);
+    }
+
+    #[test]
+    fn hover_without_code_blocks_is_documentation() {
+        let parts = parse_hover("Just *prose*.");
+        assert_eq!(parts.signature, None);
+        assert_eq!(parts.documentation.as_deref(), Some("Just *prose*."));
+    }
+
+    #[test]
+    fn markup_is_stripped() {
+        let markdown = "# Heading\n\n\n\nSee **bold**, *em* and `Vec<T>` in [the docs](https://x.y/z).\n> quoted [`Option`] and [Vec] and [text][ref]\n\n```rust\nlet a = *b;\n```";
+        assert_eq!(
+            strip_markdown(markdown),
+            "Heading\n\nSee bold, em and Vec<T> in the docs.\nquoted Option and Vec and text\n\nlet a = *b;"
+        );
+    }
+
+    #[test]
+    fn prose_that_only_looks_like_markup_is_kept() {
+        for text in [
+            "A `*const T` or a *const T or *mut T.",
+            "Reborrow with &*x, or compute a * b * c.",
+            "An array [u8; 4] and [1, 2].",
+            "* a list item",
+            "Escaped \\*stars\\* stay.",
+        ] {
+            let expected = text.replace(['`', '\\'], "");
+            assert_eq!(strip_markdown(text), expected, "{text}");
+        }
+    }
+}
@@ -1,5 +1,24 @@
+pub mod call_hierarchy;
 pub mod client;
+pub mod config;
+pub mod diagnostics;
+pub mod documents;
+pub mod encoding;
+pub mod journal;
 pub mod lsp;
+pub mod macros;
+pub mod markdown;
+pub mod pool;
+pub mod progress;
 pub mod protocol;
+pub mod rustfmt;
+pub mod supervisor;
+pub mod timeouts;
+pub mod type_hierarchy;
+pub mod uri;
 
 pub use client::RustAnalyzerClient;
+pub use config::AnalyzerConfig;
+pub use encoding::PositionEncoding;
+pub use pool::AnalyzerPool;
+pub use timeouts::RequestTimeouts;
@@ -0,0 +1,466 @@
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+use tokio::task::JoinSet;
+
+use crate::analyzer::client::RustAnalyzerClient;
+use crate::analyzer::config::AnalyzerConfig;
+use crate::analyzer::encoding::PositionEncoding;
+use crate::analyzer::journal::EditJournal;
+use crate::analyzer::timeouts::RequestTimeouts;
+use crate::analyzer::uri::{check_in_roots, current_dir, resolve_path};
+
+/// Environment variable capping how many rust-analyzer processes run at once.
+pub const MAX_ANALYZERS_ENV_VAR: &str = "RUST_MCP_MAX_ANALYZERS";
+
+const DEFAULT_MAX_ANALYZERS: usize = 3;
+
+/// Tool arguments that name a file or directory, in the order they are
+/// consulted to pick a workspace.
+const PATH_ARGUMENTS: &[&str] = &[
+    "file_path",
+    "workspace_path",
+    "manifest_path",
+    "module_path",
+    "source_file",
+    "target_file",
+];
+
+/// Read the instance cap from `RUST_MCP_MAX_ANALYZERS`, if set.
+pub fn max_analyzers_from_env() -> Result<usize> {
+    match std::env::var(MAX_ANALYZERS_ENV_VAR) {
+        Ok(raw) => match raw.trim().parse::<usize>() {
+            Ok(0) | Err(_) => Err(anyhow::anyhow!(
+                "{MAX_ANALYZERS_ENV_VAR} must be a positive integer, got {raw:?}"
+            )),
+            Ok(max) => Ok(max),
+        },
+        Err(_) => Ok(DEFAULT_MAX_ANALYZERS),
+    }
+}
+
+/// The workspace `path` belongs to: the nearest enclosing directory whose
+/// `Cargo.toml` declares a `[workspace]`, or failing that the nearest one
+/// with any `Cargo.toml`.
+pub fn find_workspace_root(path: &Path) -> Option<PathBuf> {
+    let start = if path.is_dir() { path } else { path.parent()? };
+    let mut nearest = None;
+    for dir in start.ancestors() {
+        let Ok(manifest) = std::fs::read_to_string(dir.join("Cargo.toml")) else {
+            continue;
+        };
+        if is_workspace_manifest(&manifest) {
+            return Some(dir.to_path_buf());
+        }
+        nearest.get_or_insert_with(|| dir.to_path_buf());
+    }
+    nearest
+}
+
+/// The workspace for `path`, or the directory itself outside any workspace.
+fn workspace_of(path: &Path) -> PathBuf {
+    find_workspace_root(path).unwrap_or_else(|| {
+        if path.is_dir() {
+            path.to_path_buf()
+        } else {
+            path.parent()
+                .map(Path::to_path_buf)
+                .unwrap_or(path.to_path_buf())
+        }
+    })
+}
+
+fn is_workspace_manifest(manifest: &str) -> bool {
+    manifest
+        .parse::<toml::Table>()
+        .is_ok_and(|manifest| manifest.contains_key("workspace"))
+}
+
+struct Instance {
+    client: Arc<RustAnalyzerClient>,
+    /// Initialized once `start` has succeeded; concurrent first users wait
+    /// on the same start.
+    started: Arc<OnceCell<()>>,
+    last_used: Instant,
+}
+
+impl Instance {
+    /// Nobody outside the pool is using the client right now.
+    fn is_idle(&self) -> bool {
+        Arc::strong_count(&self.client) == 1
+    }
+}
+
+/// One rust-analyzer per workspace root, started on first use.
+///
+/// When starting another would exceed the cap, the least recently used idle
+/// instance is shut down to make room.
+pub struct AnalyzerPool {
+    config: StdMutex<AnalyzerConfig>,
+    timeouts: StdMutex<RequestTimeouts>,
+    column_encoding: StdMutex<PositionEncoding>,
+    max_instances: StdMutex<usize>,
+    /// The working directory; stands in for the roots when the client has
+    /// not shared any.
+    cwd: PathBuf,
+    /// Directories the MCP client has shared through `roots/list`. Once
+    /// known, tools may only touch paths inside them.
+    roots: StdMutex<Option<Vec<PathBuf>>>,
+    /// Edits made through any analyzer, for undo.
+    journal: Arc<EditJournal>,
+    instances: StdMutex<HashMap<PathBuf, Instance>>,
+}
+
+impl AnalyzerPool {
+    pub fn new(config: AnalyzerConfig) -> Self {
+        Self {
+            config: StdMutex::new(config),
+            timeouts: StdMutex::new(RequestTimeouts::default()),
+            column_encoding: StdMutex::new(PositionEncoding::Utf8),
+            max_instances: StdMutex::new(DEFAULT_MAX_ANALYZERS),
+            cwd: current_dir(),
+            roots: StdMutex::new(None),
+            journal: Arc::new(EditJournal::default()),
+            instances: StdMutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn set_request_timeouts(&self, timeouts: RequestTimeouts) {
+        for instance in self.instances.lock().unwrap().values() {
+            instance.client.set_request_timeouts(timeouts.clone());
+        }
+        *self.timeouts.lock().unwrap() = timeouts;
+    }
+
+    pub fn set_column_encoding(&self, encoding: PositionEncoding) {
+        for instance in self.instances.lock().unwrap().values() {
+            instance.client.set_column_encoding(encoding);
+        }
+        *self.column_encoding.lock().unwrap() = encoding;
+    }
+
+    /// Change how many rust-analyzer processes may run at once. Takes effect
+    /// the next time one is started.
+    pub fn set_max_instances(&self, max: usize) {
+        *self.max_instances.lock().unwrap() = max.max(1);
+    }
+
+    /// Start rust-analyzer for the current directory's workspace, if it is
+    /// in one. Everything else starts on first use.
+    pub async fn start(&self) -> Result<()> {
+        if let Some(root) = find_workspace_root(&self.cwd) {
+            self.for_root(root).await?;
+        }
+        Ok(())
+    }
+
+    /// The roots the MCP client shared, if it has.
+    pub fn roots(&self) -> Option<Vec<PathBuf>> {
+        self.roots.lock().unwrap().clone()
+    }
+
+    /// Adopt the MCP client's roots: shut down analyzers for workspaces
+    /// unrelated to any root and start one for each root's workspace, as far
+    /// as the instance cap allows. An empty list means the client declared
+    /// no roots, so paths are no longer restricted.
+    pub async fn set_roots(&self, roots: Vec<PathBuf>) {
+        if roots.is_empty() {
+            *self.roots.lock().unwrap() = None;
+            for instance in self.instances.lock().unwrap().values() {
+                instance.client.set_roots(None);
+            }
+            return;
+        }
+        let roots: Vec<PathBuf> = roots
+            .into_iter()
+            .map(|root| root.canonicalize().unwrap_or(root))
+            .collect();
+        *self.roots.lock().unwrap() = Some(roots.clone());
+        for instance in self.instances.lock().unwrap().values() {
+            instance.client.set_roots(Some(roots.clone()));
+        }
+
+        let related = |workspace: &Path| {
+            roots
+                .iter()
+                .any(|root| workspace.starts_with(root) || root.starts_with(workspace))
+        };
+        let stale: Vec<_> = {
+            let mut instances = self.instances.lock().unwrap();
+            let stale_roots: Vec<PathBuf> = instances
+                .keys()
+                .filter(|workspace| !related(workspace))
+                .cloned()
+                .collect();
+            stale_roots
+                .iter()
+                .filter_map(|workspace| instances.remove(workspace))
+                .map(|instance| instance.client)
+                .collect()
+        };
+        for client in stale {
+            eprintln!(
+                "Shutting down rust-analyzer for {}: no longer under a workspace root",
+                client.workspace_root().display()
+            );
+            client.shutdown().await;
+        }
+
+        let mut workspaces: Vec<PathBuf> = roots.iter().map(|root| workspace_of(root)).collect();
+        // Roots sharing a workspace need one instance between them.
+        let mut seen = HashSet::new();
+        workspaces.retain(|workspace| seen.insert(workspace.clone()));
+        let max = *self.max_instances.lock().unwrap();
+        for workspace in workspaces.into_iter().take(max) {
+            if let Err(e) = self.for_root(workspace).await {
+                eprintln!("{e:#}");
+            }
+        }
+    }
+
+    /// The edits made this session.
+    pub fn journal(&self) -> &EditJournal {
+        &self.journal
+    }
+
+    /// Where relative paths are resolved from: the first root, or the
+    /// working directory.
+    fn base(&self) -> PathBuf {
+        self.roots
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|roots| roots.first().cloned())
+            .unwrap_or_else(|| self.cwd.clone())
+    }
+
+    /// Resolve a path from a tool call, refusing paths outside the client's
+    /// roots.
+    pub fn resolve_path(&self, file_path: &str) -> Result<PathBuf> {
+        let path = resolve_path(file_path, &self.base());
+        if let Some(roots) = self.roots.lock().unwrap().as_ref() {
+            check_in_roots(&path, roots)?;
+        }
+        Ok(path)
+    }
+
+    /// Replace every path argument with its absolute form, checking each is
+    /// allowed.
+    pub fn prepare_args(&self, args: &mut Value) -> Result<()> {
+        for key in PATH_ARGUMENTS {
+            if let Some(path) = args.get(*key).and_then(|v| v.as_str()) {
+                let resolved = self.resolve_path(path)?;
+                args[*key] = Value::String(resolved.display().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// The workspace a path from a tool call belongs to.
+    pub fn workspace_for(&self, file_path: &str) -> PathBuf {
+        workspace_of(&resolve_path(file_path, &self.base()))
+    }
+
+    /// The analyzer for the workspace named by a tool's arguments.
+    pub async fn for_args(&self, args: &Value) -> Result<Arc<RustAnalyzerClient>> {
+        let path = PATH_ARGUMENTS
+            .iter()
+            .find_map(|key| args.get(*key).and_then(|v| v.as_str()));
+        self.for_path(path).await
+    }
+
+    /// The analyzer for the workspace containing `file_path`. Without a path,
+    /// the most recently used analyzer, or the current directory's.
+    pub async fn for_path(&self, file_path: Option<&str>) -> Result<Arc<RustAnalyzerClient>> {
+        let root = match file_path {
+            Some(file_path) => self.workspace_for(file_path),
+            None => self.default_root(),
+        };
+        self.for_root(root).await
+    }
+
+    fn default_root(&self) -> PathBuf {
+        let instances = self.instances.lock().unwrap();
+        instances
+            .iter()
+            .max_by_key(|(_, instance)| instance.last_used)
+            .map(|(root, _)| root.clone())
+            .unwrap_or_else(|| workspace_of(&self.base()))
+    }
+
+    /// The analyzer for `root`, started if need be.
+    async fn for_root(&self, root: PathBuf) -> Result<Arc<RustAnalyzerClient>> {
+        let (client, started, evicted) = self.checkout(root)?;
+
+        if let Some(evicted) = evicted {
+            eprintln!(
+                "Shutting down rust-analyzer for {} to stay under the instance cap",
+                evicted.workspace_root().display()
+            );
+            tokio::spawn(async move { evicted.shutdown().await });
+        }
+
+        started
+            .get_or_try_init(|| client.start())
+            .await
+            .with_context(|| {
+                format!(
+                    "Could not start rust-analyzer for {}",
+                    client.workspace_root().display()
+                )
+            })?;
+        Ok(client)
+    }
+
+    /// Find or create the instance for `root`, evicting another if the pool
+    /// is full.
+    #[allow(clippy::type_complexity)]
+    fn checkout(
+        &self,
+        root: PathBuf,
+    ) -> Result<(
+        Arc<RustAnalyzerClient>,
+        Arc<OnceCell<()>>,
+        Option<Arc<RustAnalyzerClient>>,
+    )> {
+        let mut instances = self.instances.lock().unwrap();
+        if let Some(instance) = instances.get_mut(&root) {
+            instance.last_used = Instant::now();
+            return Ok((instance.client.clone(), instance.started.clone(), None));
+        }
+
+        let max = *self.max_instances.lock().unwrap();
+        let mut evicted = None;
+        if instances.len() >= max {
+            let victim = instances
+                .iter()
+                .filter(|(_, instance)| instance.is_idle())
+                .min_by_key(|(_, instance)| instance.last_used)
+                .map(|(root, _)| root.clone())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "All {max} rust-analyzer instances are busy; try again shortly or raise {MAX_ANALYZERS_ENV_VAR}"
+                    )
+                })?;
+            evicted = instances.remove(&victim).map(|instance| instance.client);
+        }
+
+        let client =
+            RustAnalyzerClient::with_root(self.config.lock().unwrap().clone(), root.clone());
+        client.set_request_timeouts(self.timeouts.lock().unwrap().clone());
+        client.set_column_encoding(*self.column_encoding.lock().unwrap());
+        client.set_roots(self.roots());
+        client.set_journal(self.journal.clone());
+        let instance = Instance {
+            client: Arc::new(client),
+            started: Arc::new(OnceCell::new()),
+            last_used: Instant::now(),
+        };
+        let checked_out = (instance.client.clone(), instance.started.clone(), evicted);
+        instances.insert(root, instance);
+        Ok(checked_out)
+    }
+
+    /// Every running analyzer, ordered by workspace.
+    fn clients(&self) -> Vec<Arc<RustAnalyzerClient>> {
+        let instances = self.instances.lock().unwrap();
+        let mut clients: Vec<_> = instances.values().map(|i| i.client.clone()).collect();
+        clients.sort_by(|a, b| a.workspace_root().cmp(b.workspace_root()));
+        clients
+    }
+
+    /// Status of the analyzer for `workspace_path`, or of all of them.
+    /// Never starts an analyzer.
+    pub async fn server_status(
+        &self,
+        workspace_path: Option<&str>,
+        wait: Option<Duration>,
+    ) -> Result<String> {
+        let clients = match workspace_path {
+            Some(path) => {
+                let root = self.workspace_for(path);
+                let client = self
+                    .instances
+                    .lock()
+                    .unwrap()
+                    .get(&root)
+                    .map(|instance| instance.client.clone());
+                match client {
+                    Some(client) => vec![client],
+                    None => {
+                        return Ok(serde_json::to_string_pretty(&json!({
+                            "workspace": root.display().to_string(),
+                            "state": "not started"
+                        }))?);
+                    }
+                }
+            }
+            None => self.clients(),
+        };
+
+        let mut instances = Vec::new();
+        for client in clients {
+            instances.push(client.status_report(wait).await);
+        }
+        let report = match workspace_path {
+            Some(_) => instances.pop().unwrap_or(Value::Null),
+            None => json!({
+                "roots": self.roots(),
+                "max_instances": *self.max_instances.lock().unwrap(),
+                "instances": instances
+            }),
+        };
+        Ok(serde_json::to_string_pretty(&report)?)
+    }
+
+    /// Restart the analyzer for `workspace_path`, or every running one.
+    pub async fn restart(&self, workspace_path: Option<&str>) -> Result<String> {
+        if let Some(path) = workspace_path {
+            let root = self.workspace_for(path);
+            let running = self.instances.lock().unwrap().contains_key(&root);
+            let client = self.for_root(root).await?;
+            if !running {
+                return Ok(format!(
+                    "rust-analyzer started for {}",
+                    client.workspace_root().display()
+                ));
+            }
+            return client.restart().await;
+        }
+
+        let clients = self.clients();
+        if clients.is_empty() {
+            return Ok("No rust-analyzer instances are running".to_string());
+        }
+        let mut results = Vec::new();
+        for client in clients {
+            let result = match client.restart().await {
+                Ok(result) => result,
+                Err(e) => format!("restart failed: {e}"),
+            };
+            results.push(format!("{}: {result}", client.workspace_root().display()));
+        }
+        Ok(results.join("\n"))
+    }
+
+    /// Shut every analyzer down, in parallel.
+    pub async fn shutdown(&self) {
+        let clients: Vec<_> = self
+            .instances
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, instance)| instance.client)
+            .collect();
+
+        let mut shutdowns = JoinSet::new();
+        for client in clients {
+            shutdowns.spawn(async move { client.shutdown().await });
+        }
+        while shutdowns.join_next().await.is_some() {}
+    }
+}
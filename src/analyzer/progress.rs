@@ -0,0 +1,144 @@
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// One `$/progress` token that has begun but not yet ended.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEntry {
+    pub title: String,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+}
+
+/// What rust-analyzer has told us about its own state.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct IndexingStatus {
+    /// `ok`, `warning` or `error` from `experimental/serverStatus`.
+    pub health: Option<String>,
+    /// Whether rust-analyzer reports having no pending work.
+    pub quiescent: Option<bool>,
+    pub message: Option<String>,
+    /// Active work done progress, keyed by token.
+    pub progress: BTreeMap<String, ProgressEntry>,
+    #[serde(skip)]
+    progress_seen: bool,
+}
+
+impl IndexingStatus {
+    /// Whether indexing has finished.
+    ///
+    /// `experimental/serverStatus` is authoritative when the server sends it.
+    /// Otherwise we consider the server ready once some progress has been
+    /// reported and all of it has ended.
+    pub fn is_ready(&self) -> bool {
+        self.quiescent
+            .unwrap_or(self.progress_seen && self.progress.is_empty())
+    }
+
+    /// The least complete active task's percentage, if any reports one.
+    pub fn percentage(&self) -> Option<u32> {
+        self.progress.values().filter_map(|p| p.percentage).min()
+    }
+}
+
+/// Tracks `$/progress` and `experimental/serverStatus` notifications and lets
+/// callers wait for indexing to finish.
+pub struct ProgressTracker {
+    status: watch::Sender<IndexingStatus>,
+}
+
+impl Default for ProgressTracker {
+    fn default() -> Self {
+        Self {
+            status: watch::Sender::new(IndexingStatus::default()),
+        }
+    }
+}
+
+impl ProgressTracker {
+    pub fn snapshot(&self) -> IndexingStatus {
+        self.status.borrow().clone()
+    }
+
+    /// Forget everything, e.g. because the server was restarted.
+    pub fn reset(&self) {
+        self.status.send_replace(IndexingStatus::default());
+    }
+
+    pub fn handle_progress(&self, params: &Value) {
+        let Some(token) = params.get("token") else {
+            return;
+        };
+        let token = match token.as_str() {
+            Some(token) => token.to_string(),
+            None => token.to_string(),
+        };
+        let value = params.get("value").unwrap_or(&Value::Null);
+        let message = value
+            .get("message")
+            .and_then(|m| m.as_str())
+            .map(str::to_string);
+        let percentage = value
+            .get("percentage")
+            .and_then(|p| p.as_u64())
+            .map(|p| p as u32);
+
+        self.status
+            .send_modify(|status| match value.get("kind").and_then(|k| k.as_str()) {
+                Some("begin") => {
+                    status.progress_seen = true;
+                    status.progress.insert(
+                        token,
+                        ProgressEntry {
+                            title: value
+                                .get("title")
+                                .and_then(|t| t.as_str())
+                                .unwrap_or_default()
+                                .to_string(),
+                            message,
+                            percentage,
+                        },
+                    );
+                }
+                Some("report") => {
+                    if let Some(entry) = status.progress.get_mut(&token) {
+                        if message.is_some() {
+                            entry.message = message;
+                        }
+                        if percentage.is_some() {
+                            entry.percentage = percentage;
+                        }
+                    }
+                }
+                Some("end") => {
+                    status.progress.remove(&token);
+                }
+                _ => {}
+            });
+    }
+
+    pub fn handle_server_status(&self, params: &Value) {
+        self.status.send_modify(|status| {
+            status.health = params
+                .get("health")
+                .and_then(|h| h.as_str())
+                .map(str::to_string);
+            status.quiescent = params.get("quiescent").and_then(|q| q.as_bool());
+            status.message = params
+                .get("message")
+                .and_then(|m| m.as_str())
+                .map(str::to_string);
+        });
+    }
+
+    /// Wait until indexing has finished. Returns `false` on timeout.
+    pub async fn wait_until_ready(&self, timeout: Duration) -> bool {
+        let mut status = self.status.subscribe();
+        matches!(
+            tokio::time::timeout(timeout, status.wait_for(IndexingStatus::is_ready)).await,
+            Ok(Ok(_))
+        )
+    }
+}
@@ -1,59 +1,1267 @@
-use serde_json::{Value, json};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
 
-pub fn create_text_document_position_params(file_path: &str, line: u32, character: u32) -> Value {
-    json!({
-        "textDocument": {
-            "uri": format!("file://{}", file_path)
-        },
-        "position": {
-            "line": line,
-            "character": character
+/// An LSP request: its method name and the types it is sent and answered
+/// with.
+pub trait Request {
+    const METHOD: &'static str;
+    type Params: Serialize;
+    type Result: DeserializeOwned;
+}
+
+pub enum GotoDefinition {}
+
+impl Request for GotoDefinition {
+    const METHOD: &'static str = "textDocument/definition";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<GotoDefinitionResponse>;
+}
+
+pub enum GotoImplementation {}
+
+impl Request for GotoImplementation {
+    const METHOD: &'static str = "textDocument/implementation";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<GotoDefinitionResponse>;
+}
+
+pub enum GotoTypeDefinition {}
+
+impl Request for GotoTypeDefinition {
+    const METHOD: &'static str = "textDocument/typeDefinition";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<GotoDefinitionResponse>;
+}
+
+pub enum GotoDeclaration {}
+
+impl Request for GotoDeclaration {
+    const METHOD: &'static str = "textDocument/declaration";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<GotoDefinitionResponse>;
+}
+
+pub enum References {}
+
+impl Request for References {
+    const METHOD: &'static str = "textDocument/references";
+    type Params = ReferenceParams;
+    type Result = Option<Vec<Location>>;
+}
+
+pub enum HoverRequest {}
+
+impl Request for HoverRequest {
+    const METHOD: &'static str = "textDocument/hover";
+    type Params = HoverParams;
+    type Result = Option<Hover>;
+}
+
+pub enum WorkspaceSymbolRequest {}
+
+impl Request for WorkspaceSymbolRequest {
+    const METHOD: &'static str = "workspace/symbol";
+    type Params = WorkspaceSymbolParams;
+    type Result = Option<Vec<SymbolInformation>>;
+}
+
+pub enum DocumentSymbolRequest {}
+
+impl Request for DocumentSymbolRequest {
+    const METHOD: &'static str = "textDocument/documentSymbol";
+    type Params = DocumentSymbolParams;
+    type Result = Option<DocumentSymbolResponse>;
+}
+
+pub enum PrepareCallHierarchy {}
+
+impl Request for PrepareCallHierarchy {
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<CallHierarchyItem>>;
+}
+
+pub enum IncomingCalls {}
+
+impl Request for IncomingCalls {
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+}
+
+pub enum OutgoingCalls {}
+
+impl Request for OutgoingCalls {
+    const METHOD: &'static str = "callHierarchy/outgoingCalls";
+    type Params = CallHierarchyCallsParams;
+    type Result = Option<Vec<CallHierarchyOutgoingCall>>;
+}
+
+pub enum PrepareTypeHierarchy {}
+
+impl Request for PrepareTypeHierarchy {
+    const METHOD: &'static str = "textDocument/prepareTypeHierarchy";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+}
+
+pub enum Supertypes {}
+
+impl Request for Supertypes {
+    const METHOD: &'static str = "typeHierarchy/supertypes";
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+}
+
+pub enum Rename {}
+
+impl Request for Rename {
+    const METHOD: &'static str = "textDocument/rename";
+    type Params = RenameParams;
+    type Result = Option<WorkspaceEdit>;
+}
+
+pub enum Formatting {}
+
+impl Request for Formatting {
+    const METHOD: &'static str = "textDocument/formatting";
+    type Params = DocumentFormattingParams;
+    type Result = Option<Vec<TextEdit>>;
+}
+
+pub enum CodeActionRequest {}
+
+impl Request for CodeActionRequest {
+    const METHOD: &'static str = "textDocument/codeAction";
+    type Params = CodeActionParams;
+    type Result = Option<Vec<CodeActionOrCommand>>;
+}
+
+/// Fill in the `edit` of an action that was listed without one.
+pub enum ResolveCodeAction {}
+
+impl Request for ResolveCodeAction {
+    const METHOD: &'static str = "codeAction/resolve";
+    type Params = CodeAction;
+    type Result = CodeAction;
+}
+
+pub enum SignatureHelpRequest {}
+
+impl Request for SignatureHelpRequest {
+    const METHOD: &'static str = "textDocument/signatureHelp";
+    type Params = SignatureHelpParams;
+    type Result = Option<SignatureHelp>;
+}
+
+pub enum Completion {}
+
+impl Request for Completion {
+    const METHOD: &'static str = "textDocument/completion";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<CompletionResponse>;
+}
+
+pub enum ResolveCompletionItem {}
+
+impl Request for ResolveCompletionItem {
+    const METHOD: &'static str = "completionItem/resolve";
+    type Params = CompletionItem;
+    type Result = CompletionItem;
+}
+
+/// rust-analyzer's extension expanding the macro call, attribute macro or
+/// derive at a position.
+pub enum ExpandMacro {}
+
+impl Request for ExpandMacro {
+    const METHOD: &'static str = "rust-analyzer/expandMacro";
+    type Params = TextDocumentPositionParams;
+    type Result = Option<ExpandedMacro>;
+}
+
+/// Decode a request's result, saying which method produced something
+/// unexpected.
+pub fn decode_result<R: Request>(result: Value) -> anyhow::Result<R::Result> {
+    R::Result::deserialize(&result).map_err(|e| {
+        let mut raw = result.to_string();
+        if raw.len() > 200 {
+            let mut cut = 200;
+            while !raw.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            raw.truncate(cut);
+            raw.push_str("...");
         }
+        anyhow::anyhow!(
+            "Could not decode {} response from rust-analyzer: {e} (got {raw})",
+            R::METHOD
+        )
     })
 }
 
-pub fn create_references_params(file_path: &str, line: u32, character: u32) -> Value {
-    json!({
-        "textDocument": {
-            "uri": format!("file://{}", file_path)
+pub fn create_text_document_position_params(
+    uri: &str,
+    position: Position,
+) -> TextDocumentPositionParams {
+    TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier::new(uri),
+        position,
+    }
+}
+
+pub fn create_hover_params(uri: &str, position: PositionOrRange) -> HoverParams {
+    HoverParams {
+        text_document: TextDocumentIdentifier::new(uri),
+        position,
+    }
+}
+
+pub fn create_signature_help_params(uri: &str, position: Position) -> SignatureHelpParams {
+    SignatureHelpParams {
+        position: create_text_document_position_params(uri, position),
+    }
+}
+
+pub fn create_references_params(uri: &str, position: Position) -> ReferenceParams {
+    ReferenceParams {
+        position: create_text_document_position_params(uri, position),
+        context: ReferenceContext {
+            include_declaration: true,
         },
-        "position": {
-            "line": line,
-            "character": character
+    }
+}
+
+pub fn create_workspace_symbol_params(query: &str) -> WorkspaceSymbolParams {
+    WorkspaceSymbolParams {
+        query: query.to_string(),
+    }
+}
+
+pub fn create_document_symbol_params(uri: &str) -> DocumentSymbolParams {
+    DocumentSymbolParams {
+        text_document: TextDocumentIdentifier::new(uri),
+    }
+}
+
+pub fn create_rename_params(uri: &str, position: Position, new_name: &str) -> RenameParams {
+    RenameParams {
+        position: create_text_document_position_params(uri, position),
+        new_name: new_name.to_string(),
+    }
+}
+
+pub fn create_formatting_params(uri: &str, options: FormattingOptions) -> DocumentFormattingParams {
+    DocumentFormattingParams {
+        text_document: TextDocumentIdentifier::new(uri),
+        options,
+    }
+}
+
+/// `only` limits the result to actions of those kinds or their
+/// sub-kinds, e.g. `refactor` also matches `refactor.extract`.
+pub fn create_code_action_params(
+    uri: &str,
+    range: &Range,
+    diagnostics: &[Diagnostic],
+    only: Option<&[&str]>,
+) -> CodeActionParams {
+    CodeActionParams {
+        text_document: TextDocumentIdentifier::new(uri),
+        range: *range,
+        context: CodeActionContext {
+            diagnostics: diagnostics.to_vec(),
+            only: only.map(|kinds| kinds.iter().map(|kind| kind.to_string()).collect()),
         },
-        "context": {
-            "includeDeclaration": true
+    }
+}
+
+/// Ordered by line, then column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct Position {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub uri: String,
+    pub range: Range,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LocationLink {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_selection_range: Option<Range>,
+    pub target_uri: String,
+    /// The whole target item, e.g. a function including its body.
+    pub target_range: Range,
+    /// The part of the target to highlight, e.g. the function's name.
+    pub target_selection_range: Range,
+}
+
+impl From<LocationLink> for Location {
+    fn from(link: LocationLink) -> Self {
+        Self {
+            uri: link.target_uri,
+            range: link.target_selection_range,
         }
-    })
+    }
 }
 
-pub fn create_workspace_symbol_params(query: &str) -> Value {
-    json!({
-        "query": query
-    })
+/// `textDocument/definition` may answer with one location, several, or
+/// location links depending on what the server feels like.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum GotoDefinitionResponse {
+    Scalar(Location),
+    Array(Vec<Location>),
+    Link(Vec<LocationLink>),
 }
 
-pub fn create_rename_params(file_path: &str, line: u32, character: u32, new_name: &str) -> Value {
-    json!({
-        "textDocument": {
-            "uri": format!("file://{}", file_path)
-        },
-        "position": {
-            "line": line,
-            "character": character
-        },
-        "newName": new_name
-    })
+impl GotoDefinitionResponse {
+    /// Links for every target. Plain locations become links whose full and
+    /// selection ranges are the same.
+    pub fn into_links(self) -> Vec<LocationLink> {
+        let link = |location: Location| LocationLink {
+            origin_selection_range: None,
+            target_uri: location.uri,
+            target_range: location.range,
+            target_selection_range: location.range,
+        };
+        match self {
+            Self::Scalar(location) => vec![link(location)],
+            Self::Array(locations) => locations.into_iter().map(link).collect(),
+            Self::Link(links) => links,
+        }
+    }
+
+    pub fn into_locations(self) -> Vec<Location> {
+        match self {
+            Self::Scalar(location) => vec![location],
+            Self::Array(locations) => locations,
+            Self::Link(links) => links.into_iter().map(Location::from).collect(),
+        }
+    }
 }
 
-pub fn create_formatting_params(file_path: &str) -> Value {
-    json!({
-        "textDocument": {
-            "uri": format!("file://{}", file_path)
-        },
-        "options": {
-            "tabSize": 4,
-            "insertSpaces": true
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextDocumentIdentifier {
+    pub uri: String,
+}
+
+impl TextDocumentIdentifier {
+    pub fn new(uri: &str) -> Self {
+        Self {
+            uri: uri.to_string(),
         }
-    })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentPositionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+/// rust-analyzer accepts a range in place of the hover position (its
+/// `hoverRange` extension) and then describes the selected expression.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PositionOrRange {
+    Position(Position),
+    Range(Range),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HoverParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: PositionOrRange,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceContext {
+    pub include_declaration: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReferenceParams {
+    #[serde(flatten)]
+    pub position: TextDocumentPositionParams,
+    pub context: ReferenceContext,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureHelpParams {
+    #[serde(flatten)]
+    pub position: TextDocumentPositionParams,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkspaceSymbolParams {
+    pub query: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenameParams {
+    #[serde(flatten)]
+    pub position: TextDocumentPositionParams,
+    pub new_name: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FormattingOptions {
+    pub tab_size: u32,
+    pub insert_spaces: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentFormattingParams {
+    pub text_document: TextDocumentIdentifier,
+    pub options: FormattingOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeActionContext {
+    pub diagnostics: Vec<Diagnostic>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub only: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeActionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+    pub context: CodeActionContext,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextEdit {
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// A document identifier with the version an edit was computed against.
+/// `None` means the edit applies to whatever is on disk.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OptionalVersionedTextDocumentIdentifier {
+    pub uri: String,
+    #[serde(default)]
+    pub version: Option<i32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TextDocumentEdit {
+    pub text_document: OptionalVersionedTextDocumentIdentifier,
+    pub edits: Vec<TextEdit>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FileOperationOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overwrite: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_if_exists: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ignore_if_not_exists: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recursive: Option<bool>,
+}
+
+/// File creation, rename or deletion inside a workspace edit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ResourceOp {
+    Create {
+        uri: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        options: Option<FileOperationOptions>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Rename {
+        old_uri: String,
+        new_uri: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        options: Option<FileOperationOptions>,
+    },
+    Delete {
+        uri: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        options: Option<FileOperationOptions>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentChange {
+    Edit(TextDocumentEdit),
+    Operation(ResourceOp),
+}
+
+/// Changes to many documents at once, as returned by rename and code
+/// actions. Servers use either `changes` or `documentChanges`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceEdit {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<HashMap<String, Vec<TextEdit>>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document_changes: Option<Vec<DocumentChange>>,
+}
+
+impl WorkspaceEdit {
+    /// Every change in the order it should be applied, with `changes`
+    /// folded into unversioned text document edits.
+    pub fn into_document_changes(self) -> Vec<DocumentChange> {
+        if let Some(document_changes) = self.document_changes {
+            return document_changes;
+        }
+        let mut changes: Vec<_> = self.changes.unwrap_or_default().into_iter().collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        changes
+            .into_iter()
+            .map(|(uri, edits)| {
+                DocumentChange::Edit(TextDocumentEdit {
+                    text_document: OptionalVersionedTextDocumentIdentifier { uri, version: None },
+                    edits,
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Command {
+    pub title: String,
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub arguments: Option<Vec<Value>>,
+}
+
+/// A macro expanded recursively, formatted by rust-analyzer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpandedMacro {
+    pub name: String,
+    pub expansion: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CodeAction {
+    pub title: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diagnostics: Option<Vec<Diagnostic>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_preferred: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edit: Option<WorkspaceEdit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command: Option<Command>,
+    /// Opaque to us; sent back with `codeAction/resolve`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// `Command` is tried first: a code action's `command` is an object, never
+/// the string a bare command has.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CodeActionOrCommand {
+    Command(Command),
+    CodeAction(CodeAction),
+}
+
+/// LSP symbol kind. Kept as a number so kinds added by newer servers still
+/// decode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SymbolKind(pub u8);
+
+impl SymbolKind {
+    const NAMES: [&'static str; 26] = [
+        "file",
+        "module",
+        "namespace",
+        "package",
+        "class",
+        "method",
+        "property",
+        "field",
+        "constructor",
+        "enum",
+        "interface",
+        "function",
+        "variable",
+        "constant",
+        "string",
+        "number",
+        "boolean",
+        "array",
+        "object",
+        "key",
+        "null",
+        "enum_member",
+        "struct",
+        "event",
+        "operator",
+        "type_parameter",
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        Self::NAMES
+            .get(usize::from(self.0).wrapping_sub(1))
+            .copied()
+            .unwrap_or("unknown")
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SymbolInformation {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+    pub location: Location,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSymbolParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentSymbol {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub kind: SymbolKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+    /// The whole item, including its body and doc comments.
+    pub range: Range,
+    /// The item's name.
+    pub selection_range: Range,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub children: Option<Vec<DocumentSymbol>>,
+}
+
+/// Servers without hierarchical support answer with a flat list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DocumentSymbolResponse {
+    Nested(Vec<DocumentSymbol>),
+    Flat(Vec<SymbolInformation>),
+}
+
+impl DocumentSymbolResponse {
+    /// The symbols as a tree; a flat answer becomes a list of leaves.
+    pub fn into_nested(self) -> Vec<DocumentSymbol> {
+        match self {
+            Self::Nested(symbols) => symbols,
+            Self::Flat(symbols) => symbols
+                .into_iter()
+                .map(|symbol| DocumentSymbol {
+                    name: symbol.name,
+                    detail: symbol.container_name,
+                    kind: symbol.kind,
+                    deprecated: symbol.deprecated,
+                    range: symbol.location.range,
+                    selection_range: symbol.location.range,
+                    children: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A function in a call hierarchy. `data` must be handed back unchanged
+/// when asking for its calls.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: String,
+    pub range: Range,
+    pub selection_range: Range,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+/// Type hierarchy items have the same shape as call hierarchy ones.
+pub type TypeHierarchyItem = CallHierarchyItem;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypeHierarchyParams {
+    pub item: TypeHierarchyItem,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CallHierarchyCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+/// A caller of the item, with the call sites inside the caller.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    pub from: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+/// A callee of the item, with the call sites inside the item.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    pub to: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarkupContent {
+    /// `plaintext` or `markdown`.
+    pub kind: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MarkedString {
+    String(String),
+    LanguageString { language: String, value: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HoverContents {
+    Markup(MarkupContent),
+    Scalar(MarkedString),
+    Array(Vec<MarkedString>),
+}
+
+impl HoverContents {
+    /// The contents as a single markdown string.
+    pub fn to_markdown(&self) -> String {
+        fn marked(s: &MarkedString) -> String {
+            match s {
+                MarkedString::String(text) => text.clone(),
+                MarkedString::LanguageString { language, value } => {
+                    format!("```{language}\n{value}\n```")
+                }
+            }
+        }
+        match self {
+            Self::Markup(markup) => markup.value.clone(),
+            Self::Scalar(s) => marked(s),
+            Self::Array(items) => items.iter().map(marked).collect::<Vec<_>>().join("\n\n"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Hover {
+    pub contents: HoverContents,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<Range>,
+}
+
+/// Documentation on a completion item or signature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Documentation {
+    String(String),
+    Markup(MarkupContent),
+}
+
+impl Documentation {
+    pub fn value(&self) -> &str {
+        match self {
+            Self::String(text) => text,
+            Self::Markup(markup) => &markup.value,
+        }
+    }
+}
+
+/// A parameter's place in its signature's label: the text itself, or
+/// UTF-16 offsets into the label.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ParameterLabel {
+    Simple(String),
+    Offsets([u32; 2]),
+}
+
+impl ParameterLabel {
+    /// The parameter's text within `signature`.
+    pub fn text(&self, signature: &str) -> String {
+        match self {
+            Self::Simple(label) => label.clone(),
+            Self::Offsets([start, end]) => {
+                let utf16: Vec<u16> = signature.encode_utf16().collect();
+                let start = (*start as usize).min(utf16.len());
+                let end = (*end as usize).clamp(start, utf16.len());
+                String::from_utf16_lossy(&utf16[start..end])
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterInformation {
+    pub label: ParameterLabel,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Documentation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureInformation {
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Documentation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Vec<ParameterInformation>>,
+    /// Overrides [`SignatureHelp::active_parameter`] for this signature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_parameter: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignatureHelp {
+    pub signatures: Vec<SignatureInformation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_signature: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_parameter: Option<u32>,
+}
+
+/// LSP `CompletionItemKind`, kept numeric for the same reason as
+/// [`SymbolKind`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CompletionItemKind(pub u8);
+
+impl CompletionItemKind {
+    const NAMES: [&'static str; 25] = [
+        "text",
+        "method",
+        "function",
+        "constructor",
+        "field",
+        "variable",
+        "class",
+        "interface",
+        "module",
+        "property",
+        "unit",
+        "value",
+        "enum",
+        "keyword",
+        "snippet",
+        "color",
+        "file",
+        "reference",
+        "folder",
+        "enum_member",
+        "constant",
+        "struct",
+        "event",
+        "operator",
+        "type_parameter",
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        Self::NAMES
+            .get(usize::from(self.0).wrapping_sub(1))
+            .copied()
+            .unwrap_or("unknown")
+    }
+}
+
+/// A completion's edit: a plain text edit, or separate insert and replace
+/// ranges for servers that distinguish them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionTextEdit {
+    Edit(TextEdit),
+    #[serde(rename_all = "camelCase")]
+    InsertReplace {
+        new_text: String,
+        insert: Range,
+        replace: Range,
+    },
+}
+
+impl CompletionTextEdit {
+    /// The edit that replaces the word under the cursor.
+    pub fn into_text_edit(self) -> TextEdit {
+        match self {
+            Self::Edit(edit) => edit,
+            Self::InsertReplace {
+                new_text, replace, ..
+            } => TextEdit {
+                range: replace,
+                new_text,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompletionItemLabelDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// A completion candidate. Everything rust-analyzer sends is kept so the
+/// item can be handed back unchanged to `completionItem/resolve`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionItem {
+    pub label: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label_details: Option<CompletionItemLabelDetails>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<CompletionItemKind>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub documentation: Option<Documentation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preselect: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sort_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insert_text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text_edit: Option<CompletionTextEdit>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub additional_text_edits: Option<Vec<TextEdit>>,
+    #[serde(flatten)]
+    pub other: serde_json::Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompletionList {
+    pub is_incomplete: bool,
+    pub items: Vec<CompletionItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CompletionResponse {
+    Array(Vec<CompletionItem>),
+    List(CompletionList),
+}
+
+impl CompletionResponse {
+    /// The items and whether the server cut the list short.
+    pub fn into_parts(self) -> (Vec<CompletionItem>, bool) {
+        match self {
+            Self::Array(items) => (items, false),
+            Self::List(list) => (list.items, list.is_incomplete),
+        }
+    }
+}
+
+/// LSP diagnostic severity. Lower values are more severe, so `Ord` sorts
+/// errors first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum DiagnosticSeverity {
+    Error = 1,
+    Warning = 2,
+    Information = 3,
+    Hint = 4,
+}
+
+impl DiagnosticSeverity {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Information => "information",
+            Self::Hint => "hint",
+        }
+    }
+}
+
+impl TryFrom<u8> for DiagnosticSeverity {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, String> {
+        match value {
+            1 => Ok(Self::Error),
+            2 => Ok(Self::Warning),
+            3 => Ok(Self::Information),
+            4 => Ok(Self::Hint),
+            other => Err(format!("invalid diagnostic severity {other}")),
+        }
+    }
+}
+
+impl From<DiagnosticSeverity> for u8 {
+    fn from(severity: DiagnosticSeverity) -> Self {
+        severity as u8
+    }
+}
+
+impl std::str::FromStr for DiagnosticSeverity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "error" => Ok(Self::Error),
+            "warning" => Ok(Self::Warning),
+            "information" | "info" => Ok(Self::Information),
+            "hint" => Ok(Self::Hint),
+            other => Err(anyhow::anyhow!("Unknown severity: {other}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticRelatedInformation {
+    pub location: Location,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Diagnostic {
+    pub range: Range,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub severity: Option<DiagnosticSeverity>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub related_information: Option<Vec<DiagnosticRelatedInformation>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PublishDiagnosticsParams {
+    pub uri: String,
+    #[serde(default)]
+    pub version: Option<i32>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn range(line: u32) -> Value {
+        json!({"start": {"line": line, "character": 0}, "end": {"line": line, "character": 4}})
+    }
+
+    #[test]
+    fn definitions_as_locations_or_links() {
+        let location = json!({"uri": "file:///a.rs", "range": range(1)});
+        let decoded = decode_result::<GotoDefinition>(location.clone()).unwrap();
+        assert!(matches!(decoded, Some(GotoDefinitionResponse::Scalar(_))));
+        let decoded = decode_result::<GotoDefinition>(json!([location])).unwrap();
+        assert!(matches!(decoded, Some(GotoDefinitionResponse::Array(ref l)) if l.len() == 1));
+
+        let link = json!({
+            "targetUri": "file:///b.rs",
+            "targetRange": range(2),
+            "targetSelectionRange": range(3)
+        });
+        let links = decode_result::<GotoDefinition>(json!([link]))
+            .unwrap()
+            .unwrap()
+            .into_links();
+        assert_eq!(links[0].target_uri, "file:///b.rs");
+        assert_eq!(links[0].target_selection_range.start.line, 3);
+
+        assert!(
+            decode_result::<GotoDefinition>(Value::Null)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn hover_contents_in_every_shape() {
+        let hover = |contents: Value| {
+            decode_result::<HoverRequest>(json!({ "contents": contents }))
+                .unwrap()
+                .unwrap()
+                .contents
+        };
+        let markup = hover(json!({"kind": "markdown", "value": "**x**"}));
+        assert!(matches!(markup, HoverContents::Markup(_)));
+        assert_eq!(markup.to_markdown(), "**x**");
+
+        let scalar = hover(json!("plain"));
+        assert!(matches!(
+            scalar,
+            HoverContents::Scalar(MarkedString::String(_))
+        ));
+        assert_eq!(scalar.to_markdown(), "plain");
+
+        let code = hover(json!({"language": "rust", "value": "fn f()"}));
+        assert!(matches!(
+            code,
+            HoverContents::Scalar(MarkedString::LanguageString { .. })
+        ));
+        assert_eq!(code.to_markdown(), "```rust\nfn f()\n```");
+
+        let array = hover(json!(["docs", {"language": "rust", "value": "fn f()"}]));
+        assert!(matches!(array, HoverContents::Array(ref items) if items.len() == 2));
+        assert_eq!(array.to_markdown(), "docs\n\n```rust\nfn f()\n```");
+    }
+
+    #[test]
+    fn document_symbols_nested_or_flat() {
+        let nested = json!([{
+            "name": "Point", "kind": 23, "range": range(0), "selectionRange": range(0),
+            "children": [{"name": "x", "kind": 8, "range": range(1), "selectionRange": range(1)}]
+        }]);
+        let decoded = decode_result::<DocumentSymbolRequest>(nested)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(decoded, DocumentSymbolResponse::Nested(_)));
+
+        let flat = json!([{
+            "name": "Point", "kind": 23,
+            "location": {"uri": "file:///a.rs", "range": range(0)}
+        }]);
+        let decoded = decode_result::<DocumentSymbolRequest>(flat)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(decoded, DocumentSymbolResponse::Flat(_)));
+        assert_eq!(decoded.into_nested()[0].name, "Point");
+    }
+
+    #[test]
+    fn code_actions_and_commands() {
+        let actions = json!([
+            {"title": "Run", "command": "rust-analyzer.runSingle", "arguments": []},
+            {"title": "Fill", "kind": "quickfix", "command": {"title": "x", "command": "y"}},
+            {"title": "Inline", "kind": "refactor.inline", "data": {"id": 1}}
+        ]);
+        let decoded = decode_result::<CodeActionRequest>(actions)
+            .unwrap()
+            .unwrap();
+        assert!(matches!(decoded[0], CodeActionOrCommand::Command(_)));
+        // An action carrying a command is still an action.
+        assert!(matches!(decoded[1], CodeActionOrCommand::CodeAction(_)));
+        assert!(matches!(decoded[2], CodeActionOrCommand::CodeAction(_)));
+    }
+
+    #[test]
+    fn completion_lists_and_edits() {
+        let item = json!({
+            "label": "push",
+            "textEdit": {"newText": "push", "insert": range(0), "replace": range(0)}
+        });
+        let decoded = decode_result::<Completion>(json!([item.clone()]))
+            .unwrap()
+            .unwrap();
+        let (items, incomplete) = decoded.into_parts();
+        assert!(!incomplete);
+        assert!(matches!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::InsertReplace { .. })
+        ));
+
+        let item = json!({"label": "len", "textEdit": {"newText": "len", "range": range(0)}});
+        let list = json!({"isIncomplete": true, "items": [item]});
+        let (items, incomplete) = decode_result::<Completion>(list)
+            .unwrap()
+            .unwrap()
+            .into_parts();
+        assert!(incomplete);
+        assert!(matches!(
+            items[0].text_edit,
+            Some(CompletionTextEdit::Edit(_))
+        ));
+    }
+
+    #[test]
+    fn workspace_edits_mix_text_edits_and_file_operations() {
+        let edit = json!({"documentChanges": [
+            {"textDocument": {"uri": "file:///a.rs", "version": 3}, "edits": [{"range": range(0), "newText": "b"}]},
+            {"kind": "rename", "oldUri": "file:///a.rs", "newUri": "file:///b.rs"}
+        ]});
+        let changes = decode_result::<Rename>(edit)
+            .unwrap()
+            .unwrap()
+            .into_document_changes();
+        assert!(matches!(changes[0], DocumentChange::Edit(_)));
+        assert!(matches!(
+            changes[1],
+            DocumentChange::Operation(ResourceOp::Rename { .. })
+        ));
+    }
+
+    #[test]
+    fn parameter_labels_as_text_or_offsets() {
+        let help = json!({"signatures": [{
+            "label": "fn f(a: u8, b: u8)",
+            "parameters": [{"label": "a: u8"}, {"label": [12, 17]}]
+        }]});
+        let help = decode_result::<SignatureHelpRequest>(help)
+            .unwrap()
+            .unwrap();
+        let signature = &help.signatures[0];
+        let labels: Vec<String> = signature
+            .parameters
+            .iter()
+            .flatten()
+            .map(|parameter| parameter.label.text(&signature.label))
+            .collect();
+        assert_eq!(labels, ["a: u8", "b: u8"]);
+    }
+
+    #[test]
+    fn decode_errors_name_the_method() {
+        let error = decode_result::<HoverRequest>(json!({"contents": 5})).unwrap_err();
+        let message = error.to_string();
+        assert!(message.starts_with("Could not decode textDocument/hover response"));
+        assert!(message.ends_with("(got {\"contents\":5})"));
+    }
 }
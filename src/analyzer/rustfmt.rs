@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+use similar::{DiffTag, TextDiff};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+
+use crate::analyzer::pool::find_workspace_root;
+use crate::analyzer::protocol::FormattingOptions;
+
+/// Environment variable naming the rustfmt binary, as `cargo fmt` reads it.
+pub const RUSTFMT_ENV_VAR: &str = "RUSTFMT";
+
+/// The edition Cargo assumes when a manifest does not name one.
+const DEFAULT_EDITION: &str = "2015";
+
+/// The rustfmt settings that apply to one file: the `rustfmt.toml` or
+/// `.rustfmt.toml` rustfmt would find for it, and its crate's edition.
+#[derive(Debug, Clone)]
+pub struct RustfmtConfig {
+    pub config_path: Option<PathBuf>,
+    /// From `rustfmt.toml` when it sets one, else the crate's `Cargo.toml`.
+    pub edition: String,
+    pub tab_spaces: u32,
+    pub hard_tabs: bool,
+}
+
+impl RustfmtConfig {
+    /// Look for the settings rustfmt would use for `path`, searching upwards
+    /// from its directory the way rustfmt does.
+    pub fn discover(path: &Path) -> Self {
+        let start = if path.is_dir() {
+            path
+        } else {
+            path.parent().unwrap_or(path)
+        };
+        let config_path = start.ancestors().find_map(|dir| {
+            ["rustfmt.toml", ".rustfmt.toml"]
+                .iter()
+                .map(|name| dir.join(name))
+                .find(|candidate| candidate.is_file())
+        });
+        let config = config_path
+            .as_deref()
+            .and_then(read_toml)
+            .unwrap_or_default();
+
+        let edition = toml_value(&config, "edition")
+            .or_else(|| crate_edition(path))
+            .unwrap_or_else(|| DEFAULT_EDITION.to_string());
+        Self {
+            config_path,
+            edition,
+            tab_spaces: toml_value(&config, "tab_spaces")
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(4),
+            hard_tabs: toml_value(&config, "hard_tabs").as_deref() == Some("true"),
+        }
+    }
+
+    /// The indentation to ask for in `textDocument/formatting`.
+    pub fn formatting_options(&self) -> FormattingOptions {
+        FormattingOptions {
+            tab_size: self.tab_spaces,
+            insert_spaces: !self.hard_tabs,
+        }
+    }
+}
+
+/// Format `text` by running rustfmt on it directly, with `config`.
+pub async fn run_rustfmt(text: &str, config: &RustfmtConfig) -> Result<String> {
+    let binary = std::env::var(RUSTFMT_ENV_VAR).unwrap_or_else(|_| "rustfmt".to_string());
+    let mut command = tokio::process::Command::new(&binary);
+    command
+        .args(["--emit", "stdout", "--edition", &config.edition])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    if let Some(config_path) = &config.config_path {
+        command.arg("--config-path").arg(config_path);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Cannot run {binary}; set {RUSTFMT_ENV_VAR} to its path"))?;
+
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = text.to_string();
+    // Write from a separate task so a large file cannot fill both pipes.
+    let writer = tokio::spawn(async move { stdin.write_all(input.as_bytes()).await });
+    let output = child.wait_with_output().await?;
+    writer.await??;
+
+    if !output.status.success() {
+        return Err(anyhow::anyhow!(
+            "rustfmt failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    String::from_utf8(output.stdout).context("rustfmt printed invalid UTF-8")
+}
+
+/// The directory of the crate `path` belongs to: the nearest enclosing one
+/// whose `Cargo.toml` has a `[package]`.
+pub fn find_crate_root(path: &Path) -> Option<PathBuf> {
+    let start = if path.is_dir() { path } else { path.parent()? };
+    start.ancestors().find_map(|dir| {
+        read_toml(&dir.join("Cargo.toml"))?
+            .contains_key("package")
+            .then(|| dir.to_path_buf())
+    })
+}
+
+/// Every Rust source file in the crate at `root`, skipping `target`, hidden
+/// directories and nested crates.
+pub fn crate_sources(root: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries =
+            std::fs::read_dir(&dir).with_context(|| format!("Cannot list {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if path.is_dir() {
+                if name.starts_with('.') || name == "target" || path.join("Cargo.toml").exists() {
+                    continue;
+                }
+                pending.push(path);
+            } else if name.ends_with(".rs") {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// `formatted` with only the changes that touch lines `start..=end` of
+/// `original` (0-based) kept, for range formatting. rustfmt's own range
+/// support needs a nightly toolchain.
+pub fn keep_changes_in_lines(original: &str, formatted: &str, start: usize, end: usize) -> String {
+    let diff = TextDiff::from_lines(original, formatted);
+    let mut out = String::with_capacity(formatted.len());
+    let in_range = |line: usize| (start..=end).contains(&line);
+    for op in diff.ops() {
+        let old = op.old_range();
+        let new = op.new_range();
+        // Reindenting a block replaces each line with one line, so those can
+        // be taken one by one rather than as a whole hunk.
+        if op.tag() == DiffTag::Replace && old.len() == new.len() {
+            for (old_line, new_line) in old.zip(new) {
+                let line = match in_range(old_line) {
+                    true => diff.new_slices()[new_line],
+                    false => diff.old_slices()[old_line],
+                };
+                out.push_str(line);
+            }
+            continue;
+        }
+        let touches = match op.tag() {
+            DiffTag::Equal => false,
+            // Lines inserted before a line of the range.
+            DiffTag::Insert => old.start >= start && old.start <= end,
+            DiffTag::Delete | DiffTag::Replace => old.start <= end && old.end > start,
+        };
+        let lines = match touches {
+            true => &diff.new_slices()[new],
+            false => &diff.old_slices()[old],
+        };
+        lines.iter().for_each(|line| out.push_str(line));
+    }
+    out
+}
+
+/// The edition in the `Cargo.toml` of the crate `path` belongs to,
+/// following `edition.workspace = true` to the workspace manifest.
+fn crate_edition(path: &Path) -> Option<String> {
+    let root = find_crate_root(path)?;
+    let manifest = read_toml(&root.join("Cargo.toml"))?;
+    if toml_value(&manifest, "package.edition.workspace").as_deref() != Some("true") {
+        return toml_value(&manifest, "package.edition");
+    }
+    let workspace = find_workspace_root(&root)?;
+    let manifest = read_toml(&workspace.join("Cargo.toml"))?;
+    toml_value(&manifest, "workspace.package.edition")
+}
+
+/// A TOML file's top-level table, or `None` if it cannot be read or parsed.
+fn read_toml(path: &Path) -> Option<toml::Table> {
+    std::fs::read_to_string(path).ok()?.parse().ok()
+}
+
+/// The value at the dotted `key` in `table`, with strings unquoted and
+/// anything else as TOML would write it.
+fn toml_value(table: &toml::Table, key: &str) -> Option<String> {
+    let mut parts = key.split('.');
+    let first = table.get(parts.next()?)?;
+    let value = parts.try_fold(first, |value, part| value.get(part))?;
+    Some(match value {
+        toml::Value::String(text) => text.clone(),
+        other => other.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_inserted_before_the_range_start_are_kept() {
+        let original = "a\nb\nc\n";
+        let formatted = "a\nX\nb\nc\n";
+        assert_eq!(keep_changes_in_lines(original, formatted, 1, 1), formatted);
+        assert_eq!(keep_changes_in_lines(original, formatted, 0, 0), original);
+        assert_eq!(keep_changes_in_lines(original, formatted, 2, 2), original);
+    }
+
+    #[test]
+    fn reindented_lines_are_taken_one_by_one() {
+        let original = "fn f() {\nlet a = 1;\nlet b = 2;\n}\n";
+        let formatted = "fn f() {\n    let a = 1;\n    let b = 2;\n}\n";
+        assert_eq!(
+            keep_changes_in_lines(original, formatted, 1, 1),
+            "fn f() {\n    let a = 1;\nlet b = 2;\n}\n"
+        );
+        assert_eq!(keep_changes_in_lines(original, formatted, 0, 3), formatted);
+        assert_eq!(keep_changes_in_lines(original, formatted, 9, 12), original);
+    }
+
+    #[test]
+    fn hunks_reaching_into_the_range_are_taken_whole() {
+        let original = "fn f() { a(); }\n\nfn g() { b(); }\n";
+        let formatted = "fn f() {\n    a();\n}\n\nfn g() {\n    b();\n}\n";
+        assert_eq!(
+            keep_changes_in_lines(original, formatted, 2, 2),
+            "fn f() { a(); }\n\nfn g() {\n    b();\n}\n"
+        );
+
+        let original = "a\n\n\n\nb\n";
+        let formatted = "a\n\nb\n";
+        assert_eq!(keep_changes_in_lines(original, formatted, 1, 3), formatted);
+        assert_eq!(keep_changes_in_lines(original, formatted, 0, 0), original);
+    }
+
+    fn value(contents: &str, key: &str) -> Option<String> {
+        toml_value(&contents.parse().unwrap(), key)
+    }
+
+    #[test]
+    fn toml_values_by_dotted_key() {
+        let manifest = r#"
+edition = "2015"
+tab_spaces = 2 # two
+[ package ]
+name = "a # b"
+description = """
+[workspace]
+edition = "2018"
+"""
+authors = [
+    "[workspace.package]",
+]
+edition = { workspace = true }
+[[bin]]
+name = "tool"
+[workspace.package]
+edition = "2024"
+"#;
+        assert_eq!(value(manifest, "edition").as_deref(), Some("2015"));
+        assert_eq!(value(manifest, "tab_spaces").as_deref(), Some("2"));
+        assert_eq!(value(manifest, "package.name").as_deref(), Some("a # b"));
+        assert_eq!(
+            value(manifest, "package.edition.workspace").as_deref(),
+            Some("true")
+        );
+        assert_eq!(
+            value(manifest, "workspace.package.edition").as_deref(),
+            Some("2024")
+        );
+        assert_eq!(value(manifest, "workspace.edition"), None);
+        assert_eq!(value(manifest, "bin.name"), None);
+        assert_eq!(value(manifest, "package.version"), None);
+    }
+
+    #[test]
+    fn crate_edition_follows_the_workspace() {
+        let dir = std::env::temp_dir().join(format!("rustmcp-rustfmt-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("member/src")).unwrap();
+        std::fs::write(
+            dir.join("Cargo.toml"),
+            "[ workspace ] # the root\nmembers = [\"member\"]\n\n[workspace.package]\nedition = \"2024\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("member/Cargo.toml"),
+            "[package] # the member\nname = \"member\"\nedition = { workspace = true }\n\n[[bin]]\nname = \"member\"\n",
+        )
+        .unwrap();
+        let source = dir.join("member/src/main.rs");
+        std::fs::write(&source, "fn main() {}\n").unwrap();
+        assert_eq!(RustfmtConfig::discover(&source).edition, "2024");
+
+        std::fs::write(
+            dir.join("member/Cargo.toml"),
+            "[package]\nname = \"member\"\nedition = \"2021\" # pinned\n",
+        )
+        .unwrap();
+        assert_eq!(RustfmtConfig::discover(&source).edition, "2021");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
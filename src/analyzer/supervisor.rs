@@ -0,0 +1,56 @@
+use std::fmt;
+use std::time::Duration;
+
+/// How many times in a row we try to bring rust-analyzer back before giving up.
+pub const MAX_RESTART_ATTEMPTS: u32 = 5;
+
+/// How often the supervisor checks whether the child process has exited.
+pub const EXIT_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long rust-analyzer gets to answer `shutdown` and exit before it is
+/// killed.
+pub const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Lifecycle of the rust-analyzer process behind a client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnalyzerState {
+    NotStarted,
+    Starting,
+    Running,
+    Restarting {
+        attempt: u32,
+    },
+    /// Restarting gave up; only a manual restart will try again.
+    Failed(String),
+    ShuttingDown,
+    /// Shut down for good; the client will not start it again.
+    Stopped,
+}
+
+impl fmt::Display for AnalyzerState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotStarted => write!(f, "not started"),
+            Self::Starting => write!(f, "starting"),
+            Self::Running => write!(f, "running"),
+            Self::Restarting { attempt } => write!(f, "restarting (attempt {attempt})"),
+            Self::Failed(reason) => write!(f, "failed: {reason}"),
+            Self::ShuttingDown => write!(f, "shutting down"),
+            Self::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+/// Delay before restart attempt `attempt` (starting at 1): immediately the
+/// first time, then doubling from one second up to thirty.
+pub fn restart_backoff(attempt: u32) -> Duration {
+    if attempt <= 1 {
+        return Duration::ZERO;
+    }
+    BASE_BACKOFF
+        .saturating_mul(1 << (attempt - 2).min(16))
+        .min(MAX_BACKOFF)
+}
@@ -0,0 +1,123 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Environment variable overriding request timeouts, as a JSON object of
+/// seconds keyed by LSP method, plus an optional `default`, e.g.
+/// `{"default": 30, "workspace/symbol": 120}`.
+pub const TIMEOUTS_ENV_VAR: &str = "RUST_MCP_REQUEST_TIMEOUTS";
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long to wait for rust-analyzer to answer each kind of request.
+#[derive(Debug, Clone)]
+pub struct RequestTimeouts {
+    default: Duration,
+    per_method: HashMap<String, Duration>,
+}
+
+impl Default for RequestTimeouts {
+    fn default() -> Self {
+        let per_method = [
+            // Loading a large workspace can take minutes before the reply.
+            ("initialize", Duration::from_secs(120)),
+            ("shutdown", Duration::from_secs(10)),
+            ("workspace/symbol", Duration::from_secs(60)),
+            ("textDocument/references", Duration::from_secs(60)),
+            ("textDocument/rename", Duration::from_secs(60)),
+        ]
+        .into_iter()
+        .map(|(method, timeout)| (method.to_string(), timeout))
+        .collect();
+
+        Self {
+            default: DEFAULT_TIMEOUT,
+            per_method,
+        }
+    }
+}
+
+impl RequestTimeouts {
+    /// Defaults, overridden by `RUST_MCP_REQUEST_TIMEOUTS` if set.
+    pub fn from_env() -> Result<Self> {
+        match std::env::var(TIMEOUTS_ENV_VAR) {
+            Ok(raw) => Self::with_overrides(&raw),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Defaults, overridden by `raw` in the format of
+    /// `RUST_MCP_REQUEST_TIMEOUTS`.
+    fn with_overrides(raw: &str) -> Result<Self> {
+        let mut timeouts = Self::default();
+        let overrides: HashMap<String, Value> = serde_json::from_str(raw)
+            .with_context(|| format!("{TIMEOUTS_ENV_VAR} is not a JSON object"))?;
+        for (method, seconds) in overrides {
+            let seconds = seconds.as_f64().ok_or_else(|| {
+                anyhow::anyhow!("{TIMEOUTS_ENV_VAR}: timeout for {method} must be a number")
+            })?;
+            let timeout = Duration::try_from_secs_f64(seconds)
+                .ok()
+                .filter(|timeout| !timeout.is_zero())
+                .ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "{TIMEOUTS_ENV_VAR}: timeout for {method} must be a positive number of seconds, not {seconds}"
+                    )
+                })?;
+            timeouts.set(&method, timeout);
+        }
+        Ok(timeouts)
+    }
+
+    /// Set the timeout for `method`, or the fallback when `method` is
+    /// `default`.
+    pub fn set(&mut self, method: &str, timeout: Duration) {
+        if method == "default" {
+            self.default = timeout;
+        } else {
+            self.per_method.insert(method.to_string(), timeout);
+        }
+    }
+
+    pub fn for_method(&self, method: &str) -> Duration {
+        self.per_method.get(method).copied().unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_apply_per_method_and_as_default() {
+        let timeouts =
+            RequestTimeouts::with_overrides(r#"{"default": 5, "workspace/symbol": 0.5}"#).unwrap();
+        assert_eq!(
+            timeouts.for_method("workspace/symbol"),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            timeouts.for_method("textDocument/hover"),
+            Duration::from_secs(5)
+        );
+        assert_eq!(timeouts.for_method("initialize"), Duration::from_secs(120));
+    }
+
+    #[test]
+    fn invalid_timeouts_are_errors() {
+        for raw in [
+            r#"{"default": -1}"#,
+            r#"{"default": 0}"#,
+            r#"{"default": 1e300}"#,
+            r#"{"default": "30"}"#,
+            "[30]",
+        ] {
+            let error = RequestTimeouts::with_overrides(raw).unwrap_err();
+            assert!(
+                error.to_string().starts_with(TIMEOUTS_ENV_VAR),
+                "{raw}: {error}"
+            );
+        }
+    }
+}
@@ -0,0 +1,522 @@
+use serde_json::{Value, json};
+
+use crate::analyzer::encoding::ColumnMapper;
+use crate::analyzer::protocol::{Location, Range};
+use crate::analyzer::uri::uri_to_file_path;
+
+/// Impl headers longer than this many lines are not followed to their `{`.
+const MAX_HEADER_LINES: usize = 20;
+
+/// The kind of item a hover signature declares, e.g. `trait` for
+/// `pub unsafe trait Send`. `None` for anything that is not a type or trait.
+pub fn item_keyword(signature: &str) -> Option<&'static str> {
+    for token in signature.split_whitespace() {
+        match token {
+            "trait" => return Some("trait"),
+            "struct" => return Some("struct"),
+            "enum" => return Some("enum"),
+            "union" => return Some("union"),
+            "type" => return Some("type"),
+            "pub" | "unsafe" | "auto" | "default" => {}
+            token if token.starts_with("pub(") => {}
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// The name declared after `keyword` in `signature`.
+pub fn item_name(signature: &str, keyword: &str) -> Option<String> {
+    let mut tokens = signature.split_whitespace();
+    tokens.find(|token| *token == keyword)?;
+    let name: String = tokens
+        .next()?
+        .chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect();
+    (!name.is_empty()).then_some(name)
+}
+
+/// The declaration header starting at byte `start` of `text`: everything up
+/// to the opening `{` or `;`, with whitespace collapsed.
+pub fn header_at(text: &str, start: usize) -> String {
+    let mut header = String::new();
+    for line in text[start..].lines().take(MAX_HEADER_LINES) {
+        let end = line.find(['{', ';']);
+        header.push_str(&line[..end.unwrap_or(line.len())]);
+        header.push(' ');
+        if end.is_some() {
+            break;
+        }
+    }
+    collapse_whitespace(&header)
+}
+
+/// The header of the impl block rust-analyzer pointed at. The block's full
+/// range may start at attributes or doc comments, and its selection range
+/// somewhere inside the header, so look for the `impl` keyword between the
+/// two and then a few lines above.
+pub fn impl_header(text: &str, full: Range, selection: Range) -> Option<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let first = full.start.line as usize;
+    let last = selection.start.line.max(full.start.line) as usize;
+    let impl_line = (first..=last)
+        .rev()
+        .chain((last.saturating_sub(5)..first).rev())
+        .find(|&i| lines.get(i).is_some_and(|line| starts_impl(line)))?;
+    let start: usize = text
+        .split_inclusive('\n')
+        .take(impl_line)
+        .map(str::len)
+        .sum();
+    Some(header_at(text, start))
+}
+
+fn starts_impl(line: &str) -> bool {
+    let line = line.trim_start();
+    let line = line.strip_prefix("unsafe ").unwrap_or(line).trim_start();
+    let line = line.strip_prefix("default ").unwrap_or(line);
+    line.starts_with("impl ") || line.starts_with("impl<")
+}
+
+/// An `impl` header, split into the trait (if any) and the implementing type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImplHeader {
+    pub trait_name: Option<String>,
+    pub self_type: String,
+    /// `impl !Send for T`.
+    pub negative: bool,
+}
+
+/// Parse `impl<T: Clone> fmt::Display for Wrapper<T> where T: Debug`.
+pub fn parse_impl_header(header: &str) -> Option<ImplHeader> {
+    let header = collapse_whitespace(header);
+    let rest = header.strip_prefix("unsafe ").unwrap_or(&header);
+    let rest = rest.strip_prefix("default ").unwrap_or(rest);
+    let rest = rest.strip_prefix("impl")?;
+    let rest = skip_generics(rest.trim_start()).trim();
+    let rest = match find_top_level(rest, " where ") {
+        Some(i) => &rest[..i],
+        None => rest.strip_suffix(" where").unwrap_or(rest),
+    };
+
+    match find_top_level(rest, " for ") {
+        Some(i) => {
+            let trait_name = rest[..i].trim();
+            let negative = trait_name.starts_with('!');
+            Some(ImplHeader {
+                trait_name: Some(trait_name.trim_start_matches('!').trim().to_string()),
+                self_type: rest[i + " for ".len()..].trim().to_string(),
+                negative,
+            })
+        }
+        None => Some(ImplHeader {
+            trait_name: None,
+            self_type: rest.trim().to_string(),
+            negative: false,
+        }),
+    }
+}
+
+/// The supertraits in a trait declaration header: the bounds after the
+/// name, plus `Self: ...` bounds in its where clause. Lifetimes and
+/// `?Sized` are left out.
+pub fn supertrait_bounds(header: &str) -> Vec<String> {
+    let header = collapse_whitespace(header);
+    let Some(start) = find_word(&header, "trait") else {
+        return Vec::new();
+    };
+    let rest = header[start + "trait".len()..].trim_start();
+    let name_len = rest
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(rest.len());
+    let rest = skip_generics(&rest[name_len..]).trim_start();
+
+    let where_start = if rest.starts_with("where ") {
+        Some(0)
+    } else {
+        find_top_level(rest, " where ").map(|i| i + 1)
+    };
+    let (bounds, where_clause) = match where_start {
+        Some(i) => (&rest[..i], &rest[i + "where ".len()..]),
+        None => (rest, ""),
+    };
+    let mut result = Vec::new();
+    if let Some(bounds) = bounds.trim().strip_prefix(':') {
+        result.extend(split_bounds(bounds));
+    }
+    for predicate in split_top_level(where_clause, ',') {
+        if let Some(bounds) = predicate.trim().strip_prefix("Self:") {
+            result.extend(split_bounds(bounds));
+        }
+    }
+    result
+}
+
+fn split_bounds(bounds: &str) -> Vec<String> {
+    split_top_level(bounds, '+')
+        .into_iter()
+        // `for<'a> Trait<'a>` names `Trait<'a>`.
+        .map(|bound| match bound.trim().strip_prefix("for") {
+            Some(rest) if rest.trim_start().starts_with('<') => skip_generics(rest.trim_start()),
+            _ => bound,
+        })
+        .map(str::trim)
+        .filter(|bound| !bound.is_empty() && !bound.starts_with('\'') && !bound.starts_with('?'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The identifier to look up for a bound: `PartialOrd` for
+/// `std::cmp::PartialOrd<Self>`.
+pub fn bound_ident(bound: &str) -> &str {
+    let path = bound.split('<').next().unwrap_or(bound);
+    let path = path.split('(').next().unwrap_or(path);
+    path.rsplit("::").next().unwrap_or(path).trim()
+}
+
+/// Byte offset of `word` in `text` as a whole identifier.
+pub fn find_word(text: &str, word: &str) -> Option<usize> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.match_indices(word).map(|(i, _)| i).find(|&i| {
+        let before = text[..i].chars().next_back();
+        let after = text[i + word.len()..].chars().next();
+        !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+    })
+}
+
+fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Skip a leading `<...>` generic parameter list.
+fn skip_generics(s: &str) -> &str {
+    if !s.starts_with('<') {
+        return s;
+    }
+    let mut depth = 0usize;
+    let mut previous = ' ';
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if previous != '-' => {
+                depth -= 1;
+                if depth == 0 {
+                    return &s[i + 1..];
+                }
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    ""
+}
+
+/// Byte offset of `needle` in `s` outside any brackets.
+fn find_top_level(s: &str, needle: &str) -> Option<usize> {
+    let mut depth = 0i32;
+    let mut previous = ' ';
+    for (i, c) in s.char_indices() {
+        if depth == 0 && s[i..].starts_with(needle) {
+            return Some(i);
+        }
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' if previous != '-' => depth -= 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+        previous = c;
+    }
+    None
+}
+
+fn split_top_level(s: &str, separator: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0i32;
+    let mut previous = ' ';
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            '>' if previous != '-' => depth -= 1,
+            ')' | ']' => depth -= 1,
+            c if c == separator && depth == 0 => {
+                parts.push(&s[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+        previous = c;
+    }
+    parts.push(&s[start..]);
+    parts
+}
+
+struct TypeNode {
+    name: String,
+    kind: &'static str,
+    location: Option<Location>,
+}
+
+struct TypeEdge {
+    from: usize,
+    to: usize,
+    relation: &'static str,
+    /// The impl block, or where a supertrait is named.
+    site: Option<Location>,
+    derived: bool,
+    negative: bool,
+}
+
+/// Types and traits linked by `implements` and `supertrait` edges. Node 0
+/// is the item the hierarchy was asked for.
+pub struct TypeGraph {
+    nodes: Vec<TypeNode>,
+    edges: Vec<TypeEdge>,
+    inherent_impls: Vec<Location>,
+}
+
+impl TypeGraph {
+    pub fn new(name: String, kind: &'static str, location: Option<Location>) -> Self {
+        Self {
+            nodes: vec![TypeNode {
+                name,
+                kind,
+                location,
+            }],
+            edges: Vec::new(),
+            inherent_impls: Vec::new(),
+        }
+    }
+
+    /// The node for `name`, added if new. A location fills in a missing one.
+    pub fn node(&mut self, name: &str, kind: &'static str, location: Option<Location>) -> usize {
+        if let Some(id) = self
+            .nodes
+            .iter()
+            .position(|node| node.name == name && node.kind == kind)
+        {
+            if self.nodes[id].location.is_none() {
+                self.nodes[id].location = location;
+            }
+            return id;
+        }
+        self.nodes.push(TypeNode {
+            name: name.to_string(),
+            kind,
+            location,
+        });
+        self.nodes.len() - 1
+    }
+
+    /// `from` implements trait `to` in the impl block at `site`.
+    pub fn implements(&mut self, from: usize, to: usize, site: Location, header: &ImplHeader) {
+        self.edges.push(TypeEdge {
+            from,
+            to,
+            relation: "implements",
+            site: Some(site),
+            derived: false,
+            negative: header.negative,
+        });
+    }
+
+    /// `from` implements trait `to` through `#[derive]` at `site`.
+    pub fn derives(&mut self, from: usize, to: usize, site: Location) {
+        self.edges.push(TypeEdge {
+            from,
+            to,
+            relation: "implements",
+            site: Some(site),
+            derived: true,
+            negative: false,
+        });
+    }
+
+    /// Trait `from` requires trait `to`.
+    pub fn supertrait(&mut self, from: usize, to: usize, site: Option<Location>) {
+        self.edges.push(TypeEdge {
+            from,
+            to,
+            relation: "supertrait",
+            site,
+            derived: false,
+            negative: false,
+        });
+    }
+
+    pub fn inherent_impl(&mut self, site: Location) {
+        self.inherent_impls.push(site);
+    }
+
+    /// Every file mentioned, for loading a column mapper.
+    pub fn uris(&self) -> Vec<&str> {
+        let mut uris: Vec<&str> = self
+            .nodes
+            .iter()
+            .filter_map(|node| node.location.as_ref())
+            .chain(self.edges.iter().filter_map(|edge| edge.site.as_ref()))
+            .chain(&self.inherent_impls)
+            .map(|location| location.uri.as_str())
+            .collect();
+        uris.sort_unstable();
+        uris.dedup();
+        uris
+    }
+
+    pub fn to_json(&self, mapper: &ColumnMapper) -> Value {
+        let location = |location: &Location| {
+            json!({
+                "file": uri_to_file_path(&location.uri),
+                "range": mapper.range_to_client(&location.uri, location.range)
+            })
+        };
+        let nodes: Vec<Value> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(id, node)| {
+                json!({
+                    "id": id,
+                    "name": node.name,
+                    "kind": node.kind,
+                    "location": node.location.as_ref().map(location)
+                })
+            })
+            .collect();
+        let edges: Vec<Value> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let mut value = json!({
+                    "from": edge.from,
+                    "to": edge.to,
+                    "relation": edge.relation,
+                    "site": edge.site.as_ref().map(location)
+                });
+                if edge.derived {
+                    value["derived"] = json!(true);
+                }
+                if edge.negative {
+                    value["negative"] = json!(true);
+                }
+                value
+            })
+            .collect();
+        json!({
+            "root": 0,
+            "nodes": nodes,
+            "edges": edges,
+            "inherent_impls": self.inherent_impls.iter().map(location).collect::<Vec<_>>()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(trait_name: Option<&str>, self_type: &str, negative: bool) -> Option<ImplHeader> {
+        Some(ImplHeader {
+            trait_name: trait_name.map(str::to_string),
+            self_type: self_type.to_string(),
+            negative,
+        })
+    }
+
+    #[test]
+    fn impl_headers() {
+        assert_eq!(
+            parse_impl_header("impl<T: Fn() -> U, U> Trait for X<T>"),
+            header(Some("Trait"), "X<T>", false)
+        );
+        assert_eq!(
+            parse_impl_header("unsafe impl<T> Send for Wrapper<T>"),
+            header(Some("Send"), "Wrapper<T>", false)
+        );
+        assert_eq!(
+            parse_impl_header("impl !Send for Handle"),
+            header(Some("Send"), "Handle", true)
+        );
+        assert_eq!(
+            parse_impl_header("impl<T> Point<T>"),
+            header(None, "Point<T>", false)
+        );
+        assert_eq!(parse_impl_header("fn main()"), None);
+    }
+
+    #[test]
+    fn impl_headers_with_where_clauses() {
+        assert_eq!(
+            parse_impl_header(
+                "impl<T> fmt::Display for Wrapper<T>\nwhere\n    T: fmt::Display + Fn() -> u8,"
+            ),
+            header(Some("fmt::Display"), "Wrapper<T>", false)
+        );
+        // A header cut off at the `{` right after `where`.
+        assert_eq!(
+            parse_impl_header("default impl<T> Tr for Vec<T> where"),
+            header(Some("Tr"), "Vec<T>", false)
+        );
+        // `for` inside the generics is not the trait's `for`.
+        assert_eq!(
+            parse_impl_header("impl<F: for<'a> Fn(&'a str)> Run for Task<F>"),
+            header(Some("Run"), "Task<F>", false)
+        );
+    }
+
+    #[test]
+    fn supertraits() {
+        assert_eq!(
+            supertrait_bounds("pub trait A: B + for<'a> C<'a>"),
+            ["B", "C<'a>"]
+        );
+        assert_eq!(
+            supertrait_bounds("trait Ord: Eq + PartialOrd<Self> + 'static + ?Sized"),
+            ["Eq", "PartialOrd<Self>"]
+        );
+        assert_eq!(
+            supertrait_bounds("unsafe trait A<T: Fn() -> u8>: B<T> where Self: C, T: D"),
+            ["B<T>", "C"]
+        );
+        assert_eq!(
+            supertrait_bounds("trait A where Self: Sized + Clone"),
+            ["Sized", "Clone"]
+        );
+        assert!(supertrait_bounds("trait Plain").is_empty());
+        assert_eq!(bound_ident("std::cmp::PartialOrd<Self>"), "PartialOrd");
+        assert_eq!(bound_ident("FnMut(u8) -> u8"), "FnMut");
+    }
+
+    #[test]
+    fn item_keywords_and_names() {
+        let signature = "pub(crate) unsafe trait Send";
+        assert_eq!(item_keyword(signature), Some("trait"));
+        assert_eq!(item_name(signature, "trait").as_deref(), Some("Send"));
+        assert_eq!(
+            item_name("struct Point<T>", "struct").as_deref(),
+            Some("Point")
+        );
+        assert_eq!(item_keyword("fn main()"), None);
+        assert_eq!(find_word("Traits Trait", "Trait"), Some(7));
+    }
+
+    #[test]
+    fn headers_end_at_the_brace() {
+        let text = "#[derive(Debug)]\nimpl<T>\n    Iterator for Iter<T>\nwhere T: Copy {\n    type Item = T;\n}\n";
+        assert_eq!(
+            header_at(text, text.find("impl").unwrap()),
+            "impl<T> Iterator for Iter<T> where T: Copy"
+        );
+        let position = |line| crate::analyzer::protocol::Position { line, character: 0 };
+        let range = |start, end| Range {
+            start: position(start),
+            end: position(end),
+        };
+        assert_eq!(
+            impl_header(text, range(0, 5), range(2, 2)).as_deref(),
+            Some("impl<T> Iterator for Iter<T> where T: Copy")
+        );
+    }
+}
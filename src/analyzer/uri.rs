@@ -0,0 +1,336 @@
+use std::path::{Component, Path, PathBuf};
+
+/// The process's working directory, canonicalized. Relative paths in tool
+/// calls are taken relative to it.
+pub fn current_dir() -> PathBuf {
+    let dir = std::env::current_dir().unwrap_or_else(|_| PathBuf::from("."));
+    dir.canonicalize().unwrap_or(dir)
+}
+
+/// Turn a path from a tool call into an absolute path.
+///
+/// Relative paths are taken relative to `base`. Existing paths are
+/// canonicalized so every spelling of a file maps to the same URI; for paths
+/// that do not exist yet the nearest existing ancestor is, and the rest is
+/// normalized lexically.
+pub fn resolve_path(file_path: &str, base: &Path) -> PathBuf {
+    let path = Path::new(file_path);
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        base.join(path)
+    };
+    let absolute = normalize(&absolute);
+
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        if let Ok(canonical) = existing.canonicalize() {
+            return missing
+                .iter()
+                .rev()
+                .fold(canonical, |path, part| path.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                missing.push(name.to_os_string());
+                existing = parent;
+            }
+            _ => return absolute,
+        }
+    }
+}
+
+/// Refuse `path` unless it lies inside one of `roots`.
+pub fn check_in_roots(path: &Path, roots: &[PathBuf]) -> anyhow::Result<()> {
+    if roots.iter().any(|root| path.starts_with(root)) {
+        return Ok(());
+    }
+    let roots: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    Err(anyhow::anyhow!(
+        "{} is outside the workspace roots ({})",
+        path.display(),
+        roots.join(", ")
+    ))
+}
+
+/// Remove `.` and resolve `..` without touching the filesystem.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+/// The `file://` URI for an absolute path, percent-encoded.
+pub fn path_to_uri(path: &Path) -> String {
+    encode_path(&path_bytes(path), cfg!(windows))
+}
+
+/// The path a `file://` URI points at, or `None` for other schemes and for
+/// files on other hosts.
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    decode_uri(uri, cfg!(windows)).map(path_from_bytes)
+}
+
+/// `uri` spelled the way [`path_to_uri`] spells it. rust-analyzer leaves
+/// characters such as `+`, `@` and `(` unescaped and lowercases drive
+/// letters, so URIs it sends must go through this before being compared
+/// with ours. URIs that are not local files are returned as they are.
+pub fn normalize_uri(uri: &str) -> String {
+    respell(uri, cfg!(windows))
+}
+
+fn respell(uri: &str, windows: bool) -> String {
+    decode_uri(uri, windows).map_or_else(|| uri.to_string(), |raw| encode_path(&raw, windows))
+}
+
+/// The URI for the bytes of an absolute path. `windows` selects Windows
+/// syntax: drive letters, backslashes and `\\server\share` paths.
+fn encode_path(raw: &[u8], windows: bool) -> String {
+    let mut raw = raw.to_vec();
+    let mut has_host = false;
+    if windows {
+        // Canonicalized paths come back as `\\?\C:\x` or `\\?\UNC\server\x`.
+        if let Some(rest) = raw.strip_prefix(br"\\?\UNC\") {
+            raw = [&br"\\"[..], rest].concat();
+        } else if let Some(rest) = raw.strip_prefix(br"\\?\") {
+            raw = rest.to_vec();
+        }
+        for byte in raw.iter_mut().filter(|byte| **byte == b'\\') {
+            *byte = b'/';
+        }
+        // Drive letters are case-insensitive; canonical paths use capitals.
+        if raw.get(1) == Some(&b':') {
+            raw[0].make_ascii_uppercase();
+        }
+        // A share's server goes in the authority: `file://server/share/x`.
+        if let Some(rest) = raw.strip_prefix(b"//") {
+            raw = rest.to_vec();
+            has_host = true;
+        }
+    }
+
+    let mut uri = String::from("file://");
+    // Drive paths (`C:/...`) need a leading slash before the drive letter.
+    if !has_host && !raw.starts_with(b"/") {
+        uri.push('/');
+    }
+    for byte in raw {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                uri.push(byte as char)
+            }
+            b':' if windows => uri.push(':'),
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+/// The path bytes of a `file://` URI, in Windows syntax if `windows`.
+fn decode_uri(uri: &str, windows: bool) -> Option<Vec<u8>> {
+    let rest = uri.strip_prefix("file://")?;
+    let (host, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+    let mut bytes = percent_decode(path)?;
+    if !windows {
+        // `file://localhost/x` and `file:///x` are the same file.
+        return matches!(host, "" | "localhost").then_some(bytes);
+    }
+
+    // `/C:/x` is `C:/x`.
+    if bytes.get(2) == Some(&b':') && bytes.first() == Some(&b'/') {
+        bytes.remove(0);
+    }
+    if !matches!(host, "" | "localhost") {
+        bytes = [&b"//"[..], &percent_decode(host)?, &bytes].concat();
+    }
+    for byte in bytes.iter_mut().filter(|byte| **byte == b'/') {
+        *byte = b'\\';
+    }
+    Some(bytes)
+}
+
+/// Undo `%XX` escapes, failing on malformed ones.
+fn percent_decode(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(byte) = input.next() {
+        if byte == b'%' {
+            let hex = [input.next()?, input.next()?];
+            // `from_str_radix` would also take a sign, as in `%+f`.
+            if !hex.iter().all(u8::is_ascii_hexdigit) {
+                return None;
+            }
+            let hex = std::str::from_utf8(&hex).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+        } else {
+            bytes.push(byte);
+        }
+    }
+    Some(bytes)
+}
+
+/// A URI as a path for display, or the URI itself if it is not a file.
+pub fn uri_to_file_path(uri: &str) -> String {
+    match uri_to_path(uri) {
+        Some(path) => path.display().to_string(),
+        None => uri.to_string(),
+    }
+}
+
+#[cfg(unix)]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+fn path_bytes(path: &Path) -> Vec<u8> {
+    path.to_string_lossy().into_owned().into_bytes()
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(std::ffi::OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unix_round_trip(path: &str, uri: &str) {
+        assert_eq!(encode_path(path.as_bytes(), false), uri);
+        assert_eq!(decode_uri(uri, false).as_deref(), Some(path.as_bytes()));
+    }
+
+    #[test]
+    fn reserved_characters_are_escaped() {
+        unix_round_trip(
+            "/home/me/my crate/src/lib.rs",
+            "file:///home/me/my%20crate/src/lib.rs",
+        );
+        unix_round_trip("/tmp/a#b/100%.rs", "file:///tmp/a%23b/100%25.rs");
+        unix_round_trip("/tmp/a?b=c&d/x.rs", "file:///tmp/a%3Fb%3Dc%26d/x.rs");
+        unix_round_trip("/tmp/x:y.rs", "file:///tmp/x%3Ay.rs");
+    }
+
+    #[test]
+    fn non_ascii_paths_are_escaped_as_utf8() {
+        unix_round_trip("/home/zoë/lib.rs", "file:///home/zo%C3%AB/lib.rs");
+        unix_round_trip(
+            "/tmp/日本/😀.rs",
+            "file:///tmp/%E6%97%A5%E6%9C%AC/%F0%9F%98%80.rs",
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_round_trip_through_uris() {
+        for path in ["/tmp/my crate/#1/50%/é😀.rs", "/"] {
+            let path = Path::new(path);
+            assert_eq!(uri_to_path(&path_to_uri(path)).as_deref(), Some(path));
+        }
+    }
+
+    #[test]
+    fn decoding_accepts_lowercase_escapes_and_localhost() {
+        assert_eq!(
+            decode_uri("file:///tmp/my%2fcrate%c3%ab", false).as_deref(),
+            Some("/tmp/my/crateë".as_bytes())
+        );
+        assert_eq!(
+            decode_uri("file://localhost/tmp/x.rs", false).as_deref(),
+            Some(&b"/tmp/x.rs"[..])
+        );
+    }
+
+    #[test]
+    fn malformed_and_foreign_uris_are_rejected() {
+        for uri in [
+            "file:///tmp/%+f.rs",
+            "file:///tmp/%-1.rs",
+            "file:///tmp/%zz.rs",
+            "file:///tmp/%4",
+            "untitled:Untitled-1",
+            "https://example.com/x.rs",
+            "file://server/share/x.rs",
+        ] {
+            assert_eq!(decode_uri(uri, false), None, "{uri}");
+        }
+    }
+
+    #[test]
+    fn windows_drive_paths() {
+        assert_eq!(
+            encode_path(br"C:\Users\me\my crate\lib.rs", true),
+            "file:///C:/Users/me/my%20crate/lib.rs"
+        );
+        assert_eq!(
+            encode_path(br"\\?\C:\x\lib.rs", true),
+            "file:///C:/x/lib.rs"
+        );
+        for uri in ["file:///C:/x/lib.rs", "file:///C%3A/x/lib.rs"] {
+            assert_eq!(decode_uri(uri, true).as_deref(), Some(&br"C:\x\lib.rs"[..]));
+        }
+    }
+
+    #[test]
+    fn rust_analyzer_spellings_are_normalized() {
+        assert_eq!(
+            respell("file:///tmp/a+b/x@(1),y=z;w&v.rs", false),
+            "file:///tmp/a%2Bb/x%40%281%29%2Cy%3Dz%3Bw%26v.rs"
+        );
+        assert_eq!(
+            respell("file:///tmp/a+b/x.rs", false),
+            encode_path(b"/tmp/a+b/x.rs", false)
+        );
+        for uri in ["file:///c:/x/lib.rs", "file:///c%3A/x/lib.rs"] {
+            assert_eq!(respell(uri, true), "file:///C:/x/lib.rs");
+        }
+        assert_eq!(respell("untitled:Untitled-1", false), "untitled:Untitled-1");
+    }
+
+    #[test]
+    fn windows_unc_paths() {
+        assert_eq!(
+            encode_path(br"\\server\share\a b.rs", true),
+            "file://server/share/a%20b.rs"
+        );
+        assert_eq!(
+            encode_path(br"\\?\UNC\server\share\x.rs", true),
+            "file://server/share/x.rs"
+        );
+        assert_eq!(
+            decode_uri("file://server/share/a%20b.rs", true).as_deref(),
+            Some(&br"\\server\share\a b.rs"[..])
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn resolve_path_normalizes_relative_paths() {
+        let base = Path::new("/nonexistent/base");
+        assert_eq!(
+            resolve_path("src/../lib.rs", base),
+            Path::new("/nonexistent/base/lib.rs")
+        );
+        assert_eq!(
+            resolve_path("./a/./b.rs", base),
+            Path::new("/nonexistent/base/a/b.rs")
+        );
+    }
+}
@@ -1,20 +1,62 @@
 use anyhow::Result;
 use rmcp::{ServiceExt, transport::stdio};
+use rustmcp::analyzer::pool::max_analyzers_from_env;
+use rustmcp::analyzer::{AnalyzerConfig, PositionEncoding, RequestTimeouts};
 use rustmcp::server::RustMcpServer;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Initialize the rust-analyzer integration
-    let mut rust_server = RustMcpServer::new();
+    let config = AnalyzerConfig::from_env()?;
+    let rust_server = RustMcpServer::with_config(config);
+    rust_server.set_request_timeouts(RequestTimeouts::from_env()?);
+    rust_server.set_column_encoding(PositionEncoding::from_env()?);
+    rust_server.set_max_analyzers(max_analyzers_from_env()?);
     rust_server.start().await?;
 
     // Note: The #[tool] macros generate additional tools beyond our manual list
     println!("Starting Rust MCP Server");
     println!("Server running on stdio transport...");
 
+    // Run until the client disconnects or we are asked to stop, then take
+    // rust-analyzer down with us rather than leaving it orphaned.
+    tokio::select! {
+        result = serve(rust_server.clone()) => {
+            rust_server.shutdown().await;
+            result
+        }
+        signal = shutdown_signal() => {
+            rust_server.shutdown().await;
+            signal?;
+            // The stdio transport is still blocked reading stdin, which would
+            // keep the runtime from exiting; there is nothing left to wait for.
+            std::process::exit(0);
+        }
+    }
+}
+
+/// Serve MCP over stdio until the client goes away.
+async fn serve(rust_server: RustMcpServer) -> Result<()> {
     // Start the MCP server using the ServiceExt trait
     let service = rust_server.serve(stdio()).await?;
     service.waiting().await?;
+    Ok(())
+}
+
+/// Resolves on SIGINT, or SIGTERM on Unix.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
 
+    eprintln!("Received shutdown signal");
     Ok(())
 }
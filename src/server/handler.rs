@@ -3,20 +3,28 @@ use rmcp::{
     ServerHandler,
     handler::server::{router::tool::ToolRouter, tool::Parameters},
     model::{ErrorData as McpError, *},
+    service::{NotificationContext, Peer, RoleServer},
     tool, tool_handler, tool_router,
 };
 use serde_json::Value;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio_util::sync::CancellationToken;
 
-use crate::analyzer::RustAnalyzerClient;
+use crate::analyzer::uri::uri_to_path;
+use crate::analyzer::{AnalyzerConfig, AnalyzerPool, PositionEncoding, RequestTimeouts};
 use crate::server::parameters::*;
 use crate::tools::{execute_tool, get_tools};
 
 #[derive(Clone)]
 pub struct RustMcpServer {
-    analyzer: Arc<Mutex<RustAnalyzerClient>>,
+    analyzers: Arc<AnalyzerPool>,
     tool_router: ToolRouter<RustMcpServer>,
+    /// Counts roots refreshes, so a refresh overtaken by a newer one can
+    /// tell and drop its list.
+    roots_generation: Arc<AtomicU64>,
+    /// Held while a refreshed roots list is applied.
+    applying_roots: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl Default for RustMcpServer {
@@ -28,24 +36,65 @@ impl Default for RustMcpServer {
 #[tool_router]
 impl RustMcpServer {
     pub fn new() -> Self {
+        Self::with_config(AnalyzerConfig::default())
+    }
+
+    /// Create a server whose rust-analyzer instances use the given settings.
+    pub fn with_config(config: AnalyzerConfig) -> Self {
         Self {
-            analyzer: Arc::new(Mutex::new(RustAnalyzerClient::new())),
+            analyzers: Arc::new(AnalyzerPool::new(config)),
             tool_router: Self::tool_router(),
+            roots_generation: Arc::new(AtomicU64::new(0)),
+            applying_roots: Arc::new(tokio::sync::Mutex::new(())),
         }
     }
 
-    pub async fn start(&mut self) -> Result<()> {
-        let mut analyzer = self.analyzer.lock().await;
-        analyzer.start().await
+    pub async fn start(&self) -> Result<()> {
+        self.analyzers.start().await
+    }
+
+    /// Shut every rust-analyzer down. Call once the MCP session has ended.
+    pub async fn shutdown(&self) {
+        self.analyzers.shutdown().await;
+    }
+
+    pub fn set_request_timeouts(&self, timeouts: RequestTimeouts) {
+        self.analyzers.set_request_timeouts(timeouts);
+    }
+
+    pub fn set_column_encoding(&self, encoding: PositionEncoding) {
+        self.analyzers.set_column_encoding(encoding);
+    }
+
+    /// Cap how many rust-analyzer processes run at once.
+    pub fn set_max_analyzers(&self, max: usize) {
+        self.analyzers.set_max_instances(max);
     }
 
     pub fn list_tools(&self) -> Vec<crate::tools::ToolDefinition> {
         get_tools()
     }
 
-    pub async fn call_tool(&mut self, name: &str, args: Value) -> Result<crate::tools::ToolResult> {
-        let mut analyzer = self.analyzer.lock().await;
-        execute_tool(name, args, &mut analyzer).await
+    pub async fn call_tool(&self, name: &str, args: Value) -> Result<crate::tools::ToolResult> {
+        execute_tool(name, args, &self.analyzers).await
+    }
+
+    /// Run a tool until it finishes or the client cancels the call.
+    ///
+    /// Dropping the tool's future drops any analyzer requests it is waiting
+    /// on, which sends `$/cancelRequest` for them. An edit that has started
+    /// writing files finishes anyway; see
+    /// [`crate::tools::edits::apply_workspace_edit`].
+    async fn execute_cancellable(
+        &self,
+        name: &str,
+        args: Value,
+        ct: CancellationToken,
+    ) -> Result<crate::tools::ToolResult> {
+        tokio::select! {
+            result = execute_tool(name, args, &self.analyzers) => result,
+            _ = ct.cancelled() => Err(anyhow::anyhow!("{name} was cancelled by the client")),
+        }
     }
 
     #[tool(description = "Find the definition of a symbol at a given position")]
@@ -55,23 +104,25 @@ impl RustMcpServer {
             file_path,
             line,
             character,
+            wait_for_indexing_secs,
         }): Parameters<FindDefinitionParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
             "line": line,
-            "character": character
+            "character": character,
+            "wait_for_indexing_secs": wait_for_indexing_secs
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("find_definition", args, &mut analyzer).await {
+        match self.execute_cancellable("find_definition", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "No definition found",
@@ -83,6 +134,307 @@ impl RustMcpServer {
         }
     }
 
+    #[tool(
+        description = "Show the signature or type, documentation and memory layout of the item at a position, or of the expression covering a range"
+    )]
+    async fn hover(
+        &self,
+        Parameters(HoverParams {
+            file_path,
+            line,
+            character,
+            end_line,
+            end_character,
+            strip_markdown,
+            wait_for_indexing_secs,
+        }): Parameters<HoverParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "end_line": end_line,
+            "end_character": end_character,
+            "strip_markdown": strip_markdown,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("hover", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No hover information found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "List completion candidates at a position, best first, with kind, detail, docs, the text edit to apply and any auto-import edits"
+    )]
+    async fn complete(
+        &self,
+        Parameters(CompleteParams {
+            file_path,
+            line,
+            character,
+            content,
+            prefix,
+            max_results,
+            wait_for_indexing_secs,
+        }): Parameters<CompleteParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "content": content,
+            "prefix": prefix,
+            "max_results": max_results,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("complete", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No completions found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Show the signatures of the function call surrounding a position, with the active parameter marked and parameter docs"
+    )]
+    async fn signature_help(
+        &self,
+        Parameters(SignatureHelpParams {
+            file_path,
+            line,
+            character,
+            content,
+            wait_for_indexing_secs,
+        }): Parameters<SignatureHelpParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "content": content,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("signature_help", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No signature found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Find the implementations of the trait, type or trait method at a position, with a source snippet around each"
+    )]
+    async fn goto_implementation(
+        &self,
+        Parameters(GotoImplementationParams {
+            file_path,
+            line,
+            character,
+            context_lines,
+            wait_for_indexing_secs,
+        }): Parameters<GotoImplementationParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "context_lines": context_lines,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self
+            .execute_cancellable("goto_implementation", args, ct)
+            .await
+        {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No implementations found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Find the definition of the type of the expression at a position, with a source snippet around it"
+    )]
+    async fn goto_type_definition(
+        &self,
+        Parameters(GotoTypeDefinitionParams {
+            file_path,
+            line,
+            character,
+            context_lines,
+            wait_for_indexing_secs,
+        }): Parameters<GotoTypeDefinitionParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "context_lines": context_lines,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self
+            .execute_cancellable("goto_type_definition", args, ct)
+            .await
+        {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No type definition found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Find the declaration of the symbol at a position, with a source snippet around it"
+    )]
+    async fn goto_declaration(
+        &self,
+        Parameters(GotoDeclarationParams {
+            file_path,
+            line,
+            character,
+            context_lines,
+            wait_for_indexing_secs,
+        }): Parameters<GotoDeclarationParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "context_lines": context_lines,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("goto_declaration", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No declaration found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Expand the macro call, attribute macro or derive at a position recursively into formatted code; whole_item expands every macro in the item around it"
+    )]
+    async fn expand_macro(
+        &self,
+        Parameters(ExpandMacroParams {
+            file_path,
+            line,
+            character,
+            whole_item,
+            wait_for_indexing_secs,
+        }): Parameters<ExpandMacroParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "whole_item": whole_item,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("expand_macro", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No macro expansion found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
     #[tool(description = "Find all references to a symbol at a given position")]
     async fn find_references(
         &self,
@@ -90,23 +442,25 @@ impl RustMcpServer {
             file_path,
             line,
             character,
+            wait_for_indexing_secs,
         }): Parameters<FindReferencesParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
             "line": line,
-            "character": character
+            "character": character,
+            "wait_for_indexing_secs": wait_for_indexing_secs
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("find_references", args, &mut analyzer).await {
+        match self.execute_cancellable("find_references", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "No references found",
@@ -118,24 +472,36 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Get compiler diagnostics for a file")]
+    #[tool(
+        description = "Get compiler diagnostics for a file or the whole workspace, optionally filtered by severity"
+    )]
     async fn get_diagnostics(
         &self,
-        Parameters(GetDiagnosticsParams { file_path }): Parameters<GetDiagnosticsParams>,
+        Parameters(GetDiagnosticsParams {
+            file_path,
+            workspace_path,
+            severity,
+            include_fixes,
+            wait_for_indexing_secs,
+        }): Parameters<GetDiagnosticsParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
-            "file_path": file_path
+            "file_path": file_path,
+            "workspace_path": workspace_path,
+            "severity": severity,
+            "include_fixes": include_fixes,
+            "wait_for_indexing_secs": wait_for_indexing_secs
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("get_diagnostics", args, &mut analyzer).await {
+        match self.execute_cancellable("get_diagnostics", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "No diagnostics found",
@@ -150,21 +516,69 @@ impl RustMcpServer {
     #[tool(description = "Search for symbols in the workspace")]
     async fn workspace_symbols(
         &self,
-        Parameters(WorkspaceSymbolsParams { query }): Parameters<WorkspaceSymbolsParams>,
+        Parameters(WorkspaceSymbolsParams {
+            query,
+            workspace_path,
+            wait_for_indexing_secs,
+        }): Parameters<WorkspaceSymbolsParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
-            "query": query
+            "query": query,
+            "workspace_path": workspace_path,
+            "wait_for_indexing_secs": wait_for_indexing_secs
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("workspace_symbols", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("workspace_symbols", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No symbols found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Outline a file: nested modules, impls, functions, structs and fields with kinds, ranges and details, as JSON or a compact indented tree"
+    )]
+    async fn document_symbols(
+        &self,
+        Parameters(DocumentSymbolsParams {
+            file_path,
+            format,
+            max_depth,
+            wait_for_indexing_secs,
+        }): Parameters<DocumentSymbolsParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "format": format,
+            "max_depth": max_depth,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("document_symbols", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "No symbols found",
@@ -176,7 +590,54 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Rename a symbol with scope awareness")]
+    #[tool(
+        description = "Walk who calls the function at a position, or what it calls, to a given depth, as a tree or a flat list of caller/callee edges"
+    )]
+    async fn call_hierarchy(
+        &self,
+        Parameters(CallHierarchyParams {
+            file_path,
+            line,
+            character,
+            direction,
+            depth,
+            format,
+            wait_for_indexing_secs,
+        }): Parameters<CallHierarchyParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "direction": direction,
+            "depth": depth,
+            "format": format,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self.execute_cancellable("call_hierarchy", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No calls found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Rename a symbol everywhere it is used, writing the changes to disk; dry_run returns a diff instead"
+    )]
     async fn rename_symbol(
         &self,
         Parameters(RenameSymbolParams {
@@ -184,24 +645,28 @@ impl RustMcpServer {
             line,
             character,
             new_name,
+            dry_run,
+            on_conflict,
         }): Parameters<RenameSymbolParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
             "line": line,
             "character": character,
-            "new_name": new_name
+            "new_name": new_name,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("rename_symbol", args, &mut analyzer).await {
+        match self.execute_cancellable("rename_symbol", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Rename operation completed",
@@ -213,24 +678,146 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Apply rustfmt formatting to a file")]
+    #[tool(
+        description = "List the code actions (quick fixes, assists and refactorings) rust-analyzer offers at a position or range"
+    )]
+    async fn list_code_actions(
+        &self,
+        Parameters(ListCodeActionsParams {
+            file_path,
+            line,
+            character,
+            end_line,
+            end_character,
+            kinds,
+            include_edits,
+            wait_for_indexing_secs,
+        }): Parameters<ListCodeActionsParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "end_line": end_line,
+            "end_character": end_character,
+            "kinds": kinds,
+            "include_edits": include_edits,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self
+            .execute_cancellable("list_code_actions", args, ct)
+            .await
+        {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No code actions found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Apply a code action, chosen by title or by index from list_code_actions, writing its edits to disk; dry_run returns a diff instead"
+    )]
+    async fn apply_code_action(
+        &self,
+        Parameters(ApplyCodeActionParams {
+            file_path,
+            line,
+            character,
+            end_line,
+            end_character,
+            kinds,
+            title,
+            index,
+            dry_run,
+            on_conflict,
+            wait_for_indexing_secs,
+        }): Parameters<ApplyCodeActionParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "file_path": file_path,
+            "line": line,
+            "character": character,
+            "end_line": end_line,
+            "end_character": end_character,
+            "kinds": kinds,
+            "title": title,
+            "index": index,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict,
+            "wait_for_indexing_secs": wait_for_indexing_secs
+        });
+
+        match self
+            .execute_cancellable("apply_code_action", args, ct)
+            .await
+        {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "Code action applied",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Format a file with rustfmt, honouring rustfmt.toml and the crate's edition; optionally just a line range or the whole crate, and check mode only reports whether it is formatted; dry_run returns a diff instead"
+    )]
     async fn format_code(
         &self,
-        Parameters(FormatCodeParams { file_path }): Parameters<FormatCodeParams>,
+        Parameters(FormatCodeParams {
+            file_path,
+            start_line,
+            end_line,
+            check,
+            whole_crate,
+            dry_run,
+            on_conflict,
+        }): Parameters<FormatCodeParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
-            "file_path": file_path
+            "file_path": file_path,
+            "start_line": start_line,
+            "end_line": end_line,
+            "check": check,
+            "whole_crate": whole_crate,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("format_code", args, &mut analyzer).await {
+        match self.execute_cancellable("format_code", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Format operation completed",
@@ -246,20 +833,20 @@ impl RustMcpServer {
     async fn analyze_manifest(
         &self,
         Parameters(AnalyzeManifestParams { manifest_path }): Parameters<AnalyzeManifestParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "manifest_path": manifest_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("analyze_manifest", args, &mut analyzer).await {
+        match self.execute_cancellable("analyze_manifest", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Analysis completed",
@@ -275,20 +862,20 @@ impl RustMcpServer {
     async fn run_cargo_check(
         &self,
         Parameters(RunCargoCheckParams { workspace_path }): Parameters<RunCargoCheckParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "workspace_path": workspace_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("run_cargo_check", args, &mut analyzer).await {
+        match self.execute_cancellable("run_cargo_check", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Cargo check completed",
@@ -300,7 +887,9 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Extract selected code into a new function")]
+    #[tool(
+        description = "Extract selected code into a new function and rename it to function_name; dry_run returns the extraction's diff instead, before the rename"
+    )]
     async fn extract_function(
         &self,
         Parameters(ExtractFunctionParams {
@@ -310,7 +899,10 @@ impl RustMcpServer {
             end_line,
             end_character,
             function_name,
+            dry_run,
+            on_conflict,
         }): Parameters<ExtractFunctionParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
@@ -318,18 +910,19 @@ impl RustMcpServer {
             "start_character": start_character,
             "end_line": end_line,
             "end_character": end_character,
-            "function_name": function_name
+            "function_name": function_name,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("extract_function", args, &mut analyzer).await {
+        match self.execute_cancellable("extract_function", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Function extracted successfully",
@@ -350,6 +943,7 @@ impl RustMcpServer {
             derives,
             file_path,
         }): Parameters<GenerateStructParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "struct_name": struct_name,
@@ -358,15 +952,14 @@ impl RustMcpServer {
             "file_path": file_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("generate_struct", args, &mut analyzer).await {
+        match self.execute_cancellable("generate_struct", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Struct generated successfully",
@@ -387,6 +980,7 @@ impl RustMcpServer {
             derives,
             file_path,
         }): Parameters<GenerateEnumParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "enum_name": enum_name,
@@ -395,15 +989,14 @@ impl RustMcpServer {
             "file_path": file_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("generate_enum", args, &mut analyzer).await {
+        match self.execute_cancellable("generate_enum", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Enum generated successfully",
@@ -423,6 +1016,7 @@ impl RustMcpServer {
             struct_name,
             file_path,
         }): Parameters<GenerateTraitImplParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "trait_name": trait_name,
@@ -430,15 +1024,17 @@ impl RustMcpServer {
             "file_path": file_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("generate_trait_impl", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("generate_trait_impl", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Trait implementation generated successfully",
@@ -458,6 +1054,7 @@ impl RustMcpServer {
             file_path,
             test_cases,
         }): Parameters<GenerateTestsParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "target_function": target_function,
@@ -465,15 +1062,14 @@ impl RustMcpServer {
             "test_cases": test_cases
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("generate_tests", args, &mut analyzer).await {
+        match self.execute_cancellable("generate_tests", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Tests generated successfully",
@@ -485,30 +1081,36 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Inline a function call at specified position")]
+    #[tool(
+        description = "Inline the function call at a position, or every call when on the function itself; dry_run returns a diff instead"
+    )]
     async fn inline_function(
         &self,
         Parameters(InlineFunctionParams {
             file_path,
             line,
             character,
+            dry_run,
+            on_conflict,
         }): Parameters<InlineFunctionParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
             "line": line,
-            "character": character
+            "character": character,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("inline_function", args, &mut analyzer).await {
+        match self.execute_cancellable("inline_function", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Function inlined successfully",
@@ -529,6 +1131,7 @@ impl RustMcpServer {
             character,
             new_signature,
         }): Parameters<ChangeSignatureParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
@@ -537,15 +1140,14 @@ impl RustMcpServer {
             "new_signature": new_signature
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("change_signature", args, &mut analyzer).await {
+        match self.execute_cancellable("change_signature", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Signature changed successfully",
@@ -557,24 +1159,32 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Organize and sort import statements in a file")]
+    #[tool(
+        description = "Organize and sort import statements in a file; dry_run returns a diff instead"
+    )]
     async fn organize_imports(
         &self,
-        Parameters(OrganizeImportsParams { file_path }): Parameters<OrganizeImportsParams>,
+        Parameters(OrganizeImportsParams {
+            file_path,
+            dry_run,
+            on_conflict,
+        }): Parameters<OrganizeImportsParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
-            "file_path": file_path
+            "file_path": file_path,
+            "dry_run": dry_run,
+            "on_conflict": on_conflict
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("organize_imports", args, &mut analyzer).await {
+        match self.execute_cancellable("organize_imports", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Imports organized successfully",
@@ -592,20 +1202,23 @@ impl RustMcpServer {
         Parameters(ApplyClippySuggestionsParams { file_path }): Parameters<
             ApplyClippySuggestionsParams,
         >,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("apply_clippy_suggestions", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("apply_clippy_suggestions", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Clippy suggestions applied successfully",
@@ -621,20 +1234,23 @@ impl RustMcpServer {
     async fn validate_lifetimes(
         &self,
         Parameters(ValidateLifetimesParams { file_path }): Parameters<ValidateLifetimesParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("validate_lifetimes", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("validate_lifetimes", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Lifetimes validated successfully",
@@ -646,30 +1262,37 @@ impl RustMcpServer {
         }
     }
 
-    #[tool(description = "Get type hierarchy for a symbol at specified position")]
+    #[tool(
+        description = "Show the traits a type implements with each impl block, or a trait's supertraits and implementors, as a graph"
+    )]
     async fn get_type_hierarchy(
         &self,
         Parameters(GetTypeHierarchyParams {
             file_path,
             line,
             character,
+            wait_for_indexing_secs,
         }): Parameters<GetTypeHierarchyParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "file_path": file_path,
             "line": line,
-            "character": character
+            "character": character,
+            "wait_for_indexing_secs": wait_for_indexing_secs
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("get_type_hierarchy", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("get_type_hierarchy", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Type hierarchy retrieved successfully",
@@ -688,21 +1311,24 @@ impl RustMcpServer {
             query,
             workspace_path,
         }): Parameters<SuggestDependenciesParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "query": query,
             "workspace_path": workspace_path
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("suggest_dependencies", args, &mut analyzer).await {
+        match self
+            .execute_cancellable("suggest_dependencies", args, ct)
+            .await
+        {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Dependencies suggested successfully",
@@ -722,6 +1348,7 @@ impl RustMcpServer {
             module_path,
             is_public,
         }): Parameters<CreateModuleParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "module_name": module_name,
@@ -729,15 +1356,14 @@ impl RustMcpServer {
             "is_public": is_public
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("create_module", args, &mut analyzer).await {
+        match self.execute_cancellable("create_module", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Module created successfully",
@@ -757,6 +1383,7 @@ impl RustMcpServer {
             target_file,
             item_names,
         }): Parameters<MoveItemsParams>,
+        ct: CancellationToken,
     ) -> Result<CallToolResult, McpError> {
         let args = serde_json::json!({
             "source_file": source_file,
@@ -764,15 +1391,14 @@ impl RustMcpServer {
             "item_names": item_names
         });
 
-        let mut analyzer = self.analyzer.lock().await;
-        match execute_tool("move_items", args, &mut analyzer).await {
+        match self.execute_cancellable("move_items", args, ct).await {
             Ok(result) => {
-                if let Some(content) = result.content.first() {
-                    if let Some(text) = content.get("text") {
-                        return Ok(CallToolResult::success(vec![Content::text(
-                            text.as_str().unwrap_or("No result"),
-                        )]));
-                    }
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
                 }
                 Ok(CallToolResult::success(vec![Content::text(
                     "Items moved successfully",
@@ -783,6 +1409,150 @@ impl RustMcpServer {
             ))])),
         }
     }
+
+    #[tool(description = "Report whether rust-analyzer is still indexing, and how far along it is")]
+    async fn server_status(
+        &self,
+        Parameters(ServerStatusParams {
+            workspace_path,
+            wait_timeout_secs,
+        }): Parameters<ServerStatusParams>,
+        ct: CancellationToken,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "workspace_path": workspace_path,
+            "wait_timeout_secs": wait_timeout_secs
+        });
+
+        match self.execute_cancellable("server_status", args, ct).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No status available",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Kill and restart rust-analyzer for a workspace, or all of them, re-opening tracked documents"
+    )]
+    async fn restart_analyzer(
+        &self,
+        Parameters(RestartAnalyzerParams { workspace_path }): Parameters<RestartAnalyzerParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "workspace_path": workspace_path
+        });
+
+        match execute_tool("restart_analyzer", args, &self.analyzers).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "rust-analyzer restarted",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "List the file edits made this session, newest first, with the files each changed and their content hashes"
+    )]
+    async fn list_edits(&self) -> Result<CallToolResult, McpError> {
+        match execute_tool("list_edits", serde_json::json!({}), &self.analyzers).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text(
+                    "No edits found",
+                )]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Undo an edit made this session, the latest by default; refuses if any of its files changed since"
+    )]
+    async fn undo_edit(
+        &self,
+        Parameters(UndoEditParams { edit_id }): Parameters<UndoEditParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "edit_id": edit_id
+        });
+
+        // Not cancellable: stopping half way would leave files part restored.
+        match execute_tool("undo_edit", args, &self.analyzers).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text("Edit undone")]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
+
+    #[tool(
+        description = "Redo an undone edit, the most recently undone by default; refuses if any of its files changed since"
+    )]
+    async fn redo_edit(
+        &self,
+        Parameters(RedoEditParams { edit_id }): Parameters<RedoEditParams>,
+    ) -> Result<CallToolResult, McpError> {
+        let args = serde_json::json!({
+            "edit_id": edit_id
+        });
+
+        // Not cancellable: stopping half way would leave files part restored.
+        match execute_tool("redo_edit", args, &self.analyzers).await {
+            Ok(result) => {
+                if let Some(content) = result.content.first()
+                    && let Some(text) = content.get("text")
+                {
+                    return Ok(CallToolResult::success(vec![Content::text(
+                        text.as_str().unwrap_or("No result"),
+                    )]));
+                }
+                Ok(CallToolResult::success(vec![Content::text("Edit redone")]))
+            }
+            Err(e) => Ok(CallToolResult::success(vec![Content::text(format!(
+                "Error: {e}"
+            ))])),
+        }
+    }
 }
 
 #[tool_handler]
@@ -797,4 +1567,56 @@ impl ServerHandler for RustMcpServer {
             instructions: Some("Rust MCP Server providing rust-analyzer integration for idiomatic Rust development tools. Provides code analysis, refactoring, and project management capabilities.".to_string()),
         }
     }
+
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        self.refresh_roots(context.peer);
+    }
+
+    async fn on_roots_list_changed(&self, context: NotificationContext<RoleServer>) {
+        self.refresh_roots(context.peer);
+    }
+}
+
+impl RustMcpServer {
+    /// Ask the client for its roots and load their workspaces. This runs in
+    /// the background: the reply arrives through the same service loop that
+    /// delivered the notification. Refreshes are applied one at a time, and
+    /// one that a later refresh has overtaken is dropped, so the latest list
+    /// always wins.
+    fn refresh_roots(&self, peer: Peer<RoleServer>) {
+        let supports_roots = peer
+            .peer_info()
+            .is_some_and(|info| info.capabilities.roots.is_some());
+        if !supports_roots {
+            return;
+        }
+        let analyzers = self.analyzers.clone();
+        let latest = self.roots_generation.clone();
+        let applying = self.applying_roots.clone();
+        let generation = latest.fetch_add(1, Ordering::SeqCst) + 1;
+        tokio::spawn(async move {
+            let roots = match peer.list_roots().await {
+                Ok(result) => result.roots,
+                Err(e) => {
+                    eprintln!("Failed to list the client's roots: {e}");
+                    return;
+                }
+            };
+            let paths = roots
+                .iter()
+                .filter_map(|root| {
+                    let path = uri_to_path(&root.uri);
+                    if path.is_none() {
+                        eprintln!("Ignoring non-file root {}", root.uri);
+                    }
+                    path
+                })
+                .collect();
+            let _applying = applying.lock().await;
+            if latest.load(Ordering::SeqCst) != generation {
+                return;
+            }
+            analyzers.set_roots(paths).await;
+        });
+    }
 }
@@ -6,6 +6,52 @@ pub struct FindDefinitionParams {
     pub file_path: String,
     pub line: u32,
     pub character: u32,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GotoImplementationParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Lines of source to include before and after each location (default: 2)
+    pub context_lines: Option<u32>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GotoTypeDefinitionParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Lines of source to include before and after each location (default: 2)
+    pub context_lines: Option<u32>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GotoDeclarationParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Lines of source to include before and after each location (default: 2)
+    pub context_lines: Option<u32>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ExpandMacroParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Expand every macro call and attribute in the item containing the position
+    pub whole_item: Option<bool>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -13,16 +59,98 @@ pub struct FindReferencesParams {
     pub file_path: String,
     pub line: u32,
     pub character: u32,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
-pub struct GetDiagnosticsParams {
+pub struct HoverParams {
     pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// End of a range to describe the expression it covers instead of the item at a position
+    pub end_line: Option<u32>,
+    pub end_character: Option<u32>,
+    /// Return the documentation as plain text rather than markdown
+    pub strip_markdown: Option<bool>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CompleteParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Unsaved contents of the file to complete in instead of what is on disk
+    pub content: Option<String>,
+    /// Only return candidates starting with this; defaults to the identifier left of the cursor
+    pub prefix: Option<String>,
+    /// Maximum number of candidates to return (default: 50)
+    pub max_results: Option<u32>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct SignatureHelpParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// Unsaved contents of the file instead of what is on disk
+    pub content: Option<String>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct GetDiagnosticsParams {
+    /// File to report on; omit to report on the whole workspace
+    pub file_path: Option<String>,
+    /// Any path inside the workspace to report on when no file is given; defaults to the most recently used one
+    pub workspace_path: Option<String>,
+    /// Minimum severity to include: error, warning, information or hint
+    pub severity: Option<String>,
+    /// Ask rust-analyzer for the quick fixes attached to each diagnostic
+    pub include_fixes: Option<bool>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct WorkspaceSymbolsParams {
     pub query: String,
+    /// Any path inside the workspace to search; defaults to the most recently used one
+    pub workspace_path: Option<String>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct DocumentSymbolsParams {
+    pub file_path: String,
+    /// `json` (default) for nested objects, or `tree` for one indented line per symbol
+    pub format: Option<String>,
+    /// How deep to nest: 0 lists only top-level symbols, 1 adds their
+    /// members, and so on. Everything by default
+    pub max_depth: Option<u32>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct CallHierarchyParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// `incoming` (default) for callers, or `outgoing` for callees
+    pub direction: Option<String>,
+    /// How many levels of calls to follow (default: 1)
+    pub depth: Option<u32>,
+    /// `tree` (default) for nested calls, or `edges` for a flat caller/callee list
+    pub format: Option<String>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -31,11 +159,65 @@ pub struct RenameSymbolParams {
     pub line: u32,
     pub character: u32,
     pub new_name: String,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ListCodeActionsParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// End of a range to get the actions for a selection instead of a position
+    pub end_line: Option<u32>,
+    pub end_character: Option<u32>,
+    /// Only list actions of these kinds or their sub-kinds, e.g. "quickfix", "refactor.extract"
+    pub kinds: Option<Vec<String>>,
+    /// Resolve every action and include the edit it would make
+    pub include_edits: Option<bool>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ApplyCodeActionParams {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+    /// End of the range the action was listed for
+    pub end_line: Option<u32>,
+    pub end_character: Option<u32>,
+    /// The kinds the action was listed with, so indexes line up
+    pub kinds: Option<Vec<String>>,
+    /// Exact title of the action to apply
+    pub title: Option<String>,
+    /// Index of the action as returned by list_code_actions; used when no title is given
+    pub index: Option<u32>,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct FormatCodeParams {
     pub file_path: String,
+    /// First line of a range to format (0-based); needs end_line
+    pub start_line: Option<u32>,
+    /// Last line of the range, inclusive
+    pub end_line: Option<u32>,
+    /// Only report whether the code is formatted, with a diff of what would change
+    pub check: Option<bool>,
+    /// Format every Rust file in the crate containing file_path
+    pub whole_crate: Option<bool>,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -56,6 +238,10 @@ pub struct ExtractFunctionParams {
     pub end_line: u32,
     pub end_character: u32,
     pub function_name: String,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -93,6 +279,10 @@ pub struct InlineFunctionParams {
     pub file_path: String,
     pub line: u32,
     pub character: u32,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -106,6 +296,10 @@ pub struct ChangeSignatureParams {
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
 pub struct OrganizeImportsParams {
     pub file_path: String,
+    /// Return a unified diff of the changes instead of writing them
+    pub dry_run: Option<bool>,
+    /// If a file changes on disk before the edit is written: "abort" (default) or "merge" it in
+    pub on_conflict: Option<String>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -123,6 +317,8 @@ pub struct GetTypeHierarchyParams {
     pub file_path: String,
     pub line: u32,
     pub character: u32,
+    /// Wait up to this many seconds for rust-analyzer to finish indexing first
+    pub wait_for_indexing_secs: Option<u64>,
 }
 
 #[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
@@ -144,3 +340,29 @@ pub struct MoveItemsParams {
     pub target_file: String,
     pub item_names: Vec<String>,
 }
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct ServerStatusParams {
+    /// Any path inside a workspace; omit to report on every running rust-analyzer
+    pub workspace_path: Option<String>,
+    /// Block up to this many seconds for indexing to finish before reporting
+    pub wait_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct RestartAnalyzerParams {
+    /// Any path inside a workspace; omit to restart every running rust-analyzer
+    pub workspace_path: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct UndoEditParams {
+    /// Id of the edit from list_edits; defaults to the latest
+    pub edit_id: Option<u64>,
+}
+
+#[derive(Debug, serde::Deserialize, schemars::JsonSchema)]
+pub struct RedoEditParams {
+    /// Id of the edit from list_edits; defaults to the one undone most recently
+    pub edit_id: Option<u64>,
+}
@@ -1,11 +1,11 @@
 use crate::analyzer::RustAnalyzerClient;
-use crate::tools::types::ToolResult;
+use crate::tools::types::{ToolResult, wait_for_indexing_if_requested};
 use anyhow::Result;
 use serde_json::{Value, json};
 
 pub async fn get_type_hierarchy_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -20,6 +20,8 @@ pub async fn get_type_hierarchy_impl(
         .and_then(|v| v.as_u64())
         .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
 
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
     let result = analyzer
         .get_type_hierarchy(file_path, line as u32, character as u32)
         .await?;
@@ -39,7 +41,7 @@ pub async fn get_type_hierarchy_impl(
 
 pub async fn suggest_dependencies_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let query = args
         .get("query")
@@ -65,10 +67,7 @@ pub async fn suggest_dependencies_impl(
     })
 }
 
-pub async fn create_module_impl(
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
-) -> Result<ToolResult> {
+pub async fn create_module_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let module_name = args
         .get("module_name")
         .and_then(|v| v.as_str())
@@ -99,7 +98,7 @@ pub async fn create_module_impl(
     })
 }
 
-pub async fn move_items_impl(args: Value, analyzer: &mut RustAnalyzerClient) -> Result<ToolResult> {
+pub async fn move_items_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let source_file = args
         .get("source_file")
         .and_then(|v| v.as_str())
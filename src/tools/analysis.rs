@@ -1,11 +1,12 @@
 use crate::analyzer::RustAnalyzerClient;
-use crate::tools::types::ToolResult;
+use crate::analyzer::protocol::DiagnosticSeverity;
+use crate::tools::types::{ToolResult, wait_for_indexing_if_requested};
 use anyhow::Result;
 use serde_json::{Value, json};
 
 pub async fn find_definition_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -20,6 +21,8 @@ pub async fn find_definition_impl(
         .and_then(|v| v.as_u64())
         .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
 
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
     // Implementation will use rust-analyzer LSP to find definition
     let result = analyzer
         .find_definition(file_path, line as u32, character as u32)
@@ -38,9 +41,286 @@ pub async fn find_definition_impl(
     })
 }
 
+pub async fn hover_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let end_line = args.get("end_line").and_then(|v| v.as_u64());
+    let end_character = args.get("end_character").and_then(|v| v.as_u64());
+    let end = match (end_line, end_character) {
+        (Some(end_line), Some(end_character)) => Some((end_line as u32, end_character as u32)),
+        (None, None) => None,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "end_line and end_character must be given together"
+            ));
+        }
+    };
+    let strip_markdown = args
+        .get("strip_markdown")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .hover(
+            file_path,
+            (line as u32, character as u32),
+            end,
+            strip_markdown,
+        )
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn complete_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let content = args
+        .get("content")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+    let prefix = args.get("prefix").and_then(|v| v.as_str());
+    let max_results = args
+        .get("max_results")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(50) as usize;
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .complete(
+            file_path,
+            line as u32,
+            character as u32,
+            content,
+            prefix,
+            max_results,
+        )
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn signature_help_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let content = args
+        .get("content")
+        .and_then(|v| v.as_str())
+        .map(str::to_string);
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .signature_help(file_path, line as u32, character as u32, content)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn goto_implementation_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let (file_path, line, character, context_lines) = goto_args(&args)?;
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .goto_implementation(file_path, line, character, context_lines)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn goto_type_definition_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let (file_path, line, character, context_lines) = goto_args(&args)?;
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .goto_type_definition(file_path, line, character, context_lines)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn goto_declaration_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let (file_path, line, character, context_lines) = goto_args(&args)?;
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .goto_declaration(file_path, line, character, context_lines)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn expand_macro_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let whole_item = args
+        .get("whole_item")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .expand_macro(file_path, line as u32, character as u32, whole_item)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+/// File, position and `context_lines` (default 2) for the goto tools.
+fn goto_args(args: &Value) -> Result<(&str, u32, u32, u32)> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let context_lines = args
+        .get("context_lines")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(2);
+    Ok((
+        file_path,
+        line as u32,
+        character as u32,
+        context_lines as u32,
+    ))
+}
+
 pub async fn find_references_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -55,6 +335,8 @@ pub async fn find_references_impl(
         .and_then(|v| v.as_u64())
         .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
 
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
     // Implementation will use rust-analyzer LSP to find references
     let result = analyzer
         .find_references(file_path, line as u32, character as u32)
@@ -75,15 +357,25 @@ pub async fn find_references_impl(
 
 pub async fn get_diagnostics_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
-    let file_path = args
-        .get("file_path")
+    let file_path = args.get("file_path").and_then(|v| v.as_str());
+    let severity = args
+        .get("severity")
         .and_then(|v| v.as_str())
-        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+        .map(str::parse::<DiagnosticSeverity>)
+        .transpose()?;
+    let include_fixes = args
+        .get("include_fixes")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
 
-    // Implementation will use rust-analyzer LSP to get diagnostics
-    let result = analyzer.get_diagnostics(file_path).await?;
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    // Diagnostics come from rust-analyzer's publishDiagnostics notifications
+    let result = analyzer
+        .get_diagnostics(file_path, severity, include_fixes)
+        .await?;
 
     Ok(ToolResult {
         content: vec![
@@ -5,7 +5,7 @@ use serde_json::{Value, json};
 
 pub async fn analyze_manifest_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let manifest_path = args
         .get("manifest_path")
@@ -30,7 +30,7 @@ pub async fn analyze_manifest_impl(
 
 pub async fn run_cargo_check_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let workspace_path = args
         .get("workspace_path")
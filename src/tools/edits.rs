@@ -0,0 +1,1058 @@
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use similar::{ChangeTag, TextDiff};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use crate::analyzer::RustAnalyzerClient;
+use crate::analyzer::client::FileNotifier;
+use crate::analyzer::documents::apply_text_edits;
+use crate::analyzer::encoding::PositionEncoding;
+use crate::analyzer::journal::{FileRecord, content_hash};
+use crate::analyzer::protocol::{
+    DocumentChange, FileOperationOptions, ResourceOp, TextDocumentEdit, WorkspaceEdit,
+};
+use crate::analyzer::uri::{normalize_uri, path_to_uri, uri_to_path};
+
+/// New contents are written next to their file under this suffix first.
+const STAGED_SUFFIX: &str = ".rustmcp-staged";
+/// Originals are kept under this suffix until every file is in place.
+const BACKUP_SUFFIX: &str = ".rustmcp-backup";
+
+/// What to do when a file changed on disk after its edit was computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Write nothing and report the files that changed.
+    #[default]
+    Abort,
+    /// Three-way merge the edit onto the new contents, aborting only where
+    /// the two overlap.
+    Merge,
+}
+
+/// How a tool that edits files was asked to write them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EditOptions {
+    /// Only report what would change.
+    pub dry_run: bool,
+    pub on_conflict: OnConflict,
+    /// When the tool started asking rust-analyzer for the edit. A file it
+    /// has not opened that was modified after this may have been edited
+    /// against its old contents.
+    pub requested_at: Option<SystemTime>,
+}
+
+/// Apply a workspace edit to disk, all of it or none of it, tell
+/// rust-analyzer, and record it in the journal under `description`. With
+/// `dry_run` nothing is written and the result carries a unified diff
+/// instead.
+pub async fn apply_workspace_edit(
+    analyzer: &RustAnalyzerClient,
+    edit: WorkspaceEdit,
+    options: EditOptions,
+    description: String,
+) -> Result<Value> {
+    let mut plan = EditPlan::new(edit, analyzer, options.requested_at).await?;
+    if options.dry_run {
+        return Ok(json!({
+            "dry_run": true,
+            "files": plan.files_json(),
+            "diff": plan.diff(analyzer.workspace_root())
+        }));
+    }
+    let merged = plan.rebase(options.on_conflict).await?;
+
+    // A cancelled tool call drops this future. Once files are written the
+    // edit must still be journaled and rust-analyzer told, so the rest runs
+    // as a task of its own.
+    let notifier = analyzer.file_notifier();
+    let journal = analyzer.journal();
+    let (files, edit_id) = tokio::spawn(async move {
+        let records = plan.commit()?;
+        let edit_id = (!records.is_empty()).then(|| journal.record(description, records));
+        plan.notify(&notifier).await?;
+        anyhow::Ok((plan.files_json(), edit_id))
+    })
+    .await
+    .context("Writing the edit failed")??;
+
+    let mut result = json!({
+        "applied": true,
+        "files": files
+    });
+    if !merged.is_empty() {
+        result["merged"] = json!(
+            merged
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+        );
+    }
+    if let Some(edit_id) = edit_id {
+        result["edit_id"] = json!(edit_id);
+    }
+    Ok(result)
+}
+
+/// A file that no longer holds what its edit was computed against.
+#[derive(Debug)]
+pub struct ChangedFile {
+    pub path: PathBuf,
+    /// `created`, `deleted` or `modified`.
+    pub change: &'static str,
+    pub expected_sha256: Option<String>,
+    pub actual_sha256: Option<String>,
+    /// Why the edit could not be merged onto the new contents, if that was
+    /// tried.
+    pub merge_conflict: Option<String>,
+}
+
+impl ChangedFile {
+    fn new(
+        path: &Path,
+        expected: Option<&[u8]>,
+        actual: Option<&[u8]>,
+        merge_conflict: Option<String>,
+    ) -> Self {
+        let change = match (expected, actual) {
+            (Some(_), None) => "deleted",
+            (None, Some(_)) => "created",
+            _ => "modified",
+        };
+        Self {
+            path: path.to_path_buf(),
+            change,
+            expected_sha256: content_hash(expected),
+            actual_sha256: content_hash(actual),
+            merge_conflict,
+        }
+    }
+}
+
+/// Files changed on disk between computing an edit and writing it. Nothing
+/// was written.
+#[derive(Debug)]
+pub struct FileChangedError {
+    pub files: Vec<ChangedFile>,
+}
+
+impl FileChangedError {
+    pub fn to_json(&self) -> Value {
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                let mut json = json!({
+                    "file": file.path.display().to_string(),
+                    "change": file.change,
+                    "expected_sha256": file.expected_sha256,
+                    "actual_sha256": file.actual_sha256
+                });
+                if let Some(conflict) = &file.merge_conflict {
+                    json["merge_conflict"] = json!(conflict);
+                }
+                json
+            })
+            .collect();
+        let hint = match self.files.iter().any(|file| file.merge_conflict.is_some()) {
+            true => "The edit cannot be merged onto changes made since; request it again",
+            false => {
+                "Request the edit again, or pass on_conflict: \"merge\" to rebase it onto the new contents"
+            }
+        };
+        json!({
+            "error": "file_changed_underneath",
+            "files": files,
+            "hint": hint
+        })
+    }
+}
+
+impl std::fmt::Display for FileChangedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let json = serde_json::to_string_pretty(&self.to_json()).map_err(|_| std::fmt::Error)?;
+        write!(f, "File changed underneath, nothing was written:\n{json}")
+    }
+}
+
+impl std::error::Error for FileChangedError {}
+
+/// Put files back into the given states, as undo and redo do. Each target
+/// is the analyzer for the file's workspace, the file, and its contents or
+/// `None` to delete it. Each analyzer is told about its own files.
+pub async fn restore_files(
+    targets: Vec<(Arc<RustAnalyzerClient>, PathBuf, Option<Vec<u8>>)>,
+) -> Result<Vec<Value>> {
+    let mut files = Vec::with_capacity(targets.len());
+    let mut notifiers = Vec::with_capacity(targets.len());
+    for (analyzer, path, target) in targets {
+        analyzer.check_editable(&path)?;
+        notifiers.push(analyzer.file_notifier());
+        let before = read_if_exists(&path)
+            .await?
+            .map(|bytes| utf8(&path, bytes))
+            .transpose()?;
+        let after = target.map(|bytes| utf8(&path, bytes)).transpose()?;
+        files.push(FileChange {
+            path,
+            before,
+            after,
+            modified_since_request: false,
+        });
+    }
+    let plan = EditPlan {
+        index: HashMap::new(),
+        emptied_dirs: Vec::new(),
+        requested_at: None,
+        files,
+    };
+    plan.commit()?;
+    for (file, notifier) in plan.files.iter().zip(&notifiers) {
+        if file.before != file.after {
+            file.notify(notifier).await?;
+        }
+    }
+    Ok(plan.files_json())
+}
+
+fn utf8(path: &Path, bytes: Vec<u8>) -> Result<String> {
+    String::from_utf8(bytes).map_err(|_| anyhow::anyhow!("{} is not UTF-8", path.display()))
+}
+
+/// One file's contents before and after an edit. `None` means the file does
+/// not exist. `before` is what the edit was computed against, checked again
+/// before writing.
+pub struct FileChange {
+    pub path: PathBuf,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// The file is not open in rust-analyzer and was modified after the
+    /// edit was requested, so `before` was read too late to be trusted.
+    pub modified_since_request: bool,
+}
+
+impl FileChange {
+    /// Tell rust-analyzer the file now holds `after`.
+    async fn notify(&self, notifier: &FileNotifier) -> Result<()> {
+        match &self.after {
+            Some(text) => {
+                notifier
+                    .document_written(&self.path, text.clone(), self.before.is_some())
+                    .await
+            }
+            None => notifier.document_deleted(&self.path).await,
+        }
+    }
+
+    fn status(&self) -> &'static str {
+        match (&self.before, &self.after) {
+            (None, _) => "created",
+            (Some(_), None) => "deleted",
+            (Some(_), Some(_)) => "modified",
+        }
+    }
+}
+
+/// What a workspace edit does to each file, worked out in memory so it can
+/// be checked in full before anything on disk changes.
+pub struct EditPlan {
+    files: Vec<FileChange>,
+    index: HashMap<PathBuf, usize>,
+    /// Directories renamed or deleted as a whole. Their files are part of
+    /// the plan; the directories go once those have.
+    emptied_dirs: Vec<PathBuf>,
+    /// See [`EditOptions::requested_at`].
+    requested_at: Option<SystemTime>,
+}
+
+impl EditPlan {
+    pub async fn new(
+        edit: WorkspaceEdit,
+        analyzer: &RustAnalyzerClient,
+        requested_at: Option<SystemTime>,
+    ) -> Result<Self> {
+        let mut plan = Self {
+            files: Vec::new(),
+            index: HashMap::new(),
+            emptied_dirs: Vec::new(),
+            requested_at,
+        };
+        let encoding = analyzer.server_encoding();
+        for change in edit.into_document_changes() {
+            match change {
+                DocumentChange::Edit(edit) => plan.edit(edit, encoding, analyzer).await?,
+                DocumentChange::Operation(ResourceOp::Create { uri, options }) => {
+                    plan.create(&uri, options.unwrap_or_default(), analyzer)
+                        .await?
+                }
+                DocumentChange::Operation(ResourceOp::Rename {
+                    old_uri,
+                    new_uri,
+                    options,
+                }) => {
+                    plan.rename(&old_uri, &new_uri, options.unwrap_or_default(), analyzer)
+                        .await?
+                }
+                DocumentChange::Operation(ResourceOp::Delete { uri, options }) => {
+                    plan.delete(&uri, options.unwrap_or_default(), analyzer)
+                        .await?
+                }
+            }
+        }
+        Ok(plan)
+    }
+
+    /// The files whose contents actually change, in the order first touched.
+    pub fn changed(&self) -> impl Iterator<Item = &FileChange> {
+        self.files.iter().filter(|file| file.before != file.after)
+    }
+
+    /// `path` as it stands after the changes planned so far.
+    async fn file(
+        &mut self,
+        path: &Path,
+        analyzer: &RustAnalyzerClient,
+    ) -> Result<&mut FileChange> {
+        if let Some(&i) = self.index.get(path) {
+            return Ok(&mut self.files[i]);
+        }
+        analyzer.check_editable(path)?;
+        let is_file = tokio::fs::metadata(path)
+            .await
+            .map(|metadata| metadata.is_file())
+            .unwrap_or(false);
+        let uri = path_to_uri(path);
+        let mut modified_since_request = false;
+        let before = match is_file {
+            true => {
+                // An edit computed against unsaved contents would overwrite
+                // whatever the file holds with them.
+                if analyzer.has_unsaved_buffer(&uri).await {
+                    return Err(anyhow::anyhow!(
+                        "Cannot edit {}: rust-analyzer is analyzing unsaved contents for it, not the file on disk; request the edit again",
+                        path.display()
+                    ));
+                }
+                let before = analyzer
+                    .document_text(&uri)
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("Cannot read {}", path.display()))?;
+                // rust-analyzer read a file it has not opened for us itself,
+                // so what it computed the edit against is only known to
+                // match `before` if nothing wrote the file since.
+                if let Some(requested_at) = self.requested_at
+                    && analyzer.document_version(&uri).await.is_none()
+                {
+                    modified_since_request = modified_after(path, requested_at);
+                }
+                Some(before)
+            }
+            false => None,
+        };
+        self.index.insert(path.to_path_buf(), self.files.len());
+        self.files.push(FileChange {
+            path: path.to_path_buf(),
+            after: before.clone(),
+            before,
+            modified_since_request,
+        });
+        Ok(self.files.last_mut().expect("just pushed"))
+    }
+
+    /// Whether `path` exists once the changes planned so far are made.
+    async fn exists(&self, path: &Path) -> bool {
+        match self.index.get(path) {
+            Some(&i) => self.files[i].after.is_some(),
+            None => tokio::fs::try_exists(path).await.unwrap_or(false),
+        }
+    }
+
+    async fn edit(
+        &mut self,
+        edit: TextDocumentEdit,
+        encoding: PositionEncoding,
+        analyzer: &RustAnalyzerClient,
+    ) -> Result<()> {
+        let uri = &normalize_uri(&edit.text_document.uri);
+        let path = file_path_of(uri)?;
+        if let Some(version) = edit.text_document.version
+            && let Some(current) = analyzer.document_version(uri).await
+            && current != version
+        {
+            return Err(anyhow::anyhow!(
+                "{} changed after the edit was computed (version {version}, now {current}); request it again",
+                path.display()
+            ));
+        }
+        let file = self.file(&path, analyzer).await?;
+        let text = file
+            .after
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("Cannot edit {}: it does not exist", path.display()))?;
+        let text = apply_text_edits(text, &edit.edits, encoding)
+            .with_context(|| format!("Cannot edit {}", path.display()))?;
+        file.after = Some(text);
+        Ok(())
+    }
+
+    async fn create(
+        &mut self,
+        uri: &str,
+        options: FileOperationOptions,
+        analyzer: &RustAnalyzerClient,
+    ) -> Result<()> {
+        let path = file_path_of(uri)?;
+        if path.is_dir() && !self.index.contains_key(&path) {
+            return Err(anyhow::anyhow!(
+                "Cannot create {}: it is a directory",
+                path.display()
+            ));
+        }
+        let file = self.file(&path, analyzer).await?;
+        if file.after.is_some() && options.overwrite != Some(true) {
+            if options.ignore_if_exists == Some(true) {
+                return Ok(());
+            }
+            return Err(anyhow::anyhow!("{} already exists", path.display()));
+        }
+        file.after = Some(String::new());
+        Ok(())
+    }
+
+    async fn rename(
+        &mut self,
+        old_uri: &str,
+        new_uri: &str,
+        options: FileOperationOptions,
+        analyzer: &RustAnalyzerClient,
+    ) -> Result<()> {
+        let from = file_path_of(old_uri)?;
+        let to = file_path_of(new_uri)?;
+        if from == to {
+            return Ok(());
+        }
+        if self.exists(&to).await && options.overwrite != Some(true) {
+            if options.ignore_if_exists == Some(true) {
+                return Ok(());
+            }
+            return Err(anyhow::anyhow!("{} already exists", to.display()));
+        }
+
+        let moves = if from.is_dir() && !self.index.contains_key(&from) {
+            analyzer.check_editable(&from)?;
+            self.emptied_dirs.push(from.clone());
+            files_under(&from)?
+                .into_iter()
+                .map(|file| {
+                    let target = to.join(file.strip_prefix(&from).expect("listed under from"));
+                    (file, target)
+                })
+                .collect()
+        } else {
+            vec![(from, to)]
+        };
+        for (from, to) in moves {
+            let text = self
+                .file(&from, analyzer)
+                .await?
+                .after
+                .take()
+                .ok_or_else(|| {
+                    anyhow::anyhow!("Cannot move {}: it does not exist", from.display())
+                })?;
+            self.file(&to, analyzer).await?.after = Some(text);
+        }
+        Ok(())
+    }
+
+    async fn delete(
+        &mut self,
+        uri: &str,
+        options: FileOperationOptions,
+        analyzer: &RustAnalyzerClient,
+    ) -> Result<()> {
+        let path = file_path_of(uri)?;
+        if path.is_dir() && !self.index.contains_key(&path) {
+            analyzer.check_editable(&path)?;
+            let files = files_under(&path)?;
+            if !files.is_empty() && options.recursive != Some(true) {
+                return Err(anyhow::anyhow!(
+                    "Cannot delete {}: the directory is not empty",
+                    path.display()
+                ));
+            }
+            for file in files {
+                self.file(&file, analyzer).await?.after = None;
+            }
+            self.emptied_dirs.push(path);
+            return Ok(());
+        }
+        let file = self.file(&path, analyzer).await?;
+        if file.after.take().is_none() && options.ignore_if_not_exists != Some(true) {
+            return Err(anyhow::anyhow!(
+                "Cannot delete {}: it does not exist",
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Check that every changed file still holds what its edit was computed
+    /// against. Any that does not fails the plan, or with
+    /// [`OnConflict::Merge`] gets the edit merged onto its new contents.
+    /// Returns the files merged.
+    pub async fn rebase(&mut self, on_conflict: OnConflict) -> Result<Vec<PathBuf>> {
+        let mut merged = Vec::new();
+        let mut changed = Vec::new();
+        for file in self.files.iter_mut() {
+            if file.before == file.after {
+                continue;
+            }
+            let current = read_if_exists(&file.path).await?;
+            if file.modified_since_request {
+                // There is no trustworthy base to check against or merge
+                // from.
+                changed.push(ChangedFile {
+                    path: file.path.clone(),
+                    change: "modified",
+                    expected_sha256: None,
+                    actual_sha256: content_hash(current.as_deref()),
+                    merge_conflict: Some(
+                        "it was modified while the edit was being computed".to_string(),
+                    ),
+                });
+                continue;
+            }
+            if current.as_deref() == file.before.as_deref().map(str::as_bytes) {
+                continue;
+            }
+            let merge_conflict = match on_conflict {
+                OnConflict::Abort => None,
+                OnConflict::Merge => match merge_onto(file, current.as_deref()) {
+                    Ok(after) => {
+                        file.before = current.map(|bytes| utf8(&file.path, bytes)).transpose()?;
+                        file.after = after;
+                        merged.push(file.path.clone());
+                        continue;
+                    }
+                    Err(conflict) => Some(conflict),
+                },
+            };
+            changed.push(ChangedFile::new(
+                &file.path,
+                file.before.as_deref().map(str::as_bytes),
+                current.as_deref(),
+                merge_conflict,
+            ));
+        }
+        if !changed.is_empty() {
+            return Err(FileChangedError { files: changed }.into());
+        }
+        Ok(merged)
+    }
+
+    /// Each changed file with its status and how many lines were added and
+    /// removed.
+    pub fn files_json(&self) -> Vec<Value> {
+        self.changed()
+            .map(|file| {
+                let diff = TextDiff::from_lines(
+                    file.before.as_deref().unwrap_or(""),
+                    file.after.as_deref().unwrap_or(""),
+                );
+                let count = |tag| diff.iter_all_changes().filter(|c| c.tag() == tag).count();
+                json!({
+                    "file": file.path.display().to_string(),
+                    "status": file.status(),
+                    "lines_added": count(ChangeTag::Insert),
+                    "lines_removed": count(ChangeTag::Delete)
+                })
+            })
+            .collect()
+    }
+
+    /// A unified diff of every changed file, with paths relative to `root`
+    /// where possible so `git apply` takes it from there. Created and
+    /// deleted files are diffed against `/dev/null`.
+    pub fn diff(&self, root: &Path) -> String {
+        let mut out = String::new();
+        for file in self.changed() {
+            let name = match file.path.strip_prefix(root) {
+                Ok(relative) => format!("/{}", relative.display()),
+                Err(_) => file.path.display().to_string(),
+            };
+            let old_name = match file.before {
+                Some(_) => format!("a{name}"),
+                None => "/dev/null".to_string(),
+            };
+            let new_name = match file.after {
+                Some(_) => format!("b{name}"),
+                None => "/dev/null".to_string(),
+            };
+            let diff = TextDiff::from_lines(
+                file.before.as_deref().unwrap_or(""),
+                file.after.as_deref().unwrap_or(""),
+            );
+            out.push_str(
+                &diff
+                    .unified_diff()
+                    .context_radius(3)
+                    .header(&old_name, &new_name)
+                    .to_string(),
+            );
+        }
+        out
+    }
+
+    /// Write the plan to disk. New contents are staged beside their files,
+    /// then swapped in with the originals kept aside, so a failure part way
+    /// puts every file back as it was. Returns what each file held on disk
+    /// before and after.
+    ///
+    /// This blocks rather than awaiting, so it cannot be interrupted with
+    /// files half swapped.
+    pub fn commit(&self) -> Result<Vec<FileRecord>> {
+        let mut transaction = Transaction::default();
+        self.write(&mut transaction)?;
+        let records = transaction.finish();
+        for dir in &self.emptied_dirs {
+            remove_empty_dirs(dir);
+        }
+        Ok(records)
+    }
+
+    /// Tell rust-analyzer about every file the committed plan changed.
+    pub async fn notify(&self, notifier: &FileNotifier) -> Result<()> {
+        for file in self.changed() {
+            file.notify(notifier).await?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, transaction: &mut Transaction) -> Result<()> {
+        for file in self.changed() {
+            let Some(text) = &file.after else {
+                continue;
+            };
+            if let Some(parent) = file.path.parent() {
+                transaction.create_dir(parent)?;
+            }
+            let staged = sibling(&file.path, STAGED_SUFFIX);
+            transaction.staged.push(staged.clone());
+            std::fs::write(&staged, text)
+                .with_context(|| format!("Cannot write {}", staged.display()))?;
+        }
+
+        for file in self.changed() {
+            let original = match std::fs::read(&file.path) {
+                Ok(bytes) => Some(bytes),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(anyhow::anyhow!("Cannot read {}: {e}", file.path.display())),
+            };
+            // Last look before the file is replaced; anything written since
+            // the plan was checked would be lost.
+            let expected = file.before.as_deref().map(str::as_bytes);
+            if original.as_deref() != expected {
+                return Err(FileChangedError {
+                    files: vec![ChangedFile::new(
+                        &file.path,
+                        expected,
+                        original.as_deref(),
+                        None,
+                    )],
+                }
+                .into());
+            }
+            let backup = match original {
+                Some(_) => {
+                    let backup = sibling(&file.path, BACKUP_SUFFIX);
+                    std::fs::rename(&file.path, &backup)
+                        .with_context(|| format!("Cannot move {} aside", file.path.display()))?;
+                    Some(backup)
+                }
+                None => None,
+            };
+            transaction.swaps.push(Swap {
+                path: file.path.clone(),
+                backup,
+                placed: false,
+            });
+            transaction.records.push(FileRecord {
+                path: file.path.clone(),
+                before: original,
+                after: file.after.clone().map(String::into_bytes),
+            });
+            if file.after.is_some() {
+                std::fs::rename(sibling(&file.path, STAGED_SUFFIX), &file.path)
+                    .with_context(|| format!("Cannot replace {}", file.path.display()))?;
+                if let Some(swap) = transaction.swaps.last_mut() {
+                    swap.placed = true;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `file`'s edit redone on top of `current`, what is on disk now, by
+/// three-way merge with what the edit was computed against. Fails with the
+/// reason when the two cannot be combined.
+fn merge_onto(file: &FileChange, current: Option<&[u8]>) -> Result<Option<String>, String> {
+    let current = current
+        .map(|bytes| std::str::from_utf8(bytes).map_err(|_| "it is no longer UTF-8".to_string()))
+        .transpose()?;
+    match (file.before.as_deref(), file.after.as_deref(), current) {
+        (Some(base), Some(ours), Some(theirs)) => diffy::merge(base, ours, theirs)
+            .map(Some)
+            .map_err(|_| "the edit overlaps changes made since".to_string()),
+        (Some(_), None, Some(_)) => Err("the edit deletes it, but it was modified".to_string()),
+        (Some(_), _, None) => Err("it was deleted".to_string()),
+        (None, ours, theirs) if ours == theirs => Ok(ours.map(str::to_string)),
+        (None, _, _) => Err("it was created".to_string()),
+    }
+}
+
+/// Whether `path` was last modified after `time`. Unknown counts as no.
+fn modified_after(path: &Path, time: SystemTime) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| modified > time)
+}
+
+/// A file's bytes, or `None` if it does not exist.
+async fn read_if_exists(path: &Path) -> Result<Option<Vec<u8>>> {
+    match tokio::fs::read(path).await {
+        Ok(bytes) => Ok(Some(bytes)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(anyhow::anyhow!("Cannot read {}: {e}", path.display())),
+    }
+}
+
+/// A file moved aside during a commit.
+struct Swap {
+    path: PathBuf,
+    /// Where the original is kept, if there was one.
+    backup: Option<PathBuf>,
+    /// Whether the new contents are in place.
+    placed: bool,
+}
+
+/// Everything a commit has done so far, undone when dropped unless it was
+/// finished, as on any error.
+#[derive(Default)]
+struct Transaction {
+    staged: Vec<PathBuf>,
+    created_dirs: Vec<PathBuf>,
+    swaps: Vec<Swap>,
+    records: Vec<FileRecord>,
+    finished: bool,
+}
+
+impl Transaction {
+    /// Create `dir` and any missing parents, remembering the outermost one
+    /// created.
+    fn create_dir(&mut self, dir: &Path) -> Result<()> {
+        let mut outermost = None;
+        for ancestor in dir.ancestors() {
+            if std::fs::exists(ancestor).unwrap_or(true) {
+                break;
+            }
+            outermost = Some(ancestor.to_path_buf());
+        }
+        if let Some(outermost) = outermost {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Cannot create {}", dir.display()))?;
+            self.created_dirs.push(outermost);
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Vec<FileRecord> {
+        self.finished = true;
+        for backup in self.swaps.iter().filter_map(|swap| swap.backup.as_ref()) {
+            let _ = std::fs::remove_file(backup);
+        }
+        std::mem::take(&mut self.records)
+    }
+}
+
+impl Drop for Transaction {
+    /// Put every file back as it was.
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        for swap in self.swaps.iter().rev() {
+            if swap.placed {
+                let _ = std::fs::remove_file(&swap.path);
+            }
+            if let Some(backup) = &swap.backup {
+                let _ = std::fs::rename(backup, &swap.path);
+            }
+        }
+        for staged in &self.staged {
+            let _ = std::fs::remove_file(staged);
+        }
+        for dir in self.created_dirs.iter().rev() {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// `.name<suffix>` in the same directory as `path`, so renaming between the
+/// two never crosses filesystems.
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{name}{suffix}"))
+}
+
+/// The local path behind a URI in a workspace edit.
+fn file_path_of(uri: &str) -> Result<PathBuf> {
+    uri_to_path(uri).ok_or_else(|| anyhow::anyhow!("Cannot edit {uri}: not a file URI"))
+}
+
+/// Remove `dir` and the directories below it, deepest first, leaving any
+/// that still hold something, such as a file created since the plan was
+/// made.
+fn remove_empty_dirs(dir: &Path) {
+    let mut dirs = vec![dir.to_path_buf()];
+    let mut i = 0;
+    while let Some(next) = dirs.get(i) {
+        if let Ok(entries) = std::fs::read_dir(next) {
+            let children: Vec<PathBuf> = entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.is_dir())
+                .collect();
+            dirs.extend(children);
+        }
+        i += 1;
+    }
+    for dir in dirs.iter().rev() {
+        let _ = std::fs::remove_dir(dir);
+    }
+}
+
+/// Every file below `dir`, recursively.
+fn files_under(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries =
+            std::fs::read_dir(&dir).with_context(|| format!("Cannot list {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh, empty directory for one test.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rustmcp-edits-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn plan(files: Vec<FileChange>) -> EditPlan {
+        EditPlan {
+            files,
+            index: HashMap::new(),
+            emptied_dirs: Vec::new(),
+            requested_at: None,
+        }
+    }
+
+    fn change(path: &Path, before: Option<&str>, after: Option<&str>) -> FileChange {
+        FileChange {
+            path: path.to_path_buf(),
+            before: before.map(str::to_string),
+            after: after.map(str::to_string),
+            modified_since_request: false,
+        }
+    }
+
+    fn listing(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn commit_writes_creates_and_deletes() {
+        let dir = scratch_dir("commit");
+        let (a, b, c) = (dir.join("a.rs"), dir.join("new/b.rs"), dir.join("c.rs"));
+        std::fs::write(&a, "old a").unwrap();
+        std::fs::write(&c, "old c").unwrap();
+
+        let records = plan(vec![
+            change(&a, Some("old a"), Some("new a")),
+            change(&b, None, Some("new b")),
+            change(&c, Some("old c"), None),
+        ])
+        .commit()
+        .unwrap();
+
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "new a");
+        assert_eq!(std::fs::read_to_string(&b).unwrap(), "new b");
+        assert!(!c.exists());
+        assert_eq!(listing(&dir), ["a.rs", "new"]);
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[2].before.as_deref(), Some(&b"old c"[..]));
+        assert_eq!(records[2].after, None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_commit_puts_every_file_back() {
+        let dir = scratch_dir("rollback");
+        let (a, b, c) = (dir.join("a.rs"), dir.join("new/b.rs"), dir.join("c.rs"));
+        std::fs::write(&a, "old a").unwrap();
+        // Changed since the plan was made, so the commit fails on it after
+        // the first two files are in place.
+        std::fs::write(&c, "changed c").unwrap();
+
+        let error = plan(vec![
+            change(&a, Some("old a"), Some("new a")),
+            change(&b, None, Some("new b")),
+            change(&c, Some("old c"), Some("new c")),
+        ])
+        .commit()
+        .unwrap_err();
+
+        assert!(error.downcast_ref::<FileChangedError>().is_some());
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "old a");
+        assert_eq!(std::fs::read_to_string(&c).unwrap(), "changed c");
+        // No new file, created directory, staged copy or backup is left.
+        assert_eq!(listing(&dir), ["a.rs", "c.rs"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebase_passes_files_nobody_touched() {
+        let dir = scratch_dir("unchanged");
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "fn a() {}\n").unwrap();
+        let mut plan = plan(vec![change(&a, Some("fn a() {}\n"), Some("fn b() {}\n"))]);
+        assert!(plan.rebase(OnConflict::Abort).await.unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebase_refuses_a_changed_file_unless_merging() {
+        let dir = scratch_dir("abort");
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "changed\n").unwrap();
+        let mut plan = plan(vec![change(&a, Some("old\n"), Some("new\n"))]);
+        let error = plan.rebase(OnConflict::Abort).await.unwrap_err();
+        let error = error.downcast_ref::<FileChangedError>().unwrap();
+        assert_eq!(error.files[0].change, "modified");
+        assert_eq!(error.files[0].merge_conflict, None);
+        assert_eq!(
+            error.files[0].actual_sha256,
+            content_hash(Some(b"changed\n"))
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebase_merges_changes_that_do_not_overlap() {
+        let dir = scratch_dir("merge");
+        let a = dir.join("a.rs");
+        std::fs::write(&a, "one\ntwo\nthree\nfour\nFIVE\n").unwrap();
+        let mut plan = plan(vec![change(
+            &a,
+            Some("one\ntwo\nthree\nfour\nfive\n"),
+            Some("ONE\ntwo\nthree\nfour\nfive\n"),
+        )]);
+        assert_eq!(
+            plan.rebase(OnConflict::Merge).await.unwrap(),
+            vec![a.clone()]
+        );
+        let file = &plan.files[0];
+        assert_eq!(
+            file.before.as_deref(),
+            Some("one\ntwo\nthree\nfour\nFIVE\n")
+        );
+        assert_eq!(file.after.as_deref(), Some("ONE\ntwo\nthree\nfour\nFIVE\n"));
+        // The merged plan is checked against the new contents when written.
+        plan.commit().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&a).unwrap(),
+            "ONE\ntwo\nthree\nfour\nFIVE\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebase_refuses_to_merge_overlapping_changes() {
+        let dir = scratch_dir("conflict");
+        let (a, b) = (dir.join("a.rs"), dir.join("b.rs"));
+        std::fs::write(&a, "one\nTheirs\nthree\n").unwrap();
+        std::fs::write(&b, "b changed\n").unwrap();
+        let mut plan = plan(vec![
+            change(&a, Some("one\ntwo\nthree\n"), Some("one\nours\nthree\n")),
+            change(&b, Some("b\n"), None),
+        ]);
+        let error = plan.rebase(OnConflict::Merge).await.unwrap_err();
+        let error = error.downcast_ref::<FileChangedError>().unwrap();
+        let conflicts: Vec<_> = error
+            .files
+            .iter()
+            .map(|file| file.merge_conflict.as_deref().unwrap())
+            .collect();
+        assert_eq!(
+            conflicts,
+            [
+                "the edit overlaps changes made since",
+                "the edit deletes it, but it was modified"
+            ]
+        );
+        // Nothing is written.
+        assert_eq!(std::fs::read_to_string(&a).unwrap(), "one\nTheirs\nthree\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn rebase_refuses_files_modified_since_the_request() {
+        let dir = scratch_dir("requested");
+        let a = dir.join("a.rs");
+        let requested_at = SystemTime::now() - std::time::Duration::from_secs(60);
+        std::fs::write(&a, "read too late\n").unwrap();
+        assert!(modified_after(&a, requested_at));
+        assert!(!modified_after(&dir.join("missing.rs"), requested_at));
+
+        // The file matches what the plan read, but not necessarily what the
+        // edit was computed against, so it cannot be merged either.
+        let mut file = change(&a, Some("read too late\n"), Some("edited\n"));
+        file.modified_since_request = true;
+        let error = plan(vec![file])
+            .rebase(OnConflict::Merge)
+            .await
+            .unwrap_err();
+        let error = error.downcast_ref::<FileChangedError>().unwrap();
+        assert_eq!(error.files[0].expected_sha256, None);
+        assert_eq!(error.files[0].change, "modified");
+        assert!(error.files[0].merge_conflict.is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,96 @@
 use crate::analyzer::RustAnalyzerClient;
-use crate::tools::types::ToolResult;
-use anyhow::Result;
+use crate::analyzer::documents::{apply_text_edits, offset_to_position};
+use crate::analyzer::protocol::{
+    DocumentChange, OptionalVersionedTextDocumentIdentifier, Position, Range, TextDocumentEdit,
+    TextEdit, WorkspaceEdit,
+};
+use crate::analyzer::rustfmt::{
+    RustfmtConfig, crate_sources, find_crate_root, keep_changes_in_lines, run_rustfmt,
+};
+use crate::analyzer::uri::path_to_uri;
+use crate::tools::edits::apply_workspace_edit;
+use crate::tools::types::{ToolResult, edit_options};
+use anyhow::{Context, Result};
 use serde_json::{Value, json};
+use std::path::{Path, PathBuf};
 
-pub async fn format_code_impl(
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
-) -> Result<ToolResult> {
+/// A file's text, the document version it was read at, and its formatted
+/// form.
+struct FormattedFile {
+    path: PathBuf,
+    version: Option<i32>,
+    original: String,
+    formatted: String,
+}
+
+pub async fn format_code_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let start_line = args.get("start_line").and_then(|v| v.as_u64());
+    let end_line = args.get("end_line").and_then(|v| v.as_u64());
+    let check = args.get("check").and_then(|v| v.as_bool()).unwrap_or(false);
+    let whole_crate = args
+        .get("whole_crate")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    let mut options = edit_options(&args)?;
+    options.dry_run |= check;
+    let lines = match (start_line, end_line) {
+        (None, None) => None,
+        (Some(start), Some(end)) if start <= end => Some((start as usize, end as usize)),
+        (Some(_), Some(_)) => return Err(anyhow::anyhow!("start_line is after end_line")),
+        _ => {
+            return Err(anyhow::anyhow!(
+                "Pass both start_line and end_line to format a range"
+            ));
+        }
+    };
+    if whole_crate && lines.is_some() {
+        return Err(anyhow::anyhow!(
+            "A line range only applies to a single file, not whole_crate"
+        ));
+    }
 
-    // Implementation will use rust-analyzer LSP to format code
-    let result = analyzer.format_code(file_path).await?;
+    let path = analyzer.resolve_path(file_path);
+    let (formatted, description, mut report) = match whole_crate {
+        true => {
+            let root = find_crate_root(&path)
+                .ok_or_else(|| anyhow::anyhow!("{} is not inside a crate", path.display()))?;
+            let (formatted, failed) = format_crate(analyzer, &root).await?;
+            let mut report = json!({"formatter": "rustfmt"});
+            if !failed.is_empty() {
+                report["failed"] = json!(failed);
+            }
+            (
+                formatted,
+                format!("format_code crate {}", root.display()),
+                report,
+            )
+        }
+        false => {
+            let (mut file, report) = format_file(analyzer, file_path, &path).await?;
+            if let Some((start, end)) = lines {
+                file.formatted = keep_changes_in_lines(&file.original, &file.formatted, start, end);
+            }
+            (vec![file], format!("format_code {file_path}"), report)
+        }
+    };
+
+    let edit = replacement_edit(analyzer, formatted);
+    let result = apply_workspace_edit(analyzer, edit, options, description).await?;
+    if check {
+        let files = result["files"].clone();
+        report["check"] = json!(true);
+        report["formatted"] = json!(files.as_array().is_some_and(Vec::is_empty));
+        report["files"] = files;
+        report["diff"] = result["diff"].clone();
+    } else if let (Some(report), Value::Object(result)) = (report.as_object_mut(), result) {
+        report.extend(result);
+    }
+    let result = serde_json::to_string_pretty(&report)?;
 
     Ok(ToolResult {
         content: vec![
@@ -27,3 +104,111 @@ pub async fn format_code_impl(
         ],
     })
 }
+
+/// Format one file through rust-analyzer when it can, else with rustfmt run
+/// directly. The report says which formatter was used.
+async fn format_file(
+    analyzer: &RustAnalyzerClient,
+    file_path: &str,
+    path: &Path,
+) -> Result<(FormattedFile, Value)> {
+    let config = RustfmtConfig::discover(path);
+    let uri = path_to_uri(path);
+    let (original, formatted, report) = match analyzer
+        .formatting_edits(file_path, config.formatting_options())
+        .await
+    {
+        Ok((text, edits)) => {
+            let formatted = apply_text_edits(&text, &edits, analyzer.server_encoding())
+                .with_context(|| format!("Cannot apply formatting to {file_path}"))?;
+            (text, formatted, json!({"formatter": "rust-analyzer"}))
+        }
+        Err(e) => {
+            let text = analyzer
+                .document_text(&uri)
+                .await
+                .ok_or_else(|| anyhow::anyhow!("Cannot read {}", path.display()))?;
+            let formatted = run_rustfmt(&text, &config).await.map_err(|rustfmt| {
+                anyhow::anyhow!(
+                    "Cannot format {file_path}: rust-analyzer failed ({e:#}), and so did rustfmt ({rustfmt:#})"
+                )
+            })?;
+            let report = json!({"formatter": "rustfmt", "fallback_reason": e.to_string()});
+            (text, formatted, report)
+        }
+    };
+    let file = FormattedFile {
+        path: path.to_path_buf(),
+        version: analyzer.document_version(&uri).await,
+        original,
+        formatted,
+    };
+    Ok((file, report))
+}
+
+/// Format every source file in the crate at `root` with rustfmt run
+/// directly. Files rustfmt rejects are left out and returned with the
+/// reason.
+async fn format_crate(
+    analyzer: &RustAnalyzerClient,
+    root: &Path,
+) -> Result<(Vec<FormattedFile>, Vec<Value>)> {
+    let mut formatted = Vec::new();
+    let mut failed = Vec::new();
+    for path in crate_sources(root)? {
+        let uri = path_to_uri(&path);
+        let version = analyzer.document_version(&uri).await;
+        let Some(text) = analyzer.document_text(&uri).await else {
+            failed.push(json!({
+                "file": path.display().to_string(),
+                "error": "cannot be read"
+            }));
+            continue;
+        };
+        match run_rustfmt(&text, &RustfmtConfig::discover(&path)).await {
+            Ok(output) => formatted.push(FormattedFile {
+                path,
+                version,
+                original: text,
+                formatted: output,
+            }),
+            Err(e) => failed.push(json!({
+                "file": path.display().to_string(),
+                "error": e.to_string()
+            })),
+        }
+    }
+    Ok((formatted, failed))
+}
+
+/// An edit replacing the whole text of each file whose formatted form
+/// differs, refused if a file has changed since it was read.
+fn replacement_edit(analyzer: &RustAnalyzerClient, files: Vec<FormattedFile>) -> WorkspaceEdit {
+    let encoding = analyzer.server_encoding();
+    let changes = files
+        .into_iter()
+        .filter(|file| file.original != file.formatted)
+        .map(|file| {
+            DocumentChange::Edit(TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    uri: path_to_uri(&file.path),
+                    version: file.version,
+                },
+                edits: vec![TextEdit {
+                    range: Range {
+                        start: Position {
+                            line: 0,
+                            character: 0,
+                        },
+                        end: offset_to_position(&file.original, file.original.len(), encoding),
+                    },
+                    new_text: file.formatted,
+                }],
+            })
+        })
+        .collect();
+    WorkspaceEdit {
+        changes: None,
+        document_changes: Some(changes),
+    }
+}
@@ -5,7 +5,7 @@ use serde_json::{Value, json};
 
 pub async fn generate_struct_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let struct_name = args
         .get("struct_name")
@@ -42,10 +42,7 @@ pub async fn generate_struct_impl(
     })
 }
 
-pub async fn generate_enum_impl(
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
-) -> Result<ToolResult> {
+pub async fn generate_enum_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let enum_name = args
         .get("enum_name")
         .and_then(|v| v.as_str())
@@ -83,7 +80,7 @@ pub async fn generate_enum_impl(
 
 pub async fn generate_trait_impl_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let trait_name = args
         .get("trait_name")
@@ -115,10 +112,7 @@ pub async fn generate_trait_impl_impl(
     })
 }
 
-pub async fn generate_tests_impl(
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
-) -> Result<ToolResult> {
+pub async fn generate_tests_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let target_function = args
         .get("target_function")
         .and_then(|v| v.as_str())
@@ -0,0 +1,159 @@
+use crate::analyzer::AnalyzerPool;
+use crate::analyzer::journal::{EditTransaction, content_hash};
+use crate::tools::edits::restore_files;
+use crate::tools::types::ToolResult;
+use anyhow::Result;
+use serde_json::{Value, json};
+use std::path::PathBuf;
+
+pub async fn list_edits_impl(_args: Value, analyzers: &AnalyzerPool) -> Result<ToolResult> {
+    let result = serde_json::to_string_pretty(&analyzers.journal().to_json())?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn undo_edit_impl(args: Value, analyzers: &AnalyzerPool) -> Result<ToolResult> {
+    let edit_id = args.get("edit_id").and_then(|v| v.as_u64());
+    let transaction = analyzers.journal().undo_candidate(edit_id)?;
+
+    // Undo only what the edit left behind; anything changed since would be
+    // lost.
+    let expected: Vec<_> = transaction
+        .files
+        .iter()
+        .map(|file| (file.path.clone(), file.after.as_deref()))
+        .collect();
+    check_unchanged(&transaction, &expected, "undo").await?;
+
+    let targets = transaction
+        .files
+        .iter()
+        .map(|file| (file.path.clone(), file.before.clone()))
+        .collect();
+    let files = restore(analyzers, targets).await?;
+    analyzers.journal().mark_undone(transaction.id);
+
+    let result = serde_json::to_string_pretty(&json!({
+        "undone": transaction.id,
+        "description": transaction.description,
+        "files": files
+    }))?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn redo_edit_impl(args: Value, analyzers: &AnalyzerPool) -> Result<ToolResult> {
+    let edit_id = args.get("edit_id").and_then(|v| v.as_u64());
+    let transaction = analyzers.journal().redo_candidate(edit_id)?;
+
+    let expected: Vec<_> = transaction
+        .files
+        .iter()
+        .map(|file| (file.path.clone(), file.before.as_deref()))
+        .collect();
+    check_unchanged(&transaction, &expected, "redo").await?;
+
+    let targets = transaction
+        .files
+        .iter()
+        .map(|file| (file.path.clone(), file.after.clone()))
+        .collect();
+    let files = restore(analyzers, targets).await?;
+    analyzers.journal().mark_redone(transaction.id);
+
+    let result = serde_json::to_string_pretty(&json!({
+        "redone": transaction.id,
+        "description": transaction.description,
+        "files": files
+    }))?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+/// Refuse to `action` a transaction unless every file still holds what it
+/// is expected to, listing each one that does not.
+async fn check_unchanged(
+    transaction: &EditTransaction,
+    expected: &[(PathBuf, Option<&[u8]>)],
+    action: &str,
+) -> Result<()> {
+    let mut conflicts = Vec::new();
+    for (path, contents) in expected {
+        let current = match tokio::fs::read(path).await {
+            Ok(bytes) => Some(bytes),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(anyhow::anyhow!("Cannot read {}: {e}", path.display())),
+        };
+        let expected_hash = content_hash(*contents);
+        let current_hash = content_hash(current.as_deref());
+        if expected_hash == current_hash {
+            continue;
+        }
+        let change = match (expected_hash, current_hash) {
+            (Some(_), None) => "deleted".to_string(),
+            (None, Some(_)) => "created".to_string(),
+            (_, current) => format!("modified, sha256 now {}", current.unwrap_or_default()),
+        };
+        conflicts.push(format!("{} ({change})", path.display()));
+    }
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    Err(anyhow::anyhow!(
+        "Cannot {action} edit {} ({}): {} changed since: {}",
+        transaction.id,
+        transaction.description,
+        if conflicts.len() == 1 {
+            "a file has"
+        } else {
+            "files have"
+        },
+        conflicts.join(", ")
+    ))
+}
+
+/// Write `targets`, each through the analyzer for its own workspace, so
+/// every rust-analyzer involved hears about the change.
+async fn restore(
+    analyzers: &AnalyzerPool,
+    targets: Vec<(PathBuf, Option<Vec<u8>>)>,
+) -> Result<Vec<Value>> {
+    let mut routed = Vec::with_capacity(targets.len());
+    for (path, target) in targets {
+        let analyzer = analyzers
+            .for_path(Some(&path.display().to_string()))
+            .await?;
+        routed.push((analyzer, path, target));
+    }
+    restore_files(routed).await
+}
@@ -1,11 +1,14 @@
 pub mod advanced;
 pub mod analysis;
 pub mod cargo;
+pub mod edits;
 pub mod formatting;
 pub mod generation;
+pub mod history;
 pub mod navigation;
 pub mod quality;
 pub mod refactoring;
+pub mod status;
 pub mod types;
 
 pub use types::*;
@@ -16,6 +19,8 @@ pub use analysis::*;
 pub use cargo::*;
 pub use formatting::*;
 pub use generation::*;
+pub use history::*;
 pub use navigation::*;
 pub use quality::*;
 pub use refactoring::*;
+pub use status::*;
@@ -1,17 +1,20 @@
 use crate::analyzer::RustAnalyzerClient;
-use crate::tools::types::ToolResult;
+use crate::analyzer::call_hierarchy::CallDirection;
+use crate::tools::types::{ToolResult, wait_for_indexing_if_requested};
 use anyhow::Result;
 use serde_json::{Value, json};
 
 pub async fn workspace_symbols_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let query = args
         .get("query")
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing query parameter"))?;
 
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
     // Implementation will use rust-analyzer LSP to search workspace symbols
     let result = analyzer.workspace_symbols(query).await?;
 
@@ -27,3 +30,91 @@ pub async fn workspace_symbols_impl(
         ],
     })
 }
+
+pub async fn document_symbols_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let tree = match args.get("format").and_then(|v| v.as_str()) {
+        None | Some("json") => false,
+        Some("tree") => true,
+        Some(other) => return Err(anyhow::anyhow!("Unknown format: {other}")),
+    };
+    let max_depth = args
+        .get("max_depth")
+        .and_then(|v| v.as_u64())
+        .map(|depth| depth as usize);
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .document_symbols(file_path, tree, max_depth)
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn call_hierarchy_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let direction: CallDirection = args
+        .get("direction")
+        .and_then(|v| v.as_str())
+        .unwrap_or("incoming")
+        .parse()?;
+    let depth = args.get("depth").and_then(|v| v.as_u64()).unwrap_or(1) as usize;
+    let edges = match args.get("format").and_then(|v| v.as_str()) {
+        None | Some("tree") => false,
+        Some("edges") => true,
+        Some(other) => return Err(anyhow::anyhow!("Unknown format: {other}")),
+    };
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let result = analyzer
+        .call_hierarchy(
+            file_path,
+            line as u32,
+            character as u32,
+            direction,
+            depth,
+            edges,
+        )
+        .await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
@@ -5,7 +5,7 @@ use serde_json::{Value, json};
 
 pub async fn apply_clippy_suggestions_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -29,7 +29,7 @@ pub async fn apply_clippy_suggestions_impl(
 
 pub async fn validate_lifetimes_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
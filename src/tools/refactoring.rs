@@ -1,12 +1,14 @@
 use crate::analyzer::RustAnalyzerClient;
-use crate::tools::types::ToolResult;
+use crate::analyzer::client::ActionChoice;
+use crate::analyzer::encoding;
+use crate::tools::edits::{EditOptions, apply_workspace_edit};
+use crate::tools::types::{ToolResult, edit_options, wait_for_indexing_if_requested};
 use anyhow::Result;
 use serde_json::{Value, json};
+use similar::{ChangeTag, TextDiff};
+use std::time::SystemTime;
 
-pub async fn rename_symbol_impl(
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
-) -> Result<ToolResult> {
+pub async fn rename_symbol_impl(args: Value, analyzer: &RustAnalyzerClient) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
         .and_then(|v| v.as_str())
@@ -24,9 +26,52 @@ pub async fn rename_symbol_impl(
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing new_name parameter"))?;
 
-    // Implementation will use rust-analyzer LSP to rename symbol
+    let options = edit_options(&args)?;
+
+    let edit = analyzer
+        .rename_edit(file_path, line as u32, character as u32, new_name)
+        .await?;
+    let result = apply_workspace_edit(
+        analyzer,
+        edit,
+        options,
+        format!("rename_symbol {file_path}:{line}:{character} to {new_name}"),
+    )
+    .await?;
+    let result = serde_json::to_string_pretty(&result)?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn list_code_actions_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let CodeActionArgs {
+        file_path,
+        start,
+        end,
+        kinds,
+    } = code_action_args(&args)?;
+    let include_edits = args
+        .get("include_edits")
+        .and_then(|v| v.as_bool())
+        .unwrap_or(false);
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
     let result = analyzer
-        .rename_symbol(file_path, line as u32, character as u32, new_name)
+        .list_code_actions(file_path, start, end, &kinds, include_edits)
         .await?;
 
     Ok(ToolResult {
@@ -42,9 +87,104 @@ pub async fn rename_symbol_impl(
     })
 }
 
+pub async fn apply_code_action_impl(
+    args: Value,
+    analyzer: &RustAnalyzerClient,
+) -> Result<ToolResult> {
+    let CodeActionArgs {
+        file_path,
+        start,
+        end,
+        kinds,
+    } = code_action_args(&args)?;
+    let titles: Vec<&str> = args
+        .get("title")
+        .and_then(|v| v.as_str())
+        .into_iter()
+        .collect();
+    let choice = match args.get("index").and_then(|v| v.as_u64()) {
+        _ if !titles.is_empty() => ActionChoice::Titles(&titles),
+        Some(index) => ActionChoice::Index(index as usize),
+        None => return Err(anyhow::anyhow!("Missing title or index parameter")),
+    };
+    let options = edit_options(&args)?;
+
+    wait_for_indexing_if_requested(&args, analyzer).await;
+
+    let (action, edit) = analyzer
+        .code_action_edit(file_path, start, end, &kinds, choice)
+        .await?;
+    let mut result = apply_workspace_edit(
+        analyzer,
+        edit,
+        options,
+        format!("apply_code_action \"{}\" in {file_path}", action.title),
+    )
+    .await?;
+    result["action"] = json!(action.title);
+    let result = serde_json::to_string_pretty(&result)?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+/// The arguments both code action tools take.
+struct CodeActionArgs<'a> {
+    file_path: &'a str,
+    start: (u32, u32),
+    end: Option<(u32, u32)>,
+    kinds: Vec<&'a str>,
+}
+
+fn code_action_args(args: &Value) -> Result<CodeActionArgs<'_>> {
+    let file_path = args
+        .get("file_path")
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
+    let line = args
+        .get("line")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing line parameter"))?;
+    let character = args
+        .get("character")
+        .and_then(|v| v.as_u64())
+        .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
+    let end_line = args.get("end_line").and_then(|v| v.as_u64());
+    let end_character = args.get("end_character").and_then(|v| v.as_u64());
+    let end = match (end_line, end_character) {
+        (Some(end_line), Some(end_character)) => Some((end_line as u32, end_character as u32)),
+        (None, None) => None,
+        _ => {
+            return Err(anyhow::anyhow!(
+                "end_line and end_character must be given together"
+            ));
+        }
+    };
+    let kinds = args
+        .get("kinds")
+        .and_then(|v| v.as_array())
+        .map(|kinds| kinds.iter().filter_map(|v| v.as_str()).collect())
+        .unwrap_or_default();
+    Ok(CodeActionArgs {
+        file_path,
+        start: (line as u32, character as u32),
+        end,
+        kinds,
+    })
+}
+
 pub async fn extract_function_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -71,34 +211,91 @@ pub async fn extract_function_impl(
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing function_name parameter"))?;
 
-    // Implementation will use rust-analyzer LSP to extract function
-    let result = analyzer
-        .extract_function(
+    let options = edit_options(&args)?;
+
+    let (_, edit) = analyzer
+        .code_action_edit(
             file_path,
-            start_line as u32,
-            start_character as u32,
-            end_line as u32,
-            end_character as u32,
-            function_name,
+            (start_line as u32, start_character as u32),
+            Some((end_line as u32, end_character as u32)),
+            &["refactor.extract"],
+            ActionChoice::Titles(&["Extract into function"]),
         )
         .await?;
+    let description = format!("extract_function {function_name} in {file_path}");
+    if options.dry_run {
+        let result = apply_workspace_edit(analyzer, edit, options, description).await?;
+        return Ok(text_result(serde_json::to_string_pretty(&result)?));
+    }
 
-    Ok(ToolResult {
+    // rust-analyzer gives the new function a placeholder name, so extract
+    // first and then rename it properly, which also renames its call.
+    let uri = analyzer.open_document(file_path).await?;
+    let before = analyzer.document_text(&uri).await.unwrap_or_default();
+    let extracted = apply_workspace_edit(analyzer, edit, options, description).await?;
+    let after = analyzer.document_text(&uri).await.unwrap_or_default();
+    let undo_hint = match extracted.get("edit_id") {
+        Some(id) => format!("undo edit {id} to revert it"),
+        None => "undo_edit can revert it".to_string(),
+    };
+    let (line, column, placeholder) = new_function(&before, &after).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Extracted the function, but could not find it in {file_path} to rename it; {undo_hint}"
+        )
+    })?;
+    if placeholder == function_name {
+        return Ok(text_result(serde_json::to_string_pretty(&extracted)?));
+    }
+
+    let character = analyzer
+        .column_encoding()
+        .len(&encoding::line_text(&after, line)[..column]) as u32;
+    let options = EditOptions {
+        requested_at: Some(SystemTime::now()),
+        ..options
+    };
+    let renamed = async {
+        let edit = analyzer
+            .rename_edit(file_path, line, character, function_name)
+            .await?;
+        apply_workspace_edit(
+            analyzer,
+            edit,
+            options,
+            format!("rename_symbol {placeholder} to {function_name} in {file_path}"),
+        )
+        .await
+    }
+    .await
+    .map_err(|e| {
+        anyhow::anyhow!(
+            "Extracted the function as {placeholder}, but renaming it failed: {e:#}; {undo_hint}"
+        )
+    })?;
+    let result = json!({
+        "extract": extracted,
+        "rename": renamed
+    });
+    Ok(text_result(serde_json::to_string_pretty(&result)?))
+}
+
+fn text_result(text: String) -> ToolResult {
+    ToolResult {
         content: vec![
             json!({
                 "type": "text",
-                "text": result
+                "text": text
             })
             .as_object()
             .unwrap()
             .clone(),
         ],
-    })
+    }
 }
 
 pub async fn inline_function_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -113,9 +310,27 @@ pub async fn inline_function_impl(
         .and_then(|v| v.as_u64())
         .ok_or_else(|| anyhow::anyhow!("Missing character parameter"))?;
 
-    let result = analyzer
-        .inline_function(file_path, line as u32, character as u32)
+    let options = edit_options(&args)?;
+
+    // At a call site rust-analyzer offers to inline that call; on the
+    // function itself, every call.
+    let (_, edit) = analyzer
+        .code_action_edit(
+            file_path,
+            (line as u32, character as u32),
+            None,
+            &["refactor.inline"],
+            ActionChoice::Titles(&["Inline call", "Inline into all callers"]),
+        )
         .await?;
+    let result = apply_workspace_edit(
+        analyzer,
+        edit,
+        options,
+        format!("inline_function {file_path}:{line}:{character}"),
+    )
+    .await?;
+    let result = serde_json::to_string_pretty(&result)?;
 
     Ok(ToolResult {
         content: vec![
@@ -132,7 +347,7 @@ pub async fn inline_function_impl(
 
 pub async fn change_signature_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
@@ -170,14 +385,32 @@ pub async fn change_signature_impl(
 
 pub async fn organize_imports_impl(
     args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    analyzer: &RustAnalyzerClient,
 ) -> Result<ToolResult> {
     let file_path = args
         .get("file_path")
         .and_then(|v| v.as_str())
         .ok_or_else(|| anyhow::anyhow!("Missing file_path parameter"))?;
 
-    let result = analyzer.organize_imports(file_path).await?;
+    let options = edit_options(&args)?;
+
+    let (_, edit) = analyzer
+        .code_action_edit(
+            file_path,
+            (0, 0),
+            None,
+            &["source.organizeImports"],
+            ActionChoice::Index(0),
+        )
+        .await?;
+    let result = apply_workspace_edit(
+        analyzer,
+        edit,
+        options,
+        format!("organize_imports {file_path}"),
+    )
+    .await?;
+    let result = serde_json::to_string_pretty(&result)?;
 
     Ok(ToolResult {
         content: vec![
@@ -191,3 +424,70 @@ pub async fn organize_imports_impl(
         ],
     })
 }
+
+/// The function an edit added to a file that held `before` and now holds
+/// `after`: the line and byte column of its name, and the name.
+fn new_function(before: &str, after: &str) -> Option<(u32, usize, String)> {
+    let diff = TextDiff::from_lines(before, after);
+    diff.iter_all_changes()
+        .filter(|change| change.tag() == ChangeTag::Insert)
+        .find_map(|change| {
+            let line = change.value();
+            let column = function_name_column(line)?;
+            let len = line[column..]
+                .find(|c: char| c != '_' && !c.is_alphanumeric())
+                .unwrap_or(line.len() - column);
+            let name = line[column..column + len].to_string();
+            Some((change.new_index()? as u32, column, name))
+        })
+}
+
+/// The byte column of the name in a line declaring a function, such as
+/// `    pub(crate) async fn name(`.
+fn function_name_column(line: &str) -> Option<usize> {
+    let is_ident = |c: char| c == '_' || c.is_alphanumeric();
+    let mut from = 0;
+    while let Some(found) = line[from..].find("fn ") {
+        let at = from + found;
+        let name = line.len() - line[at + 3..].trim_start().len();
+        let boundary = !line[..at].ends_with(is_ident);
+        let code = !line[..at].contains("//");
+        if boundary && code && line[name..].starts_with(|c: char| c == '_' || c.is_alphabetic()) {
+            return Some(name);
+        }
+        from = at + 3;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_function_an_extract_added() {
+        let before = "fn fun_name() {}\n\nfn main() {\n    let x = 1 + 2;\n}\n";
+        let after = "fn fun_name() {}\n\nfn main() {\n    let x = fun_name1();\n}\n\npub(crate) fn fun_name1() -> i32 {\n    1 + 2\n}\n";
+        assert_eq!(
+            new_function(before, after),
+            Some((6, 14, "fun_name1".to_string()))
+        );
+    }
+
+    #[test]
+    fn ignores_lines_that_only_mention_fn() {
+        for line in [
+            "let f: Box<dyn Fn()> = x;",
+            "// fn commented()",
+            "let r#fn = 1;",
+            "fn (x)",
+        ] {
+            assert_eq!(function_name_column(line), None, "{line}");
+        }
+        assert_eq!(
+            function_name_column("unsafe extern \"C\" fn  é()"),
+            Some(22)
+        );
+        assert_eq!(new_function("a\n", "a\nb\n"), None);
+    }
+}
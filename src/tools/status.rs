@@ -0,0 +1,45 @@
+use crate::analyzer::AnalyzerPool;
+use crate::tools::types::ToolResult;
+use anyhow::Result;
+use serde_json::{Value, json};
+use std::time::Duration;
+
+pub async fn server_status_impl(args: Value, analyzers: &AnalyzerPool) -> Result<ToolResult> {
+    let workspace_path = args.get("workspace_path").and_then(|v| v.as_str());
+    let wait = args
+        .get("wait_timeout_secs")
+        .and_then(|v| v.as_u64())
+        .map(Duration::from_secs);
+
+    let result = analyzers.server_status(workspace_path, wait).await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
+
+pub async fn restart_analyzer_impl(args: Value, analyzers: &AnalyzerPool) -> Result<ToolResult> {
+    let workspace_path = args.get("workspace_path").and_then(|v| v.as_str());
+
+    let result = analyzers.restart(workspace_path).await?;
+
+    Ok(ToolResult {
+        content: vec![
+            json!({
+                "type": "text",
+                "text": result
+            })
+            .as_object()
+            .unwrap()
+            .clone(),
+        ],
+    })
+}
@@ -2,8 +2,10 @@ use anyhow::Result;
 use serde_json::{Value, json};
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
-use crate::analyzer::RustAnalyzerClient;
+use crate::analyzer::{AnalyzerPool, RustAnalyzerClient};
+use crate::tools::edits::{EditOptions, OnConflict};
 
 pub struct ToolDefinition {
     pub name: Cow<'static, str>,
@@ -30,22 +32,91 @@ pub struct ToolResult {
     pub content: Vec<serde_json::Map<String, Value>>,
 }
 
+/// Honour an optional `wait_for_indexing_secs` argument by blocking until
+/// rust-analyzer has finished indexing, or the timeout elapses.
+pub async fn wait_for_indexing_if_requested(args: &Value, analyzer: &RustAnalyzerClient) {
+    if let Some(secs) = args.get("wait_for_indexing_secs").and_then(|v| v.as_u64()) {
+        analyzer.wait_for_indexing(Duration::from_secs(secs)).await;
+    }
+}
+
+/// How a tool that edits files was asked to write them: only show what it
+/// would do with `dry_run`, and what to do about files changed meanwhile with
+/// `on_conflict`.
+pub fn edit_options(args: &Value) -> Result<EditOptions> {
+    let on_conflict = match args.get("on_conflict").and_then(|v| v.as_str()) {
+        None | Some("abort") => OnConflict::Abort,
+        Some("merge") => OnConflict::Merge,
+        Some(other) => {
+            return Err(anyhow::anyhow!(
+                "Unknown on_conflict value {other}; use abort or merge"
+            ));
+        }
+    };
+    Ok(EditOptions {
+        dry_run: args
+            .get("dry_run")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false),
+        on_conflict,
+        requested_at: Some(SystemTime::now()),
+    })
+}
+
+/// Run a tool against the rust-analyzer for the workspace its arguments
+/// point into.
 pub async fn execute_tool(
     name: &str,
-    args: Value,
-    analyzer: &mut RustAnalyzerClient,
+    mut args: Value,
+    analyzers: &AnalyzerPool,
 ) -> Result<ToolResult> {
+    analyzers.prepare_args(&mut args)?;
+    match name {
+        "server_status" => return crate::tools::status::server_status_impl(args, analyzers).await,
+        "restart_analyzer" => {
+            return crate::tools::status::restart_analyzer_impl(args, analyzers).await;
+        }
+        "list_edits" => return crate::tools::history::list_edits_impl(args, analyzers).await,
+        "undo_edit" => return crate::tools::history::undo_edit_impl(args, analyzers).await,
+        "redo_edit" => return crate::tools::history::redo_edit_impl(args, analyzers).await,
+        _ => {}
+    }
+    if !get_tools().iter().any(|tool| tool.name == name) {
+        return Err(anyhow::anyhow!("Unknown tool: {}", name));
+    }
+
+    let analyzer = analyzers.for_args(&args).await?;
+    let analyzer = analyzer.as_ref();
     match name {
         "find_definition" => crate::tools::analysis::find_definition_impl(args, analyzer).await,
         "find_references" => crate::tools::analysis::find_references_impl(args, analyzer).await,
+        "goto_implementation" => {
+            crate::tools::analysis::goto_implementation_impl(args, analyzer).await
+        }
+        "goto_type_definition" => {
+            crate::tools::analysis::goto_type_definition_impl(args, analyzer).await
+        }
+        "goto_declaration" => crate::tools::analysis::goto_declaration_impl(args, analyzer).await,
+        "expand_macro" => crate::tools::analysis::expand_macro_impl(args, analyzer).await,
+        "hover" => crate::tools::analysis::hover_impl(args, analyzer).await,
+        "complete" => crate::tools::analysis::complete_impl(args, analyzer).await,
+        "signature_help" => crate::tools::analysis::signature_help_impl(args, analyzer).await,
         "get_diagnostics" => crate::tools::analysis::get_diagnostics_impl(args, analyzer).await,
         "workspace_symbols" => {
             crate::tools::navigation::workspace_symbols_impl(args, analyzer).await
         }
+        "document_symbols" => crate::tools::navigation::document_symbols_impl(args, analyzer).await,
+        "call_hierarchy" => crate::tools::navigation::call_hierarchy_impl(args, analyzer).await,
         "rename_symbol" => crate::tools::refactoring::rename_symbol_impl(args, analyzer).await,
         "extract_function" => {
             crate::tools::refactoring::extract_function_impl(args, analyzer).await
         }
+        "list_code_actions" => {
+            crate::tools::refactoring::list_code_actions_impl(args, analyzer).await
+        }
+        "apply_code_action" => {
+            crate::tools::refactoring::apply_code_action_impl(args, analyzer).await
+        }
         "format_code" => crate::tools::formatting::format_code_impl(args, analyzer).await,
         "analyze_manifest" => crate::tools::cargo::analyze_manifest_impl(args, analyzer).await,
         "run_cargo_check" => crate::tools::cargo::run_cargo_check_impl(args, analyzer).await,
@@ -91,7 +162,68 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                 "properties": {
                     "file_path": {"type": "string"},
                     "line": {"type": "number"},
-                    "character": {"type": "number"}
+                    "character": {"type": "number"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "goto_implementation",
+            "Find the implementations of the trait, type or trait method at a position, with a source snippet around each",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "context_lines": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "goto_type_definition",
+            "Find the definition of the type of the expression at a position, with a source snippet around it",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "context_lines": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "goto_declaration",
+            "Find the declaration of the symbol at a position, with a source snippet around it",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "context_lines": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "expand_macro",
+            "Expand the macro call, attribute macro or derive at a position recursively into formatted code; whole_item expands every macro in the item around it",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "whole_item": {"type": "boolean"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
                 },
                 "required": ["file_path", "line", "character"]
             }),
@@ -104,20 +236,73 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                 "properties": {
                     "file_path": {"type": "string"},
                     "line": {"type": "number"},
-                    "character": {"type": "number"}
+                    "character": {"type": "number"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
                 },
                 "required": ["file_path", "line", "character"]
             }),
         ),
         ToolDefinition::new(
-            "get_diagnostics",
-            "Get compiler diagnostics for a file",
+            "hover",
+            "Show the signature or type, documentation and memory layout of the item at a position, or of the expression covering a range",
             json!({
                 "type": "object",
                 "properties": {
-                    "file_path": {"type": "string"}
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "end_line": {"type": "number"},
+                    "end_character": {"type": "number"},
+                    "strip_markdown": {"type": "boolean"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
                 },
-                "required": ["file_path"]
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "complete",
+            "List completion candidates at a position, best first, with kind, detail, docs, the text edit to apply and any auto-import edits",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "content": {"type": "string"},
+                    "prefix": {"type": "string"},
+                    "max_results": {"type": "integer", "minimum": 1},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "signature_help",
+            "Show the signatures of the function call surrounding a position, with the active parameter marked and parameter docs",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "content": {"type": "string"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "get_diagnostics",
+            "Get compiler diagnostics for a file or the whole workspace, optionally filtered by severity",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "workspace_path": {"type": "string"},
+                    "severity": {"type": "string", "enum": ["error", "warning", "information", "hint"]},
+                    "include_fixes": {"type": "boolean"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                }
             }),
         ),
         ToolDefinition::new(
@@ -126,32 +311,112 @@ pub fn get_tools() -> Vec<ToolDefinition> {
             json!({
                 "type": "object",
                 "properties": {
-                    "query": {"type": "string"}
+                    "query": {"type": "string"},
+                    "workspace_path": {"type": "string"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
                 },
                 "required": ["query"]
             }),
         ),
+        ToolDefinition::new(
+            "document_symbols",
+            "Outline a file: nested modules, impls, functions, structs and fields with kinds, ranges and details, as JSON or a compact indented tree",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "format": {"type": "string", "enum": ["json", "tree"]},
+                    "max_depth": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path"]
+            }),
+        ),
+        ToolDefinition::new(
+            "call_hierarchy",
+            "Walk who calls the function at a position, or what it calls, to a given depth, as a tree or a flat list of caller/callee edges",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "direction": {"type": "string", "enum": ["incoming", "outgoing"]},
+                    "depth": {"type": "integer", "minimum": 1},
+                    "format": {"type": "string", "enum": ["tree", "edges"]},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
         ToolDefinition::new(
             "rename_symbol",
-            "Rename a symbol with scope awareness",
+            "Rename a symbol everywhere it is used, writing the changes to disk; dry_run returns a diff instead",
             json!({
                 "type": "object",
                 "properties": {
                     "file_path": {"type": "string"},
                     "line": {"type": "number"},
                     "character": {"type": "number"},
-                    "new_name": {"type": "string"}
+                    "new_name": {"type": "string"},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
                 },
                 "required": ["file_path", "line", "character", "new_name"]
             }),
         ),
+        ToolDefinition::new(
+            "list_code_actions",
+            "List the code actions (quick fixes, assists and refactorings) rust-analyzer offers at a position or range",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "end_line": {"type": "number"},
+                    "end_character": {"type": "number"},
+                    "kinds": {"type": "array", "items": {"type": "string"}},
+                    "include_edits": {"type": "boolean"},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
+        ToolDefinition::new(
+            "apply_code_action",
+            "Apply a code action, chosen by title or by index from list_code_actions, writing its edits to disk; dry_run returns a diff instead",
+            json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {"type": "string"},
+                    "line": {"type": "number"},
+                    "character": {"type": "number"},
+                    "end_line": {"type": "number"},
+                    "end_character": {"type": "number"},
+                    "kinds": {"type": "array", "items": {"type": "string"}},
+                    "title": {"type": "string"},
+                    "index": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
+                },
+                "required": ["file_path", "line", "character"]
+            }),
+        ),
         ToolDefinition::new(
             "format_code",
-            "Apply rustfmt formatting to a file",
+            "Format a file with rustfmt, honouring rustfmt.toml and the crate's edition; optionally just a line range or the whole crate, and check mode only reports whether it is formatted; dry_run returns a diff instead",
             json!({
                 "type": "object",
                 "properties": {
-                    "file_path": {"type": "string"}
+                    "file_path": {"type": "string"},
+                    "start_line": {"type": "number"},
+                    "end_line": {"type": "number"},
+                    "check": {"type": "boolean"},
+                    "whole_crate": {"type": "boolean"},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
                 },
                 "required": ["file_path"]
             }),
@@ -180,7 +445,7 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         ),
         ToolDefinition::new(
             "extract_function",
-            "Extract selected code into a new function",
+            "Extract selected code into a new function; dry_run returns a diff instead",
             json!({
                 "type": "object",
                 "properties": {
@@ -189,7 +454,9 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                     "start_character": {"type": "number"},
                     "end_line": {"type": "number"},
                     "end_character": {"type": "number"},
-                    "function_name": {"type": "string"}
+                    "function_name": {"type": "string"},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
                 },
                 "required": ["file_path", "start_line", "start_character", "end_line", "end_character", "function_name"]
             }),
@@ -250,13 +517,15 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         ),
         ToolDefinition::new(
             "inline_function",
-            "Inline a function call at specified position",
+            "Inline the function call at a position, or every call when on the function itself; dry_run returns a diff instead",
             json!({
                 "type": "object",
                 "properties": {
                     "file_path": {"type": "string"},
                     "line": {"type": "number"},
-                    "character": {"type": "number"}
+                    "character": {"type": "number"},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
                 },
                 "required": ["file_path", "line", "character"]
             }),
@@ -277,11 +546,13 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         ),
         ToolDefinition::new(
             "organize_imports",
-            "Organize and sort import statements in a file",
+            "Organize and sort import statements in a file; dry_run returns a diff instead",
             json!({
                 "type": "object",
                 "properties": {
-                    "file_path": {"type": "string"}
+                    "file_path": {"type": "string"},
+                    "dry_run": {"type": "boolean"},
+                    "on_conflict": {"type": "string", "enum": ["abort", "merge"]}
                 },
                 "required": ["file_path"]
             }),
@@ -310,13 +581,14 @@ pub fn get_tools() -> Vec<ToolDefinition> {
         ),
         ToolDefinition::new(
             "get_type_hierarchy",
-            "Get type hierarchy for a symbol at specified position",
+            "Show the traits a type implements with each impl block, or a trait's supertraits and implementors, as a graph",
             json!({
                 "type": "object",
                 "properties": {
                     "file_path": {"type": "string"},
                     "line": {"type": "integer", "minimum": 0},
-                    "character": {"type": "integer", "minimum": 0}
+                    "character": {"type": "integer", "minimum": 0},
+                    "wait_for_indexing_secs": {"type": "integer", "minimum": 0}
                 },
                 "required": ["file_path", "line", "character"]
             }),
@@ -362,5 +634,55 @@ pub fn get_tools() -> Vec<ToolDefinition> {
                 "required": ["source_file", "target_file", "item_names"]
             }),
         ),
+        // Server Management
+        ToolDefinition::new(
+            "server_status",
+            "Report whether rust-analyzer is still indexing, and how far along it is",
+            json!({
+                "type": "object",
+                "properties": {
+                    "workspace_path": {"type": "string"},
+                    "wait_timeout_secs": {"type": "integer", "minimum": 0}
+                }
+            }),
+        ),
+        ToolDefinition::new(
+            "restart_analyzer",
+            "Kill and restart rust-analyzer for a workspace, or all of them, re-opening tracked documents",
+            json!({
+                "type": "object",
+                "properties": {
+                    "workspace_path": {"type": "string"}
+                }
+            }),
+        ),
+        ToolDefinition::new(
+            "list_edits",
+            "List the file edits made this session, newest first, with the files each changed and their content hashes",
+            json!({
+                "type": "object",
+                "properties": {}
+            }),
+        ),
+        ToolDefinition::new(
+            "undo_edit",
+            "Undo an edit made this session, the latest by default; refuses if any of its files changed since",
+            json!({
+                "type": "object",
+                "properties": {
+                    "edit_id": {"type": "integer", "minimum": 1}
+                }
+            }),
+        ),
+        ToolDefinition::new(
+            "redo_edit",
+            "Redo an undone edit, the most recently undone by default; refuses if any of its files changed since",
+            json!({
+                "type": "object",
+                "properties": {
+                    "edit_id": {"type": "integer", "minimum": 1}
+                }
+            }),
+        ),
     ]
 }